@@ -125,6 +125,8 @@ impl kernel::mpu::MPU for MPU {
         regs.region_base_address.set(region_num | 1 << 4 | start_addr);
         let xn = execute as u32;
         let ap = access as u32;
-        regs.region_attributes_and_size.set(1 | len << 1 | ap << 24 | xn << 28);
+        // Sizes below 32 bytes are reserved; use size 0 to disable a region
+        let enable = if len == 0 { 0 } else { 1 };
+        regs.region_attributes_and_size.set(enable | len << 1 | ap << 24 | xn << 28);
     }
 }
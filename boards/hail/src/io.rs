@@ -1,6 +1,8 @@
 use core::fmt::*;
+use kernel::debug;
 use kernel::hil::uart::{self, UART};
 use kernel::process;
+use kernel::syscall_trace;
 use sam4l;
 
 pub struct Writer {
@@ -57,6 +59,12 @@ pub unsafe extern "C" fn panic_fmt(args: Arguments, file: &'static str, line: u3
         procs[idx].as_mut().map(|process| { process.statistics_str(writer); });
     }
 
+    // print the most recent syscalls, if the kernel records them
+    syscall_trace::dump(writer);
+
+    // print kernel debug output that was not transmitted yet
+    debug::dump(writer);
+
     // blink the panic signal
     let led = &sam4l::gpio::PA[13];
     led.enable_output();
@@ -4,17 +4,21 @@
 
 extern crate capsules;
 extern crate cortexm4;
-#[macro_use(static_init)]
+#[macro_use(static_init, debug)]
 extern crate kernel;
 extern crate sam4l;
 
+use capsules::app_loader::{self, AppLoader};
 use capsules::console::{self, Console};
 use capsules::nrf51822_serialization::{self, Nrf51822Serialization};
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{VirtualSpiMasterDevice, MuxSpiMaster};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use core::slice;
 use kernel::{Chip, Platform};
+use kernel::common::deferred_call::DeferredCall;
 use kernel::hil;
 use kernel::hil::Controller;
 use kernel::hil::spi::SpiMaster;
@@ -29,7 +33,10 @@ mod test_take_map_cell;
 static mut SPI_READ_BUF: [u8; 64] = [0; 64];
 static mut SPI_WRITE_BUF: [u8; 64] = [0; 64];
 
-unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'static>>] {
+/// Loads the apps in flash. Returns the processes and the app memory left
+/// over for apps installed at runtime.
+unsafe fn load_processes()
+                  -> (&'static mut [Option<kernel::process::Process<'static>>], &'static mut [u8]) {
     extern "C" {
         /// Beginning of the ROM region containing app images.
         static _sapps: u8;
@@ -56,20 +63,24 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
                                              FAULT_RESPONSE);
 
         if process.is_none() {
-            break;
+            // An image that was removed at runtime still occupies flash
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
 
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
     }
 
-    &mut PROCESSES
+    (&mut PROCESSES, slice::from_raw_parts_mut(app_memory_ptr, app_memory_size))
 }
 
 struct Hail {
-    console: &'static Console<'static, usart::USART>,
+    console: &'static Console<'static, UartDevice<'static, usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     isl29035: &'static capsules::isl29035::Isl29035<'static,
@@ -82,7 +93,9 @@ struct Hail {
     nrf51822: &'static Nrf51822Serialization<'static, usart::USART>,
     adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
+    button: &'static capsules::button::Button<'static,
+                                              sam4l::gpio::GPIOPin,
+                                              VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     rng: &'static capsules::rng::SimpleRng<'static, sam4l::trng::Trng<'static>>,
     ipc: kernel::ipc::IPC,
 }
@@ -180,14 +193,35 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Share the UART between the console and kernel debug output
+    let uart_mux = static_init!(
+        MuxUart<'static, usart::USART>,
+        MuxUart::new(&usart::USART0),
+        96/8);
+    hil::uart::UART::set_client(&usart::USART0, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    console_uart.init();
+    let debug_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    debug_uart.init();
+
     let console = static_init!(
-        Console<usart::USART>,
-        Console::new(&usart::USART0,
+        Console<UartDevice<usart::USART>>,
+        Console::new(console_uart,
                      115200,
                      &mut console::WRITE_BUF,
+                     &mut console::READ_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&usart::USART0, console);
+        672/8);
+    hil::uart::UART::set_client(console_uart, console);
+    let console_deferred_call = static_init!(DeferredCall, DeferredCall::new(), 32/8);
+    console_deferred_call.set_client(console);
+    console.set_deferred_call(console_deferred_call);
 
     // Create the Nrf51822Serialization driver for passing BLE commands
     // over UART to the nRF51822 radio.
@@ -305,13 +339,22 @@ pub unsafe fn reset_handler() {
         [&'static sam4l::gpio::GPIOPin; 1],
         [&sam4l::gpio::PA[16]],
         1 * 4);
+    let button_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        192/8);
     let button = static_init!(
-        capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Container::create()),
-        96/8);
+        capsules::button::Button<'static,
+                                 sam4l::gpio::GPIOPin,
+                                 VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::button::Button::new(button_pins,
+                                      button_virtual_alarm,
+                                      kernel::Container::create()),
+        128/8);
     for btn in button_pins.iter() {
         btn.set_client(button);
     }
+    button_virtual_alarm.set_client(button);
 
     // Setup ADC
     let adc = static_init!(
@@ -360,7 +403,7 @@ pub unsafe fn reset_handler() {
         led: led,
         button: button,
         rng: rng,
-        ipc: kernel::ipc::IPC::new(),
+        ipc: kernel::ipc::IPC::new(&[]),
     };
 
     // Need to reset the nRF on boot
@@ -370,6 +413,8 @@ pub unsafe fn reset_handler() {
     sam4l::gpio::PA[17].set();
 
     hail.console.initialize();
+    // The console configures the UART that debug output shares
+    kernel::debug::set_uart(debug_uart);
     hail.nrf51822.initialize();
 
     let mut chip = sam4l::chip::Sam4l::new();
@@ -378,5 +423,37 @@ pub unsafe fn reset_handler() {
     // Uncomment to measure overheads for TakeCell and MapCell:
     // test_take_map_cell::test_take_map_cell();
 
-    kernel::main(&hail, &mut chip, load_processes(), &hail.ipc);
+    let (processes, spare_app_memory) = load_processes();
+
+    // Install and remove apps at runtime over the UART on the header pins
+    extern "C" {
+        /// Beginning and end of the flash region holding app images.
+        static _sapps: u8;
+        static _eapps: u8;
+    }
+    let flash = &mut sam4l::flashcalw::FLASH_CONTROLLER;
+    flash.configure();
+    let flash_deferred_call = static_init!(DeferredCall, DeferredCall::new(), 32/8);
+    flash_deferred_call.set_client(&sam4l::flashcalw::FLASH_CONTROLLER);
+    flash.set_deferred_call(flash_deferred_call);
+    static mut PAGE_BUF: [u8; 512] = [0; 512];
+    let apps_flash = slice::from_raw_parts(&_sapps as *const u8,
+                                           &_eapps as *const u8 as usize -
+                                           &_sapps as *const u8 as usize);
+    let app_loader = static_init!(
+        AppLoader<'static, usart::USART, sam4l::flashcalw::FLASHCALW>,
+        AppLoader::new(&usart::USART2,
+                       &sam4l::flashcalw::FLASH_CONTROLLER,
+                       apps_flash,
+                       spare_app_memory,
+                       kernel::process::FaultResponse::Panic,
+                       &mut app_loader::RX_BUF,
+                       &mut app_loader::TX_BUF,
+                       &mut PAGE_BUF),
+        640/8);
+    hil::uart::UART::set_client(&usart::USART2, app_loader);
+    hil::flash::Flash::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, app_loader);
+    app_loader.initialize();
+
+    kernel::main(&hail, &mut chip, processes, &hail.ipc);
 }
@@ -3,16 +3,21 @@
 #![feature(const_fn,lang_items)]
 
 extern crate capsules;
-#[macro_use(static_init)]
+#[macro_use(static_init, debug)]
 extern crate kernel;
 extern crate sam4l;
 
+use capsules::lpl::LowPowerListening;
 use capsules::rf233::RF233;
+use capsules::sixlowpan::{self, Sixlowpan};
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules::virtual_radio::{MuxRadio, VirtualRadio};
 use capsules::virtual_spi::{VirtualSpiMasterDevice, MuxSpiMaster};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::Chip;
+use kernel::common::deferred_call::DeferredCall;
 use kernel::hil;
 use kernel::hil::Controller;
 use kernel::hil::radio;
@@ -30,7 +35,8 @@ mod i2c_dummy;
 mod spi_dummy;
 
 struct Imix {
-    console: &'static capsules::console::Console<'static, sam4l::usart::USART>,
+    console: &'static capsules::console::Console<'static,
+                                                 UartDevice<'static, sam4l::usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     si7021: &'static capsules::si7021::SI7021<'static,
@@ -40,15 +46,28 @@ struct Imix {
                                                                     sam4l::ast::Ast<'static>>>,
     adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
+    button: &'static capsules::button::Button<'static,
+                                              sam4l::gpio::GPIOPin,
+                                              VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
     ipc: kernel::ipc::IPC,
     fxos8700_cq: &'static capsules::fxos8700_cq::Fxos8700cq<'static>,
-    radio: &'static capsules::radio::RadioDriver<'static,
-                                                 capsules::rf233::RF233<'static,
-                                                 VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>>,
+    radio: &'static capsules::radio::RadioDriver<'static, RadioUser>,
+    udp: &'static capsules::udp::UDPDriver<'static,
+                                           RadioUser,
+                                           VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
 }
 
+// The radio stack: the RF233, duty cycled by low-power listening and shared
+// by the radio system call interface and 6LoWPAN.
+type RF233Device = RF233<'static,
+                         VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
+                         VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+type LPLRadio = LowPowerListening<'static,
+                                  RF233Device,
+                                  VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+type RadioUser = VirtualRadio<'static, LPLRadio>;
+
 // The RF233 radio stack requires our buffers for its SPI operations:
 //
 //   1. buf: a packet-sized buffer for SPI operations, which is
@@ -66,6 +85,21 @@ static mut RF233_REG_READ: [u8; 2] = [0x00; 2];
 // copies application transmissions into or copies out to application buffers
 // for reception.
 static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+// Low-power listening passes received frames up in its own buffer while the
+// RF233 receives the next one. The radio system call interface and 6LoWPAN
+// share the radio through a mux, which copies each received frame into a
+// buffer of each of them.
+static mut LPL_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut RADIO_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+// 6LoWPAN needs a buffer to build frames in, one to decompress unfragmented
+// packets into and one to reassemble fragmented datagrams in.
+static mut SIXLOWPAN_FRAME: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_PACKET: [u8; sixlowpan::MAX_PACKET_SIZE] = [0x00; sixlowpan::MAX_PACKET_SIZE];
+static mut SIXLOWPAN_REASSEMBLY: [u8; sixlowpan::MAX_DATAGRAM_SIZE] =
+    [0x00; sixlowpan::MAX_DATAGRAM_SIZE];
+// The UDP system call interface builds outgoing datagrams in this buffer.
+static mut UDP_BUF: [u8; sixlowpan::MAX_DATAGRAM_SIZE] = [0x00; sixlowpan::MAX_DATAGRAM_SIZE];
 
 impl kernel::Platform for Imix {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
@@ -84,6 +118,7 @@ impl kernel::Platform for Imix {
             10 => f(Some(self.si7021)),
             11 => f(Some(self.fxos8700_cq)),
             154 => f(Some(self.radio)),
+            155 => f(Some(self.udp)),
             0xff => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -169,15 +204,38 @@ pub unsafe fn reset_handler() {
 
     // # CONSOLE
 
+    // Share the UART between the console and kernel debug output
+    let uart_mux = static_init!(
+        MuxUart<'static, sam4l::usart::USART>,
+        MuxUart::new(&sam4l::usart::USART3),
+        96/8);
+    hil::uart::UART::set_client(&sam4l::usart::USART3, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, sam4l::usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    console_uart.init();
+    let debug_uart = static_init!(
+        UartDevice<'static, sam4l::usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    debug_uart.init();
+
     let console = static_init!(
-        capsules::console::Console<sam4l::usart::USART>,
-        capsules::console::Console::new(&sam4l::usart::USART3,
+        capsules::console::Console<UartDevice<sam4l::usart::USART>>,
+        capsules::console::Console::new(console_uart,
                      115200,
                      &mut capsules::console::WRITE_BUF,
+                     &mut capsules::console::READ_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&sam4l::usart::USART3, console);
+        672/8);
+    hil::uart::UART::set_client(console_uart, console);
+    let console_deferred_call = static_init!(DeferredCall, DeferredCall::new(), 32/8);
+    console_deferred_call.set_client(console);
+    console.set_deferred_call(console_deferred_call);
     console.initialize();
+    // The console configures the UART that debug output shares
+    kernel::debug::set_uart(debug_uart);
 
     // # TIMER
 
@@ -267,15 +325,25 @@ pub unsafe fn reset_handler() {
     let rf233_spi = static_init!(VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
                                  VirtualSpiMasterDevice::new(mux_spi, 3),
                                  352/8);
+    // The RF233 uses an alarm to timestamp received packets
+    let rf233_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
     // Create the RF233 driver, passing its pins and SPI client
-    let rf233: &RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>> =
-        static_init!(RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
+    let rf233: &RF233<'static,
+                      VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
+                      VirtualMuxAlarm<'static, sam4l::ast::Ast>> =
+        static_init!(RF233<'static,
+                           VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
+                           VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
                              RF233::new(rf233_spi,
+                                        rf233_alarm,
                                         &sam4l::gpio::PA[09],    // reset
                                         &sam4l::gpio::PA[10],    // sleep
                                         &sam4l::gpio::PA[08],    // irq
                                         &sam4l::gpio::PA[08]),   // irq_ctl
-                                        116);
+                                        156);
 
     sam4l::gpio::PA[08].set_client(rf233);
 
@@ -341,26 +409,85 @@ pub unsafe fn reset_handler() {
         [&sam4l::gpio::PC[24]],
         1 * 4);
 
+    let button_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        192/8);
     let button = static_init!(
-        capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Container::create()),
-        96/8);
+        capsules::button::Button<'static,
+                                 sam4l::gpio::GPIOPin,
+                                 VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::button::Button::new(button_pins,
+                                      button_virtual_alarm,
+                                      kernel::Container::create()),
+        128/8);
     for btn in button_pins.iter() {
         btn.set_client(button);
     }
+    button_virtual_alarm.set_client(button);
 
     rf233_spi.set_client(rf233);
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
 
+    // Duty cycle the RF233 with low-power listening. It keeps the radio on
+    // until a wakeup interval is set.
+    let lpl_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    let lpl = static_init!(
+        LPLRadio,
+        LowPowerListening::new(rf233, lpl_alarm),
+        608/8);
+    lpl_alarm.set_client(lpl);
+    rf233.set_transmit_client(lpl);
+    rf233.set_receive_client(lpl, &mut RF233_RX_BUF);
+    rf233.set_power_client(lpl);
+
+    // Share the radio between the radio system call interface and 6LoWPAN
+    let mux_radio = static_init!(MuxRadio<'static, LPLRadio>, MuxRadio::new(lpl), 96/8);
+    lpl.set_transmit_client(mux_radio);
+    lpl.set_receive_client(mux_radio, &mut LPL_RX_BUF);
+    lpl.set_energy_client(mux_radio);
+    lpl.set_power_client(mux_radio);
+
+    let radio_user = static_init!(RadioUser, VirtualRadio::new(mux_radio), 512/8);
+    radio_user.init();
     let radio_capsule = static_init!(
-        capsules::radio::RadioDriver<'static,
-                                     RF233<'static,
-                                           VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>>,
-        capsules::radio::RadioDriver::new(rf233),
-        544/8);
+        capsules::radio::RadioDriver<'static, RadioUser>,
+        capsules::radio::RadioDriver::new(radio_user, kernel::Container::create()),
+        256/8);
     radio_capsule.config_buffer(&mut RADIO_BUF);
-    rf233.set_transmit_client(radio_capsule);
-    rf233.set_receive_client(radio_capsule, &mut RF233_RX_BUF);
+    radio_user.set_transmit_client(radio_capsule);
+    radio_user.set_receive_client(radio_capsule, &mut RADIO_RX_BUF);
+    radio_user.set_energy_client(radio_capsule);
+
+    // UDP over 6LoWPAN, on the second user of the radio
+    let sixlowpan_user = static_init!(RadioUser, VirtualRadio::new(mux_radio), 512/8);
+    sixlowpan_user.init();
+    // 6LoWPAN uses an alarm to abandon incomplete reassemblies
+    let sixlowpan_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    let sixlowpan = static_init!(
+        Sixlowpan<'static, RadioUser, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        Sixlowpan::new(sixlowpan_user,
+                       sixlowpan_alarm,
+                       &mut SIXLOWPAN_FRAME,
+                       &mut SIXLOWPAN_PACKET,
+                       &mut SIXLOWPAN_REASSEMBLY),
+        992/8);
+    sixlowpan_alarm.set_client(sixlowpan);
+    sixlowpan_user.set_transmit_client(sixlowpan);
+    sixlowpan_user.set_receive_client(sixlowpan, &mut SIXLOWPAN_RX_BUF);
+
+    let udp = static_init!(
+        capsules::udp::UDPDriver<'static, RadioUser, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::udp::UDPDriver::new(sixlowpan, kernel::Container::create()),
+        384/8);
+    udp.config_buffer(&mut UDP_BUF);
+    sixlowpan.set_client(udp);
 
     let imix = Imix {
         console: console,
@@ -372,9 +499,10 @@ pub unsafe fn reset_handler() {
         led: led,
         button: button,
         spi: spi_syscalls,
-        ipc: kernel::ipc::IPC::new(),
+        ipc: kernel::ipc::IPC::new(&[]),
         fxos8700_cq: fx0,
         radio: radio_capsule,
+        udp: udp,
     };
 
     let mut chip = sam4l::chip::Sam4l::new();
@@ -384,7 +512,10 @@ pub unsafe fn reset_handler() {
     rf233.reset();
     rf233.set_pan(0xABCD);
     rf233.set_address(0x1008);
-    rf233.start();
+    // To duty cycle the radio, listening 10% of the time:
+    // lpl.set_wakeup_interval(500);
+    // lpl.set_duty_cycle(10);
+    lpl.start();
     kernel::main(&imix, &mut chip, load_processes(), &imix.ipc);
 }
 
@@ -415,10 +546,14 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
                                              FAULT_RESPONSE);
 
         if process.is_none() {
-            break;
+            // An image that was removed at runtime still occupies flash
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
 
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
@@ -45,7 +45,9 @@ extern crate nrf51;
 
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::{Chip, SysTick};
+use kernel::common::deferred_call::DeferredCall;
 use kernel::hil::uart::UART;
 use nrf51::pinmux::Pinmux;
 use nrf51::rtc::{RTC, Rtc};
@@ -92,10 +94,14 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
                                              FAULT_RESPONSE);
 
         if process.is_none() {
-            break;
+            // An image that was removed at runtime still occupies flash
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
 
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
@@ -107,9 +113,11 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
 pub struct Platform {
     gpio: &'static capsules::gpio::GPIO<'static, nrf51::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
-    console: &'static capsules::console::Console<'static, nrf51::uart::UART>,
+    console: &'static capsules::console::Console<'static, UartDevice<'static, nrf51::uart::UART>>,
     led: &'static capsules::led::LED<'static, nrf51::gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, nrf51::gpio::GPIOPin>,
+    button: &'static capsules::button::Button<'static,
+                                              nrf51::gpio::GPIOPin,
+                                              VirtualMuxAlarm<'static, Rtc>>,
 }
 
 
@@ -146,24 +154,6 @@ pub unsafe fn reset_handler() {
         capsules::led::LED::new(led_pins, capsules::led::ActivationMode::ActiveLow),
         96/8);
 
-    let button_pins = static_init!(
-        [&'static nrf51::gpio::GPIOPin; 4],
-        [&nrf51::gpio::PORT[BUTTON1_PIN], // 17
-         &nrf51::gpio::PORT[BUTTON2_PIN], // 18
-         &nrf51::gpio::PORT[BUTTON3_PIN], // 19
-         &nrf51::gpio::PORT[BUTTON4_PIN], // 20
-        ],
-        4 * 4);
-    let button = static_init!(
-        capsules::button::Button<'static, nrf51::gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Container::create()),
-        96/8);
-    for btn in button_pins.iter() {
-        use kernel::hil::gpio::PinCtl;
-        btn.set_input_mode(kernel::hil::gpio::InputMode::PullUp);
-        btn.set_client(button);
-    }
-
     let gpio_pins = static_init!(
         [&'static nrf51::gpio::GPIOPin; 11],
         [&nrf51::gpio::PORT[1],  // Bottom left header on DK board
@@ -192,15 +182,38 @@ pub unsafe fn reset_handler() {
                                  Pinmux::new(11),
                                  Pinmux::new(10),
                                  Pinmux::new(8));
+    // Share the UART between the console and kernel debug output
+    let uart_mux = static_init!(
+        MuxUart<'static, nrf51::uart::UART>,
+        MuxUart::new(&nrf51::uart::UART0),
+        96/8);
+    UART::set_client(&nrf51::uart::UART0, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, nrf51::uart::UART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    console_uart.init();
+    let debug_uart = static_init!(
+        UartDevice<'static, nrf51::uart::UART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    debug_uart.init();
+
     let console = static_init!(
-        capsules::console::Console<nrf51::uart::UART>,
-        capsules::console::Console::new(&nrf51::uart::UART0,
+        capsules::console::Console<UartDevice<nrf51::uart::UART>>,
+        capsules::console::Console::new(console_uart,
                                         115200,
                                         &mut capsules::console::WRITE_BUF,
+                                        &mut capsules::console::READ_BUF,
                                         kernel::Container::create()),
-        224/8);
-    UART::set_client(&nrf51::uart::UART0, console);
+        672/8);
+    UART::set_client(console_uart, console);
+    let console_deferred_call = static_init!(DeferredCall, DeferredCall::new(), 32/8);
+    console_deferred_call.set_client(console);
+    console.set_deferred_call(console_deferred_call);
     console.initialize();
+    // The console configures the UART that debug output shares
+    kernel::debug::set_uart(debug_uart);
 
     let alarm = &nrf51::rtc::RTC;
     alarm.start();
@@ -219,6 +232,31 @@ pub unsafe fn reset_handler() {
         12);
     virtual_alarm1.set_client(timer);
 
+    let button_pins = static_init!(
+        [&'static nrf51::gpio::GPIOPin; 4],
+        [&nrf51::gpio::PORT[BUTTON1_PIN], // 17
+         &nrf51::gpio::PORT[BUTTON2_PIN], // 18
+         &nrf51::gpio::PORT[BUTTON3_PIN], // 19
+         &nrf51::gpio::PORT[BUTTON4_PIN], // 20
+        ],
+        4 * 4);
+    let button_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    let button = static_init!(
+        capsules::button::Button<'static, nrf51::gpio::GPIOPin, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::button::Button::new(button_pins,
+                                      button_virtual_alarm,
+                                      kernel::Container::create()),
+        128/8);
+    for btn in button_pins.iter() {
+        use kernel::hil::gpio::PinCtl;
+        btn.set_input_mode(kernel::hil::gpio::InputMode::PullUp);
+        btn.set_client(button);
+    }
+    button_virtual_alarm.set_client(button);
+
     // Start all of the clocks. Low power operation will require a better
     // approach than this.
     nrf51::clock::CLOCK.low_stop();
@@ -247,7 +285,7 @@ pub unsafe fn reset_handler() {
     kernel::main(&platform,
                  &mut chip,
                  load_process(),
-                 &kernel::ipc::IPC::new());
+                 &kernel::ipc::IPC::new(&[]));
 
 }
 
@@ -256,12 +294,24 @@ use core::fmt::Arguments;
 #[cfg(not(test))]
 #[lang="panic_fmt"]
 #[no_mangle]
-pub unsafe extern "C" fn rust_begin_unwind(_args: &Arguments,
-                                           _file: &'static str,
-                                           _line: usize)
+pub unsafe extern "C" fn rust_begin_unwind(args: &Arguments,
+                                           file: &'static str,
+                                           line: usize)
                                            -> ! {
+    use core::fmt::{write, Write};
     use kernel::hil::gpio::Pin;
 
+    let writer = &mut io::WRITER;
+    let _ = writer.write_fmt(format_args!("Kernel panic at {}:{}:\r\n\t\"", file, line));
+    let _ = write(writer, *args);
+    let _ = writer.write_str("\"\r\n");
+
+    // print the most recent syscalls, if the kernel records them
+    kernel::syscall_trace::dump(writer);
+
+    // print kernel debug output that was not transmitted yet
+    kernel::debug::dump(writer);
+
     let led0 = &nrf51::gpio::PORT[LED1_PIN];
     let led1 = &nrf51::gpio::PORT[LED2_PIN];
 
@@ -4,7 +4,7 @@
 
 extern crate capsules;
 extern crate cortexm4;
-#[macro_use(static_init)]
+#[macro_use(static_init, debug)]
 extern crate kernel;
 extern crate sam4l;
 
@@ -14,7 +14,9 @@ use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{VirtualSpiMasterDevice, MuxSpiMaster};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::{Chip, Platform};
+use kernel::common::deferred_call::DeferredCall;
 use kernel::hil;
 use kernel::hil::Controller;
 use kernel::hil::gpio::PinCtl;
@@ -70,10 +72,14 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
                                              FAULT_RESPONSE);
 
         if process.is_none() {
-            break;
+            // An image that was removed at runtime still occupies flash
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
 
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
@@ -83,7 +89,7 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
 }
 
 struct Firestorm {
-    console: &'static Console<'static, usart::USART>,
+    console: &'static Console<'static, UartDevice<'static, usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     tmp006: &'static capsules::tmp006::TMP006<'static>,
@@ -231,14 +237,35 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Share the UART between the console and kernel debug output
+    let uart_mux = static_init!(
+        MuxUart<'static, usart::USART>,
+        MuxUart::new(&usart::USART3),
+        96/8);
+    hil::uart::UART::set_client(&usart::USART3, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    console_uart.init();
+    let debug_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux),
+        256/8);
+    debug_uart.init();
+
     let console = static_init!(
-        Console<usart::USART>,
-        Console::new(&usart::USART3,
+        Console<UartDevice<usart::USART>>,
+        Console::new(console_uart,
                      115200,
                      &mut console::WRITE_BUF,
+                     &mut console::READ_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&usart::USART3, console);
+        672/8);
+    hil::uart::UART::set_client(console_uart, console);
+    let console_deferred_call = static_init!(DeferredCall, DeferredCall::new(), 32/8);
+    console_deferred_call.set_client(console);
+    console.set_deferred_call(console_deferred_call);
 
     // Create the Nrf51822Serialization driver for passing BLE commands
     // over UART to the nRF51822 radio.
@@ -393,7 +420,7 @@ pub unsafe fn reset_handler() {
         nrf51822: nrf_serialization,
         adc: adc,
         led: led,
-        ipc: kernel::ipc::IPC::new(),
+        ipc: kernel::ipc::IPC::new(&[]),
         rng: rng_driver,
     };
 
@@ -427,15 +454,17 @@ pub unsafe fn reset_handler() {
     // flash_dummy::set_read_write_test();
 
     firestorm.console.initialize();
+    // The console configures the UART that debug output shares
+    kernel::debug::set_uart(debug_uart);
     firestorm.nrf51822.initialize();
 
     let mut chip = sam4l::chip::Sam4l::new();
     
         chip.mpu().enable_mpu();
     //print!("this");
-    println!("Team");
-    println!("New");
-    println!("World");
+    debug!("Team");
+    debug!("New");
+    debug!("World");
     //////////////////////
     
     //aesa_temp.aes_set_config();
@@ -476,19 +505,19 @@ let  ref_plain_text1:[u32; 4] = [ 1,
     }
     let output:u32 = aesa_temp.aes_read_output_data();
     
-    println!("We got {} , {}",output, ref_cipher_text[0]);
+    debug!("We got {} , {}",output, ref_cipher_text[0]);
     
     let output:u32 = aesa_temp.aes_read_output_data();
-    println!("We got {} , {}",output, ref_cipher_text[1]);
+    debug!("We got {} , {}",output, ref_cipher_text[1]);
     
     let output:u32 = aesa_temp.aes_read_output_data();
-    println!("We got {} , {}",output, ref_cipher_text[2]);
+    debug!("We got {} , {}",output, ref_cipher_text[2]);
     
     let output:u32 = aesa_temp.aes_read_output_data();
-    println!("We got {} , {}",output, ref_cipher_text[3]);
+    debug!("We got {} , {}",output, ref_cipher_text[3]);
     
     
-    println!("Are we ready? Check sr {}",aesa_temp.aes_read_done());
+    debug!("Are we ready? Check sr {}",aesa_temp.aes_read_done());
     ///////////////////////////////////////////////////////////////////// aes_read_data_buffer
 
     
@@ -0,0 +1,412 @@
+//! App loader capsule: installs and removes applications at runtime.
+//!
+//! The loader listens on a UART (the console UART or, e.g., the nRF51822
+//! serialization link) for a simple framed protocol:
+//!
+//!   * `'I'` followed by a complete TBF image. The loader first receives the
+//!     image header and validates it against `LoadInfo`. It then checks that
+//!     the image fits in the free space at the end of the app flash region
+//!     and in the remaining app memory, and streams the image into flash one
+//!     page at a time. Once the last page is written the process is created
+//!     and scheduled without a reboot.
+//!   * `'R'` followed by one byte holding a process index clears the
+//!     version field of that process's image header, so the image is skipped
+//!     from then on, including after a reboot. The loader rewrites the flash
+//!     page holding the header, so the image need not start on a page
+//!     boundary. The process is stopped once the page has been written; if
+//!     the write fails, it keeps running.
+//!
+//! Every command is answered with two bytes: the `ReturnCode` (as a signed
+//! byte, 0 on success) and, for a successful install, the index of the new
+//! process. If the previous answer is still being sent, the loader waits for
+//! it to finish before sending the next one and listening for another
+//! command.
+//!
+//! Installed images must be a multiple of the flash page size. Flash offsets
+//! passed to the flash HIL are absolute addresses. Flash and RAM belonging to
+//! removed apps is not reclaimed until the board is re-flashed, and neither is
+//! their process slot, so that the `AppId` of a removed app never names a new
+//! one.
+
+use core::cell::Cell;
+use core::cmp;
+use core::slice;
+use kernel::{AppId, ReturnCode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{self, Flash};
+use kernel::hil::uart::{self, UART};
+use kernel::process::{self, FaultResponse};
+
+pub static mut RX_BUF: [u8; 128] = [0; 128];
+pub static mut TX_BUF: [u8; 2] = [0; 2];
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for a command byte
+    Idle,
+    /// Receiving the header of a new image
+    Header,
+    /// Receiving the body of a new image
+    Image,
+    /// Writing a page of a new image to flash
+    Writing,
+    /// Waiting for the index of the process to remove
+    RemoveTarget,
+    /// Retiring the image of a removed process
+    Removing,
+}
+
+pub struct AppLoader<'a, U: UART + 'a, F: Flash + 'a> {
+    uart: &'a U,
+    flash: &'a F,
+    apps_flash: &'static [u8],
+    app_memory: TakeCell<'static, [u8]>,
+    fault_response: FaultResponse,
+    rx_buffer: TakeCell<'static, [u8]>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    page_buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// Offset of the image being installed within `apps_flash`
+    image_start: Cell<usize>,
+    image_len: Cell<usize>,
+    /// Bytes of the image received so far
+    received: Cell<usize>,
+    /// Bytes of the current page received so far
+    page_fill: Cell<usize>,
+    /// An answer waiting for the previous one to be sent
+    pending_reply: Cell<Option<ReturnCode>>,
+    /// Index of the process whose image header is being retired
+    remove_target: Cell<usize>,
+}
+
+impl<'a, U: UART, F: Flash> AppLoader<'a, U, F> {
+    /// `apps_flash` is the flash region holding app images and
+    /// `app_memory` the RAM left over after loading the apps present at
+    /// boot. The length of `page_buffer` must be the flash page size.
+    pub fn new(uart: &'a U,
+               flash: &'a F,
+               apps_flash: &'static [u8],
+               app_memory: &'static mut [u8],
+               fault_response: FaultResponse,
+               rx_buffer: &'static mut [u8],
+               tx_buffer: &'static mut [u8],
+               page_buffer: &'static mut [u8])
+               -> AppLoader<'a, U, F> {
+        AppLoader {
+            uart: uart,
+            flash: flash,
+            apps_flash: apps_flash,
+            app_memory: TakeCell::new(app_memory),
+            fault_response: fault_response,
+            rx_buffer: TakeCell::new(rx_buffer),
+            tx_buffer: TakeCell::new(tx_buffer),
+            page_buffer: TakeCell::new(page_buffer),
+            state: Cell::new(State::Idle),
+            image_start: Cell::new(0),
+            image_len: Cell::new(0),
+            received: Cell::new(0),
+            page_fill: Cell::new(0),
+            pending_reply: Cell::new(None),
+            remove_target: Cell::new(0),
+        }
+    }
+
+    pub fn initialize(&self) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: 115200,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        self.wait_for_command();
+    }
+
+    fn wait_for_command(&self) {
+        self.state.set(State::Idle);
+        self.rx_buffer.take().map(|buffer| self.uart.receive(buffer, 1));
+    }
+
+    /// Reports the result of a command and waits for the next one, once the
+    /// previous answer has been sent.
+    fn respond(&self, result: ReturnCode) {
+        match self.tx_buffer.take() {
+            Some(buffer) => {
+                match result {
+                    ReturnCode::SuccessWithValue { value } => {
+                        buffer[0] = 0;
+                        buffer[1] = value as u8;
+                    }
+                    _ => {
+                        buffer[0] = isize::from(result) as u8;
+                        buffer[1] = 0;
+                    }
+                }
+                self.uart.transmit(buffer, 2);
+                self.wait_for_command();
+            }
+            None => {
+                self.state.set(State::Idle);
+                self.pending_reply.set(Some(result));
+            }
+        }
+    }
+
+    /// Offset within `apps_flash` of the first byte after the last image.
+    fn free_offset(&self) -> usize {
+        let mut offset = 0;
+        while offset < self.apps_flash.len() {
+            match process::parse_image_header(&self.apps_flash[offset..]) {
+                Some(info) if info.total_size > 0 => offset += info.total_size,
+                _ => break,
+            }
+        }
+        offset
+    }
+
+    /// Checks a received image header and, if the image fits, prepares to
+    /// receive the rest of it.
+    fn start_image(&self, header: &[u8]) -> ReturnCode {
+        let info = match process::parse_image_header(header) {
+            Some(info) if !info.removed && info.total_size >= header.len() => info,
+            _ => return ReturnCode::EINVAL,
+        };
+
+        let page_len = self.page_buffer.map_or(0, |page| page.len());
+        if page_len == 0 || info.total_size % page_len != 0 {
+            return ReturnCode::ESIZE;
+        }
+
+        let start = self.free_offset();
+        if start % page_len != 0 || start + info.total_size > self.apps_flash.len() {
+            return ReturnCode::ENOMEM;
+        }
+
+        // Leave room to align the process memory to its size
+        if self.app_memory.map_or(0, |memory| memory.len()) < 2 * info.memory_size {
+            return ReturnCode::ENOMEM;
+        }
+
+        self.page_buffer.map(|page| for (i, c) in header.iter().enumerate() {
+            page[i] = *c;
+        });
+        self.image_start.set(start);
+        self.image_len.set(info.total_size);
+        self.received.set(header.len());
+        self.page_fill.set(header.len());
+        ReturnCode::SUCCESS
+    }
+
+    /// Receives the next chunk of the image, which never spans a page.
+    fn receive_chunk(&self, buffer: &'static mut [u8]) {
+        let page_len = self.page_buffer.map_or(0, |page| page.len());
+        let len = cmp::min(buffer.len(),
+                           cmp::min(page_len - self.page_fill.get(),
+                                    self.image_len.get() - self.received.get()));
+        self.state.set(State::Image);
+        self.uart.receive(buffer, len);
+    }
+
+    /// Writes the page buffer to flash if it is full or holds the end of the
+    /// image, or else receives more of the image.
+    fn continue_image(&self) {
+        if !self.flush_page() {
+            self.rx_buffer.take().map(|buffer| self.receive_chunk(buffer));
+        }
+    }
+
+    /// Writes the page buffer to flash if it is full or holds the end of the
+    /// image. Returns whether the page was written, or the write failed and
+    /// has been answered.
+    fn flush_page(&self) -> bool {
+        let page_len = self.page_buffer.map_or(0, |page| page.len());
+        let fill = self.page_fill.get();
+        if fill < page_len && self.received.get() < self.image_len.get() {
+            return false;
+        }
+
+        self.page_buffer.take().map_or(false, |page| {
+            for c in page[fill..].iter_mut() {
+                *c = 0xff;
+            }
+            let page_start = self.received.get() - fill;
+            let address = self.apps_flash.as_ptr() as usize + self.image_start.get() +
+                          page_start;
+            match self.flash.write(address, page) {
+                Ok(()) => {
+                    self.state.set(State::Writing);
+                    true
+                }
+                Err((result, page)) => {
+                    self.page_buffer.replace(page);
+                    self.respond(result);
+                    true
+                }
+            }
+        })
+    }
+
+    /// Creates and schedules the process for a fully written image.
+    fn install(&self) -> ReturnCode {
+        let image = unsafe { self.apps_flash.as_ptr().offset(self.image_start.get() as isize) };
+        self.app_memory.take().map_or(ReturnCode::ENOMEM, |memory| {
+            let result = unsafe {
+                process::install(image, memory.as_mut_ptr(), memory.len(), self.fault_response)
+            };
+            match result {
+                Some((appid, used)) => {
+                    let (_, rest) = memory.split_at_mut(used);
+                    self.app_memory.replace(rest);
+                    ReturnCode::SuccessWithValue { value: appid.idx() }
+                }
+                None => {
+                    self.app_memory.replace(memory);
+                    ReturnCode::FAIL
+                }
+            }
+        })
+    }
+
+    /// Clears the version of a process's image header. The process is
+    /// stopped when the write completes.
+    fn remove(&self, idx: usize) -> ReturnCode {
+        let image = match process::image(AppId::new(idx)) {
+            Some(image) => image,
+            None => return ReturnCode::EINVAL,
+        };
+
+        self.page_buffer.take().map_or(ReturnCode::EBUSY, |page| {
+            // Rewrite the whole page holding the start of the image
+            let offset = image.as_ptr() as usize % page.len();
+            if offset + 4 > page.len() {
+                self.page_buffer.replace(page);
+                return ReturnCode::EINVAL;
+            }
+            let page_start = image.as_ptr() as usize - offset;
+            let current = unsafe { slice::from_raw_parts(page_start as *const u8, page.len()) };
+            page.copy_from_slice(current);
+            for c in page[offset..offset + 4].iter_mut() {
+                *c = 0;
+            }
+            match self.flash.write(page_start, page) {
+                Ok(()) => {
+                    self.remove_target.set(idx);
+                    self.state.set(State::Removing);
+                    ReturnCode::SUCCESS
+                }
+                Err((result, page)) => {
+                    self.page_buffer.replace(page);
+                    result
+                }
+            }
+        })
+    }
+}
+
+impl<'a, U: UART, F: Flash> uart::Client for AppLoader<'a, U, F> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        self.pending_reply.get().map(|result| {
+            self.pending_reply.set(None);
+            self.respond(result);
+        });
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if error != uart::Error::CommandComplete {
+            self.rx_buffer.replace(buffer);
+            self.respond(ReturnCode::FAIL);
+            return;
+        }
+
+        match self.state.get() {
+            State::Idle => {
+                match buffer[0] {
+                    b'I' => {
+                        if buffer.len() < process::header_len() {
+                            self.rx_buffer.replace(buffer);
+                            self.respond(ReturnCode::ESIZE);
+                        } else {
+                            self.state.set(State::Header);
+                            self.uart.receive(buffer, process::header_len());
+                        }
+                    }
+                    b'R' => {
+                        self.state.set(State::RemoveTarget);
+                        self.uart.receive(buffer, 1);
+                    }
+                    _ => {
+                        self.rx_buffer.replace(buffer);
+                        self.respond(ReturnCode::ENOSUPPORT);
+                    }
+                }
+            }
+            State::Header => {
+                let result = self.start_image(&buffer[..rx_len]);
+                self.rx_buffer.replace(buffer);
+                if result != ReturnCode::SUCCESS {
+                    self.respond(result);
+                } else {
+                    self.continue_image();
+                }
+            }
+            State::Image => {
+                let fill = self.page_fill.get();
+                self.page_buffer.map(|page| for (i, c) in buffer[..rx_len].iter().enumerate() {
+                    page[fill + i] = *c;
+                });
+                self.page_fill.set(fill + rx_len);
+                self.received.set(self.received.get() + rx_len);
+
+                self.rx_buffer.replace(buffer);
+                self.continue_image();
+            }
+            State::RemoveTarget => {
+                let idx = buffer[0] as usize;
+                self.rx_buffer.replace(buffer);
+                let result = self.remove(idx);
+                if result != ReturnCode::SUCCESS {
+                    self.respond(result);
+                }
+            }
+            State::Writing | State::Removing => {
+                // Unexpected data while flash is busy; drop it.
+                self.rx_buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl<'a, U: UART, F: Flash> flash::Client for AppLoader<'a, U, F> {
+    fn read_complete(&self, read_buffer: &'static mut [u8], _result: ReturnCode) {
+        self.page_buffer.replace(read_buffer);
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut [u8], result: ReturnCode) {
+        self.page_buffer.replace(write_buffer);
+
+        match self.state.get() {
+            State::Writing => {
+                if result != ReturnCode::SUCCESS {
+                    self.respond(result);
+                } else if self.received.get() < self.image_len.get() {
+                    self.page_fill.set(0);
+                    self.rx_buffer.take().map(|buffer| self.receive_chunk(buffer));
+                } else {
+                    let result = self.install();
+                    self.respond(result);
+                }
+            }
+            State::Removing => {
+                if result == ReturnCode::SUCCESS {
+                    unsafe {
+                        process::remove(AppId::new(self.remove_target.get()));
+                    }
+                }
+                self.respond(result);
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, _result: ReturnCode) {}
+}
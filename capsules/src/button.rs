@@ -1,23 +1,39 @@
 //! Provide capsule driver for controlling buttons on a board.  This allows for much more cross
 //! platform controlling of buttons without having to know which of the GPIO pins exposed across
 //! the syscall interface are buttons.
+//!
+//! If an app's callback queue is full when a button changes state, the event is
+//! remembered and delivered again, with the pin's state at that time, every
+//! `RETRY_MS` milliseconds until the app has room for it. Several dropped
+//! events for the same pin are coalesced into one callback.
 
+use core::cell::Cell;
 use kernel::{AppId, Container, Callback, Driver, ReturnCode};
 use kernel::hil;
 use kernel::hil::gpio::{Client, InterruptMode};
+use kernel::hil::time::{self, Alarm, Frequency};
 use kernel::process::Error;
 
 pub type SubscribeMap = u32;
 
-pub struct Button<'a, G: hil::gpio::Pin + 'a> {
+/// How often events dropped because of a full callback queue are retried
+pub const RETRY_MS: u32 = 10;
+
+/// Per-app state: the callback, the pins the app subscribed to, and the pins
+/// with events that could not be delivered yet.
+pub type ButtonState = (Option<Callback>, SubscribeMap, SubscribeMap);
+
+pub struct Button<'a, G: hil::gpio::Pin + 'a, A: Alarm + 'a> {
     pins: &'a [&'a G],
-    callback: Container<(Option<Callback>, SubscribeMap)>,
+    alarm: &'a A,
+    callback: Container<ButtonState>,
 }
 
-impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Button<'a, G> {
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> Button<'a, G, A> {
     pub fn new(pins: &'a [&'a G],
-               container: Container<(Option<Callback>, SubscribeMap)>)
-               -> Button<'a, G> {
+               alarm: &'a A,
+               container: Container<ButtonState>)
+               -> Button<'a, G, A> {
         // Make all pins output and off
         for (i, pin) in pins.iter().enumerate() {
             pin.make_input();
@@ -26,12 +42,37 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Button<'a, G> {
 
         Button {
             pins: pins,
+            alarm: alarm,
             callback: container,
         }
     }
 }
 
-impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
+impl<'a, G: hil::gpio::Pin, A: Alarm> Button<'a, G, A> {
+    /// Delivers the dropped events of the pins in `dropped` with their
+    /// current state. Returns the pins that still did not fit.
+    fn redeliver(&self, callback: &mut Callback, dropped: SubscribeMap) -> SubscribeMap {
+        let mut still_dropped = 0;
+        for (i, pin) in self.pins.iter().enumerate() {
+            if dropped & (1 << i) != 0 && !callback.schedule(i, pin.read() as usize, 0) {
+                still_dropped |= 1 << i;
+            }
+        }
+        still_dropped
+    }
+
+    /// Retries dropped events after `RETRY_MS`, unless a retry is already
+    /// due.
+    fn retry_later(&self) {
+        if !self.alarm.is_armed() {
+            let interval = RETRY_MS * <A::Frequency>::frequency() / 1000;
+            let tics = self.alarm.now().wrapping_add(interval);
+            self.alarm.set_alarm(tics);
+        }
+    }
+}
+
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> Driver for Button<'a, G, A> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
             // set callback for pin interrupts (no affect or reliance on individual pins being
@@ -112,17 +153,43 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
     }
 }
 
-impl<'a, G: hil::gpio::Pin> Client for Button<'a, G> {
+impl<'a, G: hil::gpio::Pin, A: Alarm> Client for Button<'a, G, A> {
     fn fired(&self, pin_num: usize) {
         // read the value of the pin
         let pins = self.pins.as_ref();
         let pin_state = pins[pin_num].read();
 
         // schedule callback with the pin number and value
-        self.callback.each(|cntr| {
-            cntr.0.map(|mut callback| if cntr.1 & (1 << pin_num) != 0 {
-                callback.schedule(pin_num, pin_state as usize, 0);
-            });
+        let retry = Cell::new(false);
+        self.callback.each(|cntr| if let Some(mut callback) = cntr.0 {
+            // Retry events dropped earlier, reporting the current state
+            let mut dropped = self.redeliver(&mut callback, cntr.2 & !(1 << pin_num));
+            if cntr.1 & (1 << pin_num) != 0 &&
+               !callback.schedule(pin_num, pin_state as usize, 0) {
+                dropped |= 1 << pin_num;
+            }
+            cntr.2 = dropped;
+            if dropped != 0 {
+                retry.set(true);
+            }
         });
+        if retry.get() {
+            self.retry_later();
+        }
+    }
+}
+
+impl<'a, G: hil::gpio::Pin, A: Alarm> time::Client for Button<'a, G, A> {
+    fn fired(&self) {
+        let retry = Cell::new(false);
+        self.callback.each(|cntr| if let Some(mut callback) = cntr.0 {
+            cntr.2 = self.redeliver(&mut callback, cntr.2);
+            if cntr.2 != 0 {
+                retry.set(true);
+            }
+        });
+        if retry.get() {
+            self.retry_later();
+        }
     }
 }
@@ -1,60 +1,145 @@
 //! Console Capsule
 //!
 //! Console provides userspace with the ability to print text via a serial
-//! interface.
+//! interface, and to read lines typed into it.
+//!
+//! Several apps share the console. With `set_tag_output(true)` every line an
+//! app prints starts with its package name in brackets, and a line an app
+//! leaves unfinished is ended when another app prints. Input is read a line
+//! at a time and goes to the selected app, or to the first app waiting for a
+//! line if none is selected. Lines are received with the UART's
+//! `receive_until_terminator`, ending at the terminator chosen by the app
+//! they go to (newline by default). When another app is selected, starts
+//! reading or changes its terminator, the reception restarts with the new
+//! terminator and keeps the bytes received so far.
+//!
+//! Typing `@name` on its own line selects the app called `name`; `@name
+//! text` also sends it `text`. `@kernel trace` writes the recent system
+//! calls to the kernel's `debug!` output, if the kernel records them (see
+//! `kernel::syscall_trace`). Lines starting with `@` always end at a
+//! newline, so for an app with another terminator the first byte of each
+//! line is received on its own to tell which terminator to use. A line that
+//! its app is not waiting for, or a selection of an unknown app, is dropped
+//! with a warning on the kernel's debug output.
+//!
+//! If the board gives the console a deferred call with `set_deferred_call`,
+//! writes that waited for another app's to finish are started from the
+//! kernel main loop rather than from inside the UART's transmit callback.
+//!
+//! ### Allow
+//! * 0: buffer for the next line read
+//! * 1: buffer to write
+//!
+//! ### Subscribe
+//! * 0: read a line; the callback gets the number of bytes read, without
+//!   the terminator
+//! * 1: write the buffer; the callback gets the number of bytes written
+//!
+//! ### Command
+//! * 0: check if present
+//! * 1: write the byte `arg1`
+//! * 2: end lines read by this app at the byte `arg1`
 
 use core::cell::Cell;
+use core::cmp::min;
+use core::str;
 use kernel::{AppId, AppSlice, Container, Callback, Shared, Driver, ReturnCode};
+use kernel::common::deferred_call::{self, DeferredCall};
 use kernel::common::take_cell::TakeCell;
-use kernel::hil::uart::{self, UART, Client};
-use kernel::process::Error;
+use kernel::hil::uart::{self, UARTAdvanced, Client};
+use kernel::process::{self, Error};
+use kernel::syscall_trace;
+
+/// Lines end at a newline unless the app picks another terminator
+pub const DEFAULT_TERMINATOR: u8 = b'\n';
+/// Longest package name printed in an output tag
+const MAX_TAG_NAME: usize = 16;
 
 pub struct App {
     write_callback: Option<Callback>,
+    read_callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
     write_buffer: Option<AppSlice<Shared, u8>>,
     write_len: usize,
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
-    read_idx: usize,
+    terminator: u8,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             write_callback: None,
+            read_callback: None,
             read_buffer: None,
             write_buffer: None,
             write_len: 0,
             write_remaining: 0,
             pending_write: false,
-            read_idx: 0,
+            terminator: DEFAULT_TERMINATOR,
         }
     }
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
+pub static mut READ_BUF: [u8; 128] = [0; 128];
 
-pub struct Console<'a, U: UART + 'a> {
+pub struct Console<'a, U: UARTAdvanced + 'a> {
     uart: &'a U,
     apps: Container<App>,
     in_progress: Cell<Option<AppId>>,
     tx_buffer: TakeCell<'static, [u8]>,
+    // Two buffers to receive into, so that one is free to restart the
+    // reception with, and the line being typed
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_spare: TakeCell<'static, [u8]>,
+    line: TakeCell<'static, [u8]>,
+    line_len: Cell<usize>,
+    line_error: Cell<bool>,
+    // Whether a reception is running, and its terminator (`None` when it
+    // receives the first byte of a line on its own)
+    receiving: Cell<bool>,
+    rx_terminator: Cell<Option<u8>>,
     baud_rate: u32,
+    tag_output: Cell<bool>,
+    // Whether the next byte printed starts a line, and which app printed
+    // the last one
+    line_start: Cell<bool>,
+    last_writer: Cell<Option<usize>>,
+    selected: Cell<Option<AppId>>,
+    deferred_call: Cell<Option<&'static DeferredCall>>,
 }
 
-impl<'a, U: UART> Console<'a, U> {
+impl<'a, U: UARTAdvanced> Console<'a, U> {
+    /// The first half of `rx_buffer` holds the line being typed, and each
+    /// quarter after it receives input.
     pub fn new(uart: &'a U,
                baud_rate: u32,
                tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
                container: Container<App>)
                -> Console<'a, U> {
+        let half = rx_buffer.len() / 2;
+        let (line, rx_buffer) = rx_buffer.split_at_mut(half);
+        let (rx_buffer, rx_spare) = rx_buffer.split_at_mut(half / 2);
         Console {
             uart: uart,
             apps: container,
             in_progress: Cell::new(None),
             tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_spare: TakeCell::new(rx_spare),
+            line: TakeCell::new(line),
+            line_len: Cell::new(0),
+            line_error: Cell::new(false),
+            receiving: Cell::new(false),
+            rx_terminator: Cell::new(None),
             baud_rate: baud_rate,
+            tag_output: Cell::new(false),
+            line_start: Cell::new(true),
+            last_writer: Cell::new(None),
+            selected: Cell::new(None),
+            deferred_call: Cell::new(None),
         }
     }
 
@@ -65,6 +150,48 @@ impl<'a, U: UART> Console<'a, U> {
             parity: uart::Parity::None,
             hw_flow_control: false,
         });
+        self.start_receive();
+    }
+
+    /// Starts every line an app prints with its package name.
+    pub fn set_tag_output(&self, tag: bool) {
+        self.tag_output.set(tag);
+    }
+
+    /// Gives the console the deferred call it starts waiting writes with.
+    pub fn set_deferred_call(&self, deferred_call: &'static DeferredCall) {
+        self.deferred_call.set(Some(deferred_call));
+    }
+
+    /// Starts the write of the first app that is waiting for the UART.
+    fn send_pending(&self) {
+        if self.in_progress.get().is_some() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let started_tx = cntr.enter(|app, _| {
+                if app.pending_write {
+                    app.pending_write = false;
+                    match self.send_continue(app.appid(), app) {
+                        Ok(more_to_send) => more_to_send,
+                        Err(return_code) => {
+                            // XXX This shouldn't ever happen?
+                            app.write_len = 0;
+                            app.write_remaining = 0;
+                            app.pending_write = false;
+                            let r0 = isize::from(return_code) as usize;
+                            app.write_callback.map(|mut cb| { cb.schedule(r0, 0, 0); });
+                            false
+                        }
+                    }
+                } else {
+                    false
+                }
+            });
+            if started_tx {
+                break;
+            }
+        }
     }
 
     /// Internal helper function for setting up a new send transaction
@@ -103,24 +230,13 @@ impl<'a, U: UART> Console<'a, U> {
         if self.in_progress.get().is_none() {
             self.in_progress.set(Some(app_id));
             self.tx_buffer.take().map(|buffer| {
-                let mut transaction_len = app.write_remaining;
-                for (i, c) in slice.as_ref()[slice.len() - app.write_remaining..slice.len()]
-                    .iter()
-                    .enumerate() {
-                    if buffer.len() <= i {
-                        break;
-                    }
-                    buffer[i] = *c;
-                }
+                let start = slice.len() - app.write_remaining;
+                let transaction_len = self.fill(app_id, app, &slice.as_ref()[start..], buffer);
 
-                // Check if everything we wanted to print
-                // fit in the buffer.
-                if app.write_remaining > buffer.len() {
-                    transaction_len = buffer.len();
-                    app.write_remaining -= buffer.len();
+                // Keep the slice if not everything we wanted to print fit
+                // in the buffer.
+                if app.write_remaining > 0 {
                     app.write_buffer = Some(slice);
-                } else {
-                    app.write_remaining = 0;
                 }
 
                 self.uart.transmit(buffer, transaction_len);
@@ -130,16 +246,224 @@ impl<'a, U: UART> Console<'a, U> {
             app.write_buffer = Some(slice);
         }
     }
+
+    /// Copies as much of `data` as fits into `buffer`, tagging lines with
+    /// the app's name if enabled. Returns the number of bytes to transmit.
+    fn fill(&self, app_id: AppId, app: &mut App, data: &[u8], buffer: &mut [u8]) -> usize {
+        let name = if self.tag_output.get() {
+            process::package_name(app_id).map(|name| {
+                let bytes = name.as_bytes();
+                &bytes[..min(bytes.len(), MAX_TAG_NAME)]
+            })
+        } else {
+            None
+        };
+
+        let mut len = 0;
+        for &byte in data.iter() {
+            if let Some(name) = name {
+                let other_writer = self.last_writer.get() != Some(app_id.idx());
+                if self.line_start.get() || other_writer {
+                    // End the other app's line, then "[name] " and this byte
+                    let end_line = !self.line_start.get();
+                    let needed = end_line as usize + name.len() + 4;
+                    if len + needed > buffer.len() {
+                        break;
+                    }
+                    if end_line {
+                        buffer[len] = b'\n';
+                        len += 1;
+                    }
+                    buffer[len] = b'[';
+                    buffer[len + 1..len + 1 + name.len()].copy_from_slice(name);
+                    len += 1 + name.len();
+                    buffer[len] = b']';
+                    buffer[len + 1] = b' ';
+                    len += 2;
+                    self.line_start.set(false);
+                    self.last_writer.set(Some(app_id.idx()));
+                }
+            }
+            if len == buffer.len() {
+                break;
+            }
+            buffer[len] = byte;
+            len += 1;
+            app.write_remaining -= 1;
+            self.line_start.set(byte == b'\n');
+            self.last_writer.set(Some(app_id.idx()));
+        }
+        len
+    }
+
+    /// The terminator to receive the rest of the line being typed with, or
+    /// `None` to receive its first byte on its own, which tells whether it
+    /// is a console command ending at a newline.
+    fn next_rx_terminator(&self) -> Option<u8> {
+        let terminator = self.terminator();
+        if self.line_len.get() == 0 && terminator != DEFAULT_TERMINATOR {
+            None
+        } else {
+            Some(terminator)
+        }
+    }
+
+    /// Starts receiving the line being typed into a free buffer, replacing
+    /// the reception in progress if there is one. Returns whether it did.
+    fn start_receive(&self) -> bool {
+        let buffer = match self.rx_buffer.take().or_else(|| self.rx_spare.take()) {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let terminator = self.next_rx_terminator();
+        self.receiving.set(true);
+        self.rx_terminator.set(terminator);
+        match terminator {
+            Some(terminator) => self.uart.receive_until_terminator(buffer, terminator),
+            None => self.uart.receive(buffer, 1),
+        }
+        true
+    }
+
+    /// Restarts the reception if the app the line goes to, or its
+    /// terminator, changed. The replaced reception hands back the bytes it
+    /// got, which can change the terminator again.
+    fn restart_receive(&self) {
+        while self.receiving.get() && self.rx_terminator.get() != self.next_rx_terminator() {
+            if !self.start_receive() {
+                break;
+            }
+        }
+    }
+
+    /// Adds received bytes to the line being typed, handling each line
+    /// they end.
+    fn add_input(&self, input: &[u8]) {
+        for &byte in input.iter() {
+            let terminator = self.terminator();
+            self.line.take().map(|line| {
+                let mut len = self.line_len.get();
+                if byte != terminator {
+                    line[len] = byte;
+                    len += 1;
+                }
+                if byte == terminator || len == line.len() {
+                    self.line_len.set(0);
+                    if !self.line_error.get() {
+                        self.line_received(&line[..len]);
+                    }
+                    self.line_error.set(false);
+                } else {
+                    self.line_len.set(len);
+                }
+                self.line.replace(line);
+            });
+        }
+    }
+
+    /// The byte that ends the line being typed: a newline for console
+    /// commands, otherwise the terminator of the app the line goes to.
+    fn terminator(&self) -> u8 {
+        let command = self.line_len.get() > 0 && self.line.map_or(false, |line| line[0] == b'@');
+        if command {
+            return DEFAULT_TERMINATOR;
+        }
+        self.reader()
+            .and_then(|appid| self.apps.enter(appid, |app, _| app.terminator).ok())
+            .unwrap_or(DEFAULT_TERMINATOR)
+    }
+
+    /// Handles a line typed into the console, without its terminator.
+    fn line_received(&self, line: &[u8]) {
+        let mut line = line;
+        // Terminals often end lines with "\r\n"
+        if line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        if line == b"@kernel trace" {
+            syscall_trace::log();
+        } else if line.first() == Some(&b'@') {
+            let name_end = line.iter().position(|c| *c == b' ').unwrap_or(line.len());
+            let name = &line[1..name_end];
+            if !self.select(name) {
+                debug_warn!("console: no app called {}", str::from_utf8(name).unwrap_or("?"));
+            } else if name_end < line.len() {
+                self.deliver(&line[name_end + 1..]);
+            }
+        } else {
+            self.deliver(line);
+        }
+    }
+
+    /// The app the next line goes to: the selected one, or else the first
+    /// one waiting for a line.
+    fn reader(&self) -> Option<AppId> {
+        if self.selected.get().is_some() {
+            return self.selected.get();
+        }
+        self.apps
+            .iter()
+            .filter_map(|cntr| {
+                cntr.enter(|app, _| if app.read_callback.is_some() {
+                    Some(app.appid())
+                } else {
+                    None
+                })
+            })
+            .next()
+    }
+
+    /// Selects the app called `name`, returning whether there is one.
+    fn select(&self, name: &[u8]) -> bool {
+        let appid = self.apps
+            .iter()
+            .map(|cntr| cntr.enter(|app, _| app.appid()))
+            .find(|appid| process::package_name(*appid).map_or(false, |n| n.as_bytes() == name));
+        if appid.is_some() {
+            self.selected.set(appid);
+        }
+        appid.is_some()
+    }
+
+    /// Gives `line` to the app that is reading, if it is waiting for one.
+    fn deliver(&self, line: &[u8]) {
+        let appid = match self.reader() {
+            Some(appid) => appid,
+            None => {
+                debug_warn!("console: no app is reading, line dropped");
+                return;
+            }
+        };
+        let delivered = self.apps
+            .enter(appid, |app, _| {
+                app.read_callback
+                    .take()
+                    .map(|mut cb| {
+                        let len = app.read_buffer.as_mut().map_or(0, |buffer| {
+                            let len = min(line.len(), buffer.len());
+                            buffer.as_mut()[..len].copy_from_slice(&line[..len]);
+                            len
+                        });
+                        cb.schedule(len, 0, 0);
+                    })
+                    .is_some()
+            })
+            .unwrap_or(false);
+        if !delivered {
+            debug_warn!("console: {} is not reading, line dropped",
+                        process::package_name(appid).unwrap_or("?"));
+        }
+    }
 }
 
-impl<'a, U: UART> Driver for Console<'a, U> {
+impl<'a, U: UARTAdvanced> Driver for Console<'a, U> {
     fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
         match allow_num {
             0 => {
                 self.apps
                     .enter(appid, |app, _| {
                         app.read_buffer = Some(slice);
-                        app.read_idx = 0;
                         ReturnCode::SUCCESS
                     })
                     .unwrap_or_else(|err| match err {
@@ -167,8 +491,22 @@ impl<'a, U: UART> Driver for Console<'a, U> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
             0 /* read line */ => {
-                // read line is not implemented for console at this time
-                ReturnCode::ENOSUPPORT
+                self.apps.enter(callback.app_id(), |app, _| {
+                    if app.read_buffer.is_none() {
+                        return ReturnCode::EINVAL;
+                    }
+                    app.read_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }).map(|rcode| {
+                    self.restart_receive();
+                    rcode
+                }).unwrap_or_else(|err| {
+                    match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    }
+                })
             },
             1 /* putstr/write_done */ => {
                 self.apps.enter(callback.app_id(), |app, _| {
@@ -185,7 +523,7 @@ impl<'a, U: UART> Driver for Console<'a, U> {
         }
     }
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> ReturnCode {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             1 /* putc */ => {
@@ -195,12 +533,33 @@ impl<'a, U: UART> Driver for Console<'a, U> {
                 });
                 ReturnCode::SuccessWithValue { value: 1 }
             },
+            2 /* set line terminator */ => {
+                self.apps.enter(appid, |app, _| {
+                    app.terminator = arg1 as u8;
+                    ReturnCode::SUCCESS
+                }).map(|rcode| {
+                    self.restart_receive();
+                    rcode
+                }).unwrap_or_else(|err| {
+                    match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    }
+                })
+            },
             _ => ReturnCode::ENOSUPPORT
         }
     }
 }
 
-impl<'a, U: UART> Client for Console<'a, U> {
+impl<'a, U: UARTAdvanced> deferred_call::Client for Console<'a, U> {
+    fn call(&self) {
+        self.send_pending();
+    }
+}
+
+impl<'a, U: UARTAdvanced> Client for Console<'a, U> {
     fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
         // Either print more from the AppSlice or send a callback to the
         // application.
@@ -232,37 +591,32 @@ impl<'a, U: UART> Client for Console<'a, U> {
         // If we are not printing more from the current AppSlice,
         // see if any other applications have pending messages.
         if self.in_progress.get().is_none() {
-            for cntr in self.apps.iter() {
-                let started_tx = cntr.enter(|app, _| {
-                    if app.pending_write {
-                        app.pending_write = false;
-                        match self.send_continue(app.appid(), app) {
-                            Ok(more_to_send) => more_to_send,
-                            Err(return_code) => {
-                                // XXX This shouldn't ever happen?
-                                app.write_len = 0;
-                                app.write_remaining = 0;
-                                app.pending_write = false;
-                                let r0 = isize::from(return_code) as usize;
-                                app.write_callback.map(|mut cb| { cb.schedule(r0, 0, 0); });
-                                false
-                            }
-                        }
-                    } else {
-                        false
-                    }
-                });
-                if started_tx {
-                    break;
-                }
+            match self.deferred_call.get() {
+                Some(deferred_call) => deferred_call.set(),
+                None => self.send_pending(),
             }
         }
     }
 
-    fn receive_complete(&self,
-                        _rx_buffer: &'static mut [u8],
-                        _rx_len: usize,
-                        _error: uart::Error) {
-        // this is currently unimplemented for console
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        let rx_len = min(rx_len, rx_buffer.len());
+        match error {
+            // RepeatCallError: a new reception replaced this one, keep what
+            // it got
+            uart::Error::CommandComplete |
+            uart::Error::RepeatCallError => self.add_input(&rx_buffer[..rx_len]),
+            // Lines with reception errors are dropped
+            _ => self.line_error.set(true),
+        }
+        if self.rx_buffer.is_none() {
+            self.rx_buffer.replace(rx_buffer);
+        } else {
+            self.rx_spare.replace(rx_buffer);
+        }
+        // The reception that replaced this one is already running
+        if error != uart::Error::RepeatCallError {
+            self.receiving.set(false);
+            self.start_receive();
+        }
     }
 }
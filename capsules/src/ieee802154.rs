@@ -0,0 +1,453 @@
+//! IEEE 802.15.4 MAC frame headers.
+//!
+//! `Header` describes the MAC header of a frame: its type, whether an
+//! acknowledgement is requested, the sequence number and the destination and
+//! source PAN IDs and addresses. `Header::decode` parses the header at the
+//! start of a frame and `Header::encode` writes one, compressing the source
+//! PAN ID when both PAN IDs are the same. Neither depends on a radio, so
+//! captured frames are checked by the host tests at the end of this file.
+//!
+//! Frames are handled without the 2-byte FCS, which the radio computes and
+//! checks. Secured frames are not supported: `decode` rejects them.
+//!
+//! ```ignore
+//! let header = Header {
+//!     frame_type: FrameType::Data,
+//!     frame_pending: false,
+//!     ack_requested: true,
+//!     version: FrameVersion::V2003,
+//!     seq: 7,
+//!     dst_pan: Some(0xabcd),
+//!     dst_addr: Some(MacAddress::Short(0x1008)),
+//!     src_pan: Some(0xabcd),
+//!     src_addr: Some(MacAddress::Short(0x1009)),
+//! };
+//! let payload_offset = header.encode(&mut frame).unwrap();
+//! ```
+
+/// Largest frame the PHY carries, including the FCS.
+pub const MAX_FRAME_SIZE: usize = 127;
+/// Size of the frame check sequence the radio appends.
+pub const FCS_SIZE: usize = 2;
+/// Short address that every node accepts.
+pub const BROADCAST_ADDR: u16 = 0xffff;
+/// Short address meaning the node has no short address and uses its
+/// extended address instead.
+pub const NO_SHORT_ADDR: u16 = 0xfffe;
+
+// Frame control field bits
+const FCF_TYPE_MASK: u16 = 0x7;
+const FCF_SECURITY: u16 = 1 << 3;
+const FCF_FRAME_PENDING: u16 = 1 << 4;
+const FCF_ACK_REQUEST: u16 = 1 << 5;
+const FCF_PAN_ID_COMPRESSION: u16 = 1 << 6;
+const FCF_DST_MODE_SHIFT: u16 = 10;
+const FCF_VERSION_SHIFT: u16 = 12;
+const FCF_SRC_MODE_SHIFT: u16 = 14;
+
+// Addressing modes
+const ADDR_MODE_NONE: u16 = 0;
+const ADDR_MODE_SHORT: u16 = 2;
+const ADDR_MODE_LONG: u16 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameType {
+    Beacon = 0,
+    Data = 1,
+    Acknowledgement = 2,
+    MACCommand = 3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameVersion {
+    V2003 = 0,
+    V2006 = 1,
+}
+
+/// A short address, or an extended (EUI-64) address. Extended addresses are
+/// stored most significant byte first, as they are usually written; on air
+/// they are sent least significant byte first.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MacAddress {
+    Short(u16),
+    Long([u8; 8]),
+}
+
+impl MacAddress {
+    fn mode(address: &Option<MacAddress>) -> u16 {
+        match *address {
+            None => ADDR_MODE_NONE,
+            Some(MacAddress::Short(_)) => ADDR_MODE_SHORT,
+            Some(MacAddress::Long(_)) => ADDR_MODE_LONG,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            MacAddress::Short(_) => 2,
+            MacAddress::Long(_) => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Header {
+    pub frame_type: FrameType,
+    pub frame_pending: bool,
+    pub ack_requested: bool,
+    pub version: FrameVersion,
+    pub seq: u8,
+    pub dst_pan: Option<u16>,
+    pub dst_addr: Option<MacAddress>,
+    /// When the PAN ID is compressed, the source PAN is the destination PAN
+    pub src_pan: Option<u16>,
+    pub src_addr: Option<MacAddress>,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+/// Reads an address with the given addressing mode at `offset`, returning it
+/// and the offset that follows it.
+fn read_address(buf: &[u8], offset: usize, mode: u16) -> Option<(Option<MacAddress>, usize)> {
+    match mode {
+        ADDR_MODE_NONE => Some((None, offset)),
+        ADDR_MODE_SHORT => {
+            if buf.len() < offset + 2 {
+                return None;
+            }
+            Some((Some(MacAddress::Short(read_u16(buf, offset))), offset + 2))
+        }
+        ADDR_MODE_LONG => {
+            if buf.len() < offset + 8 {
+                return None;
+            }
+            let mut addr = [0; 8];
+            for (i, byte) in addr.iter_mut().enumerate() {
+                *byte = buf[offset + 7 - i];
+            }
+            Some((Some(MacAddress::Long(addr)), offset + 8))
+        }
+        _ => None,
+    }
+}
+
+fn write_address(buf: &mut [u8], offset: usize, address: &MacAddress) -> usize {
+    match *address {
+        MacAddress::Short(addr) => write_u16(buf, offset, addr),
+        MacAddress::Long(addr) => {
+            for (i, byte) in addr.iter().enumerate() {
+                buf[offset + 7 - i] = *byte;
+            }
+        }
+    }
+    offset + address.size()
+}
+
+impl Header {
+    /// Whether the source PAN ID is left out because it equals the
+    /// destination PAN ID.
+    fn pan_id_compressed(&self) -> bool {
+        self.dst_addr.is_some() && self.src_addr.is_some() && self.dst_pan.is_some() &&
+        self.src_pan == self.dst_pan
+    }
+
+    /// Number of bytes `encode` writes.
+    pub fn size(&self) -> usize {
+        let mut size = 3;
+        if self.dst_addr.is_some() {
+            size += 2;
+        }
+        if self.src_addr.is_some() && !self.pan_id_compressed() {
+            size += 2;
+        }
+        size + self.dst_addr.map_or(0, |addr| addr.size()) +
+        self.src_addr.map_or(0, |addr| addr.size())
+    }
+
+    /// Parses the MAC header at the start of `frame` (which does not include
+    /// the FCS). Returns the header and the offset of the payload, or `None`
+    /// if the frame is truncated, secured or uses a reserved addressing mode.
+    pub fn decode(frame: &[u8]) -> Option<(Header, usize)> {
+        if frame.len() < 3 {
+            return None;
+        }
+        let fcf = read_u16(frame, 0);
+        if fcf & FCF_SECURITY != 0 {
+            return None;
+        }
+        let frame_type = match fcf & FCF_TYPE_MASK {
+            0 => FrameType::Beacon,
+            1 => FrameType::Data,
+            2 => FrameType::Acknowledgement,
+            3 => FrameType::MACCommand,
+            _ => return None,
+        };
+        let version = match (fcf >> FCF_VERSION_SHIFT) & 0x3 {
+            0 => FrameVersion::V2003,
+            1 => FrameVersion::V2006,
+            _ => return None,
+        };
+        let dst_mode = (fcf >> FCF_DST_MODE_SHIFT) & 0x3;
+        let src_mode = (fcf >> FCF_SRC_MODE_SHIFT) & 0x3;
+        let compressed = fcf & FCF_PAN_ID_COMPRESSION != 0;
+
+        let mut offset = 3;
+        let mut dst_pan = None;
+        if dst_mode != ADDR_MODE_NONE {
+            if frame.len() < offset + 2 {
+                return None;
+            }
+            dst_pan = Some(read_u16(frame, offset));
+            offset += 2;
+        }
+        let (dst_addr, next) = match read_address(frame, offset, dst_mode) {
+            Some(result) => result,
+            None => return None,
+        };
+        offset = next;
+
+        let mut src_pan = None;
+        if src_mode != ADDR_MODE_NONE {
+            if compressed {
+                src_pan = dst_pan;
+            } else {
+                if frame.len() < offset + 2 {
+                    return None;
+                }
+                src_pan = Some(read_u16(frame, offset));
+                offset += 2;
+            }
+        }
+        let (src_addr, next) = match read_address(frame, offset, src_mode) {
+            Some(result) => result,
+            None => return None,
+        };
+        offset = next;
+
+        let header = Header {
+            frame_type: frame_type,
+            frame_pending: fcf & FCF_FRAME_PENDING != 0,
+            ack_requested: fcf & FCF_ACK_REQUEST != 0,
+            version: version,
+            seq: frame[2],
+            dst_pan: dst_pan,
+            dst_addr: dst_addr,
+            src_pan: src_pan,
+            src_addr: src_addr,
+        };
+        Some((header, offset))
+    }
+
+    /// Writes the header at the start of `buf`. Returns the offset of the
+    /// payload, or `None` if `buf` is too short or a PAN ID is missing for
+    /// an address.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        if buf.len() < self.size() || (self.dst_addr.is_some() && self.dst_pan.is_none()) ||
+           (self.src_addr.is_some() && self.src_pan.is_none()) {
+            return None;
+        }
+        let compressed = self.pan_id_compressed();
+
+        let mut fcf = self.frame_type as u16;
+        if self.frame_pending {
+            fcf |= FCF_FRAME_PENDING;
+        }
+        if self.ack_requested {
+            fcf |= FCF_ACK_REQUEST;
+        }
+        if compressed {
+            fcf |= FCF_PAN_ID_COMPRESSION;
+        }
+        fcf |= MacAddress::mode(&self.dst_addr) << FCF_DST_MODE_SHIFT;
+        fcf |= (self.version as u16) << FCF_VERSION_SHIFT;
+        fcf |= MacAddress::mode(&self.src_addr) << FCF_SRC_MODE_SHIFT;
+        write_u16(buf, 0, fcf);
+        buf[2] = self.seq;
+
+        let mut offset = 3;
+        if let Some(ref addr) = self.dst_addr {
+            write_u16(buf, offset, self.dst_pan.unwrap_or(0));
+            offset = write_address(buf, offset + 2, addr);
+        }
+        if let Some(ref addr) = self.src_addr {
+            if !compressed {
+                write_u16(buf, offset, self.src_pan.unwrap_or(0));
+                offset += 2;
+            }
+            offset = write_address(buf, offset, addr);
+        }
+        Some(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DST_LONG: [u8; 8] = [0x00, 0x12, 0x4b, 0x00, 0x01, 0x02, 0x03, 0x04];
+    const SRC_LONG: [u8; 8] = [0x00, 0x12, 0x4b, 0x00, 0x0a, 0x0b, 0x0c, 0x0d];
+
+    // Broadcast data frame with short addresses and a compressed PAN ID
+    const DATA: [u8; 11] = [0x41, 0x88, 0x05, 0xcd, 0xab, 0xff, 0xff, 0x08, 0x10, 0x68, 0x69];
+
+    // Unicast data frame between extended addresses, PAN ID compressed
+    const EXTENDED: [u8; 23] = [0x61, 0xcc, 0x07, 0xcd, 0xab, 0x04, 0x03, 0x02, 0x01, 0x00,
+                                0x4b, 0x12, 0x00, 0x0d, 0x0c, 0x0b, 0x0a, 0x00, 0x4b, 0x12,
+                                0x00, 0x68, 0x69];
+
+    fn data_header(seq: u8) -> Header {
+        Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2003,
+            seq: seq,
+            dst_pan: None,
+            dst_addr: None,
+            src_pan: None,
+            src_addr: None,
+        }
+    }
+
+    /// Checks that `frame` decodes to `expected` with the payload at
+    /// `payload_offset`, and that `expected` encodes back to the same bytes.
+    fn round_trip(frame: &[u8], expected: Header, payload_offset: usize) {
+        assert_eq!(Header::decode(frame), Some((expected, payload_offset)));
+        assert_eq!(expected.size(), payload_offset);
+
+        let mut buf = [0; MAX_FRAME_SIZE];
+        assert_eq!(expected.encode(&mut buf), Some(payload_offset));
+        assert_eq!(&buf[..payload_offset], &frame[..payload_offset]);
+    }
+
+    #[test]
+    fn short_addresses() {
+        round_trip(&DATA,
+                   Header {
+                       dst_pan: Some(0xabcd),
+                       dst_addr: Some(MacAddress::Short(BROADCAST_ADDR)),
+                       src_pan: Some(0xabcd),
+                       src_addr: Some(MacAddress::Short(0x1008)),
+                       ..data_header(5)
+                   },
+                   9);
+    }
+
+    #[test]
+    fn acknowledgement() {
+        round_trip(&[0x02, 0x00, 0x2a],
+                   Header { frame_type: FrameType::Acknowledgement, ..data_header(0x2a) },
+                   3);
+    }
+
+    #[test]
+    fn source_address_only() {
+        let beacon = [0x00, 0x80, 0x01, 0xcd, 0xab, 0x34, 0x12, 0xff, 0xcf, 0x00, 0x00];
+        round_trip(&beacon,
+                   Header {
+                       frame_type: FrameType::Beacon,
+                       src_pan: Some(0xabcd),
+                       src_addr: Some(MacAddress::Short(0x1234)),
+                       ..data_header(1)
+                   },
+                   7);
+    }
+
+    #[test]
+    fn extended_addresses() {
+        round_trip(&EXTENDED,
+                   Header {
+                       ack_requested: true,
+                       dst_pan: Some(0xabcd),
+                       dst_addr: Some(MacAddress::Long(DST_LONG)),
+                       src_pan: Some(0xabcd),
+                       src_addr: Some(MacAddress::Long(SRC_LONG)),
+                       ..data_header(7)
+                   },
+                   21);
+    }
+
+    #[test]
+    fn different_pans() {
+        let mixed = [0x21, 0x9c, 0x09, 0xcd, 0xab, 0x04, 0x03, 0x02, 0x01, 0x00, 0x4b, 0x12,
+                     0x00, 0xef, 0xbe, 0x02, 0x00];
+        round_trip(&mixed,
+                   Header {
+                       ack_requested: true,
+                       version: FrameVersion::V2006,
+                       dst_pan: Some(0xabcd),
+                       dst_addr: Some(MacAddress::Long(DST_LONG)),
+                       src_pan: Some(0xbeef),
+                       src_addr: Some(MacAddress::Short(0x0002)),
+                       ..data_header(9)
+                   },
+                   17);
+    }
+
+    #[test]
+    fn frame_pending() {
+        let header = Header {
+            frame_pending: true,
+            dst_pan: Some(0xabcd),
+            dst_addr: Some(MacAddress::Short(0x1009)),
+            src_pan: Some(0xabcd),
+            src_addr: Some(MacAddress::Short(0x1008)),
+            ..data_header(3)
+        };
+        let mut buf = [0; MAX_FRAME_SIZE];
+        assert_eq!(header.encode(&mut buf), Some(9));
+        assert_eq!(buf[0], 0x51);
+        assert_eq!(Header::decode(&buf[..9]), Some((header, 9)));
+    }
+
+    #[test]
+    fn reject_secured() {
+        let secured = [0x49, 0x88, 0x05, 0xcd, 0xab, 0xff, 0xff, 0x08, 0x10, 0x00];
+        assert_eq!(Header::decode(&secured), None);
+    }
+
+    #[test]
+    fn reject_truncated() {
+        for len in 0..9 {
+            assert_eq!(Header::decode(&DATA[..len]), None);
+        }
+        for len in 0..21 {
+            assert_eq!(Header::decode(&EXTENDED[..len]), None);
+        }
+    }
+
+    #[test]
+    fn reject_reserved_fields() {
+        // Frame types 4 to 7
+        assert_eq!(Header::decode(&[0x04, 0x00, 0x01]), None);
+        assert_eq!(Header::decode(&[0x07, 0x00, 0x01]), None);
+        // Frame versions 2 and 3
+        assert_eq!(Header::decode(&[0x01, 0x20, 0x01]), None);
+        assert_eq!(Header::decode(&[0x01, 0x30, 0x01]), None);
+        // Reserved destination and source addressing modes
+        assert_eq!(Header::decode(&[0x01, 0x04, 0x01, 0xcd, 0xab, 0x00, 0x00]), None);
+        assert_eq!(Header::decode(&[0x01, 0x40, 0x01, 0xcd, 0xab, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn encode_rejects_bad_headers() {
+        let header = Header {
+            dst_pan: Some(0xabcd),
+            dst_addr: Some(MacAddress::Long(DST_LONG)),
+            src_pan: Some(0xabcd),
+            src_addr: Some(MacAddress::Long(SRC_LONG)),
+            ..data_header(7)
+        };
+        let mut buf = [0; MAX_FRAME_SIZE];
+        assert_eq!(header.encode(&mut buf[..20]), None);
+        assert_eq!(Header { dst_pan: None, ..header }.encode(&mut buf), None);
+        assert_eq!(Header { src_pan: None, ..header }.encode(&mut buf), None);
+    }
+}
@@ -1,8 +1,14 @@
 #![feature(const_fn)]
 #![no_std]
 
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+#[macro_use]
 extern crate kernel;
 
+pub mod app_loader;
 pub mod button;
 pub mod console;
 pub mod fm25cl;
@@ -17,12 +23,19 @@ pub mod spi;
 pub mod virtual_alarm;
 pub mod virtual_i2c;
 pub mod virtual_spi;
+pub mod virtual_radio;
+pub mod virtual_uart;
 pub mod adc;
 pub mod i2c_master_slave_driver;
 pub mod lps25hb;
 pub mod tsl2561;
 pub mod fxos8700_cq;
+pub mod ieee802154;
 pub mod rf233;
 pub mod rf233_const;
+pub mod lpl;
+pub mod sim_radio;
 pub mod radio;
+pub mod sixlowpan;
+pub mod udp;
 pub mod rng;
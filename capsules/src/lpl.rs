@@ -0,0 +1,505 @@
+//! Low-power listening: duty cycles an 802.15.4 radio so that battery
+//! powered nodes can receive frames without keeping the radio on.
+//!
+//! This is preamble sampling in the style of BoX-MAC-2. Every wakeup
+//! interval the radio turns on and listens for a short window (the duty
+//! cycle is the fraction of the interval spent listening), then goes back
+//! to sleep. Since a neighbor may be asleep, a frame is "strobed": it is
+//! sent over and over for a whole wakeup interval, so that it overlaps one
+//! of the receiver's listen windows. Strobing a frame that requests an
+//! acknowledgement stops as soon as it is acknowledged; broadcast frames
+//! are strobed for the whole interval. All nodes must use the same wakeup
+//! interval.
+//!
+//! Receivers drop the extra copies of a strobed frame by comparing each
+//! frame to the last one they received. After receiving or sending a frame
+//! the radio stays on for another listen window, to catch the rest of a
+//! fragmented datagram or a reply.
+//!
+//! `LowPowerListening` implements `hil::radio::Radio` and sits between a
+//! radio and its clients. With a wakeup interval of 0 (the default) the
+//! radio stays on and frames are sent once. Frames are strobed by default
+//! when duty cycling; `set_strobe` changes the default and
+//! `transmit_strobed` chooses for one frame.
+//!
+//! ```ignore
+//! let lpl = static_init!(
+//!     LowPowerListening<'static, RF233<'static, ...>, VirtualMuxAlarm<'static, Ast>>,
+//!     LowPowerListening::new(rf233, lpl_alarm),
+//!     608/8);
+//! lpl_alarm.set_client(lpl);
+//! rf233.set_transmit_client(lpl);
+//! rf233.set_receive_client(lpl, &mut RF233_RX_BUF);
+//! rf233.set_power_client(lpl);
+//! lpl.set_receive_client(mux_radio, &mut LPL_RX_BUF);
+//! lpl.set_wakeup_interval(500);
+//! lpl.set_duty_cycle(5);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::{max, min};
+use ieee802154::Header;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::returncode::ReturnCode;
+
+/// Longest supported wakeup interval
+pub const MAX_WAKEUP_INTERVAL_MS: u32 = 10000;
+/// Shortest listen window. A window must be longer than the gap between
+/// two copies of a strobed frame, which includes writing the frame to the
+/// radio over SPI.
+pub const MIN_LISTEN_MS: u32 = 20;
+const DEFAULT_DUTY_CYCLE: u8 = 10;
+
+/// FNV-1a hash of a frame, to recognize copies of a strobed frame.
+fn frame_hash(frame: &[u8]) -> u32 {
+    frame.iter().fold(0x811c9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+pub struct LowPowerListening<'a, R: radio::Radio + 'a, A: Alarm + 'a> {
+    radio: &'a R,
+    alarm: &'a A,
+    tx_client: Cell<Option<&'static radio::TxClient>>,
+    rx_client: Cell<Option<&'static radio::RxClient>>,
+    power_client: Cell<Option<&'static radio::PowerClient>>,
+    // Whether the client has turned the radio on, whether the radio is
+    // awake or waking up, and whether to tell the client once it is
+    on: Cell<bool>,
+    awake: Cell<bool>,
+    waking: Cell<bool>,
+    notify_on: Cell<bool>,
+    interval_ms: Cell<u32>,
+    duty_cycle: Cell<u8>,
+    strobe: Cell<bool>,
+    // A frame being sent: whether it is strobed and requests an
+    // acknowledgement, whether any copy has been sent and when strobing
+    // stops. The frame is in tx_buf while it waits for the radio.
+    transmitting: Cell<bool>,
+    tx_strobe: Cell<bool>,
+    tx_ack: Cell<bool>,
+    tx_sent: Cell<bool>,
+    tx_len: Cell<u8>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_deadline: Cell<u32>,
+    // The buffer the radio receives the next frame into after the current
+    // one is passed up, and the hash of the last frame received
+    rx_buf: TakeCell<'static, [u8]>,
+    last_rx: Cell<Option<u32>>,
+}
+
+impl<'a, R: radio::Radio, A: Alarm> LowPowerListening<'a, R, A> {
+    pub fn new(radio: &'a R, alarm: &'a A) -> LowPowerListening<'a, R, A> {
+        LowPowerListening {
+            radio: radio,
+            alarm: alarm,
+            tx_client: Cell::new(None),
+            rx_client: Cell::new(None),
+            power_client: Cell::new(None),
+            on: Cell::new(false),
+            awake: Cell::new(false),
+            waking: Cell::new(false),
+            notify_on: Cell::new(false),
+            interval_ms: Cell::new(0),
+            duty_cycle: Cell::new(DEFAULT_DUTY_CYCLE),
+            strobe: Cell::new(true),
+            transmitting: Cell::new(false),
+            tx_strobe: Cell::new(false),
+            tx_ack: Cell::new(false),
+            tx_sent: Cell::new(false),
+            tx_len: Cell::new(0),
+            tx_buf: TakeCell::empty(),
+            tx_deadline: Cell::new(0),
+            rx_buf: TakeCell::empty(),
+            last_rx: Cell::new(None),
+        }
+    }
+
+    /// Wakes the radio every `interval_ms` milliseconds to listen for
+    /// frames. 0 keeps the radio on. Returns ERANGE above
+    /// `MAX_WAKEUP_INTERVAL_MS`.
+    pub fn set_wakeup_interval(&self, interval_ms: u32) -> ReturnCode {
+        if interval_ms > MAX_WAKEUP_INTERVAL_MS {
+            return ReturnCode::ERANGE;
+        }
+        self.interval_ms.set(interval_ms);
+        if !self.on.get() || self.transmitting.get() {
+            // The new interval applies once the frame is sent
+            return ReturnCode::SUCCESS;
+        }
+        if interval_ms == 0 {
+            self.alarm.disable();
+            if self.wake() {
+                self.radio_awake();
+            }
+        } else if self.awake.get() {
+            self.set_alarm_ms(self.listen_ms());
+        }
+        ReturnCode::SUCCESS
+    }
+
+    pub fn get_wakeup_interval(&self) -> u32 {
+        self.interval_ms.get()
+    }
+
+    /// The percentage (1-100) of each wakeup interval the radio listens
+    /// for. Listen windows are at least `MIN_LISTEN_MS` long.
+    pub fn set_duty_cycle(&self, percent: u8) -> ReturnCode {
+        if percent == 0 || percent > 100 {
+            return ReturnCode::ERANGE;
+        }
+        self.duty_cycle.set(percent);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn get_duty_cycle(&self) -> u8 {
+        self.duty_cycle.get()
+    }
+
+    /// Whether `transmit` strobes frames while duty cycling (the default).
+    /// Without strobing a frame is sent once, which only reaches neighbors
+    /// that are awake.
+    pub fn set_strobe(&self, strobe: bool) {
+        self.strobe.set(strobe);
+    }
+
+    /// Like `transmit`, choosing whether to strobe this frame.
+    pub fn transmit_strobed(&self,
+                            frame: &'static mut [u8],
+                            frame_len: u8,
+                            strobe: bool)
+                            -> ReturnCode {
+        let start = self.radio.psdu_offset() as usize;
+        if !self.on.get() {
+            return ReturnCode::EOFF;
+        } else if self.transmitting.get() {
+            return ReturnCode::EBUSY;
+        } else if start + frame_len as usize > frame.len() ||
+                  frame_len > radio::MAX_FRAME_SIZE - 2 {
+            return ReturnCode::ESIZE;
+        }
+        let ack = Header::decode(&frame[start..start + frame_len as usize])
+            .map_or(false, |(header, _)| header.ack_requested);
+        self.tx_ack.set(ack);
+        self.tx_strobe.set(strobe && self.interval_ms.get() != 0);
+        self.tx_sent.set(false);
+        self.tx_len.set(frame_len);
+        self.tx_buf.replace(frame);
+        self.transmitting.set(true);
+        if self.awake.get() || self.wake() {
+            self.start_transmission();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn duty_cycling(&self) -> bool {
+        self.on.get() && self.interval_ms.get() != 0
+    }
+
+    fn listen_ms(&self) -> u32 {
+        max(self.interval_ms.get() * self.duty_cycle.get() as u32 / 100,
+            MIN_LISTEN_MS)
+    }
+
+    fn set_alarm_ms(&self, ms: u32) {
+        let tics = ms * <A::Frequency>::frequency() / 1000;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    /// Turns the radio on. Returns true if it already is, in which case no
+    /// power callback follows.
+    fn wake(&self) -> bool {
+        if self.awake.get() {
+            return true;
+        } else if self.waking.get() {
+            return false;
+        }
+        self.waking.set(true);
+        if self.radio.start() == ReturnCode::EALREADY {
+            self.waking.set(false);
+            self.awake.set(true);
+            return true;
+        }
+        false
+    }
+
+    fn sleep(&self) {
+        self.awake.set(false);
+        self.waking.set(false);
+        self.radio.stop();
+    }
+
+    /// The radio is on: send the waiting frame, or listen.
+    fn radio_awake(&self) {
+        self.waking.set(false);
+        self.awake.set(true);
+        if self.transmitting.get() {
+            self.start_transmission();
+        } else if !self.on.get() {
+            // Stopped while waking up
+            self.sleep();
+        } else if self.duty_cycling() {
+            self.set_alarm_ms(self.listen_ms());
+        }
+    }
+
+    fn start_transmission(&self) {
+        if self.tx_strobe.get() {
+            let ms = self.interval_ms.get() + self.listen_ms();
+            let tics = ms * <A::Frequency>::frequency() / 1000;
+            self.tx_deadline.set(self.alarm.now().wrapping_add(tics));
+        }
+        self.send_copy();
+    }
+
+    fn send_copy(&self) {
+        self.tx_buf.take().map(|frame| {
+            // This cannot fail: the radio is on and not sending, and the
+            // frame fits
+            self.radio.transmit(frame, self.tx_len.get());
+        });
+    }
+
+    /// Whether the strobe of the frame being sent is over.
+    fn strobe_expired(&self) -> bool {
+        let deadline = self.tx_deadline.get();
+        self.alarm.now().wrapping_sub(deadline) < (1 << 31)
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::TxClient for LowPowerListening<'a, R, A> {
+    fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
+        if result == ReturnCode::SUCCESS {
+            self.tx_sent.set(true);
+        }
+        let acked = self.tx_ack.get() && result == ReturnCode::SUCCESS;
+        if self.tx_strobe.get() && !acked && self.on.get() && !self.strobe_expired() {
+            self.tx_buf.replace(buf);
+            self.send_copy();
+            return;
+        }
+
+        // A broadcast strobe succeeds if any copy was sent
+        let result = if self.tx_strobe.get() && !self.tx_ack.get() && self.tx_sent.get() {
+            ReturnCode::SUCCESS
+        } else {
+            result
+        };
+        self.transmitting.set(false);
+        if !self.on.get() {
+            self.sleep();
+        } else if self.duty_cycling() {
+            // Listen for a reply
+            self.set_alarm_ms(self.listen_ms());
+        }
+        self.tx_client.get().map(move |client| client.send_done(buf, result));
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::RxClient for LowPowerListening<'a, R, A> {
+    fn receive(&self,
+               buf: &'static mut [u8],
+               frame_len: u8,
+               info: radio::RxInfo,
+               result: ReturnCode) {
+        if self.duty_cycling() && self.awake.get() && !self.transmitting.get() {
+            // Keep listening for the frames that may follow
+            self.set_alarm_ms(self.listen_ms());
+        }
+
+        let start = self.radio.psdu_offset() as usize;
+        let end = min(start + frame_len as usize, buf.len());
+        let hash = frame_hash(&buf[start..end]);
+        let copy = result == ReturnCode::SUCCESS && self.last_rx.get() == Some(hash);
+        if result == ReturnCode::SUCCESS {
+            self.last_rx.set(Some(hash));
+        }
+        match (self.rx_client.get(), self.rx_buf.take()) {
+            (Some(client), Some(next)) if !copy => {
+                self.radio.set_receive_buffer(next);
+                client.receive(buf, frame_len, info, result);
+            }
+            (_, next) => {
+                // A copy of the last frame, or the client still holds the
+                // last one: receive the next frame into the same buffer
+                next.map(|next| self.rx_buf.replace(next));
+                self.radio.set_receive_buffer(buf);
+            }
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::PowerClient for LowPowerListening<'a, R, A> {
+    fn changed(&self, on: bool) {
+        if on {
+            self.radio_awake();
+            if self.notify_on.get() {
+                self.notify_on.set(false);
+                self.power_client.get().map(|client| client.changed(true));
+            }
+        } else if !self.on.get() {
+            self.power_client.get().map(|client| client.changed(false));
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> time::Client for LowPowerListening<'a, R, A> {
+    fn fired(&self) {
+        // While a frame is being sent the radio stays on; the schedule
+        // resumes once it is sent
+        if !self.duty_cycling() || self.transmitting.get() {
+            return;
+        }
+        let interval = self.interval_ms.get();
+        let listen = self.listen_ms();
+        if self.awake.get() {
+            // End of a listen window
+            if listen < interval {
+                self.sleep();
+                self.set_alarm_ms(interval - listen);
+            } else {
+                self.set_alarm_ms(listen);
+            }
+        } else if self.wake() {
+            self.radio_awake();
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::Radio for LowPowerListening<'a, R, A> {
+    fn initialize(&self,
+                  spi_buf: &'static mut [u8],
+                  reg_write: &'static mut [u8],
+                  reg_read: &'static mut [u8])
+                  -> ReturnCode {
+        self.radio.initialize(spi_buf, reg_write, reg_read)
+    }
+
+    /// Turns the radio on, and starts duty cycling it if a wakeup interval
+    /// is set.
+    fn start(&self) -> ReturnCode {
+        if self.on.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.on.set(true);
+        if self.wake() {
+            self.radio_awake();
+            return ReturnCode::EALREADY;
+        }
+        self.notify_on.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    /// Turns the radio off once the frame being sent, if any, is sent.
+    /// Returns EALREADY if the radio is asleep between listen windows.
+    fn stop(&self) -> ReturnCode {
+        if !self.on.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.on.set(false);
+        self.notify_on.set(false);
+        self.alarm.disable();
+        if self.transmitting.get() {
+            ReturnCode::SUCCESS
+        } else if self.awake.get() || self.waking.get() {
+            self.sleep();
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EALREADY
+        }
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.radio.reset()
+    }
+
+    /// The radio accepts frames whenever it is on, even while it sleeps
+    /// between listen windows.
+    fn ready(&self) -> bool {
+        self.on.get()
+    }
+
+    fn set_transmit_client(&self, client: &'static radio::TxClient) {
+        self.tx_client.set(Some(client));
+    }
+
+    fn set_receive_client(&self,
+                          client: &'static radio::RxClient,
+                          receive_buffer: &'static mut [u8]) {
+        self.rx_client.set(Some(client));
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_energy_client(&self, client: &'static radio::EnergyClient) {
+        self.radio.set_energy_client(client);
+    }
+
+    fn set_power_client(&self, client: &'static radio::PowerClient) {
+        self.power_client.set(Some(client));
+    }
+
+    fn set_address(&self, addr: u16) -> ReturnCode {
+        self.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) -> ReturnCode {
+        self.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, addr: u16) -> ReturnCode {
+        self.radio.set_pan(addr)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.radio.get_pan()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.radio.set_channel(chan)
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.radio.get_channel()
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.radio.set_tx_power(power)
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.radio.get_tx_power()
+    }
+
+    fn set_max_retries(&self, retries: u8) -> ReturnCode {
+        self.radio.set_max_retries(retries)
+    }
+
+    fn get_max_retries(&self) -> u8 {
+        self.radio.get_max_retries()
+    }
+
+    fn psdu_offset(&self) -> u8 {
+        self.radio.psdu_offset()
+    }
+
+    fn transmit(&self, frame: &'static mut [u8], frame_len: u8) -> ReturnCode {
+        self.transmit_strobed(frame, frame_len, self.strobe.get())
+    }
+
+    /// Only measures while the radio is awake; returns EOFF otherwise.
+    fn energy_detect(&self) -> ReturnCode {
+        self.radio.energy_detect()
+    }
+}
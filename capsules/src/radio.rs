@@ -3,83 +3,216 @@
 
 // System call interface for sending and receiving 802.15.4 packets.
 //
+// Each application has its own receive and transmit buffers and
+// callbacks. Transmissions are queued per application (at most one
+// outstanding packet each) and the radio serves waiting applications
+// in round-robin order. Packets are sent as 802.15.4 data frames to a
+// short or extended address; unicast frames request an acknowledgement
+// and the transmit callback reports ENOACK if none arrived.
+//
+// Every received packet is delivered to all applications that have a
+// receive buffer, unless an application has asked to only see packets
+// from a particular source address or PAN. The receive callback also
+// carries the RSSI, LQI and timestamp of the packet.
+//
 // Author: Philip Levis
 // Date: Jan 12 2017
 //
 
 use core::cell::Cell;
-use kernel::{AppId, Driver, Callback, AppSlice, Shared};
-use kernel::common::take_cell::{MapCell, TakeCell};
+use ieee802154::{self, FrameType, FrameVersion, Header, MacAddress};
+use kernel::{AppId, Driver, Callback, AppSlice, Container, Shared};
+use kernel::common::take_cell::TakeCell;
 use kernel::hil::radio;
+use kernel::process::Error;
 use kernel::returncode::ReturnCode;
 
-struct App {
+pub struct App {
     tx_callback: Option<Callback>,
     rx_callback: Option<Callback>,
+    energy_callback: Option<Callback>,
     app_read: Option<AppSlice<Shared, u8>>,
     app_write: Option<AppSlice<Shared, u8>>,
+    // Destination address and payload length of a packet waiting to be sent
+    pending_tx: Option<(MacAddress, usize)>,
+    // Extended destination address set with command 13
+    dst_long: [u8; 8],
+    // Only deliver packets from this source address
+    src_filter: Option<u16>,
+    // Only deliver packets with this PAN ID
+    pan_filter: Option<u16>,
+    // Waiting for an energy measurement
+    energy_pending: bool,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            tx_callback: None,
+            rx_callback: None,
+            energy_callback: None,
+            app_read: None,
+            app_write: None,
+            pending_tx: None,
+            dst_long: [0; 8],
+            src_filter: None,
+            pan_filter: None,
+            energy_pending: false,
+        }
+    }
 }
 
 pub struct RadioDriver<'a, R: radio::Radio + 'a> {
     radio: &'a R,
-    busy: Cell<bool>,
-    app: MapCell<App>,
+    apps: Container<App>,
+    // The app whose packet the radio is currently sending
+    current_app: Cell<Option<AppId>>,
+    // Index of the app that transmitted last, for round-robin
+    last_app: Cell<usize>,
     kernel_tx: TakeCell<'static, [u8]>,
+    seq: Cell<u8>,
 }
 
 impl<'a, R: radio::Radio> RadioDriver<'a, R> {
-    pub fn new(radio: &'a R) -> RadioDriver<'a, R> {
+    pub fn new(radio: &'a R, container: Container<App>) -> RadioDriver<'a, R> {
         RadioDriver {
             radio: radio,
-            busy: Cell::new(false),
-            app: MapCell::empty(),
+            apps: container,
+            current_app: Cell::new(None),
+            last_app: Cell::new(0),
             kernel_tx: TakeCell::empty(),
+            seq: Cell::new(0),
         }
     }
 
     pub fn config_buffer(&mut self, tx_buf: &'static mut [u8]) {
         self.kernel_tx.replace(tx_buf);
     }
+
+    /// Picks the next app with a queued packet: the first one after the app
+    /// that transmitted last, wrapping around to the lowest index.
+    fn next_pending(&self) -> Option<AppId> {
+        let last = self.last_app.get();
+        let mut first: Option<AppId> = None;
+        let mut after: Option<AppId> = None;
+        for cntr in self.apps.iter() {
+            let appid = match cntr.enter(|app, _| app.pending_tx.map(|_| app.appid())) {
+                Some(appid) => appid,
+                None => continue,
+            };
+            if first.map_or(true, |id| appid.idx() < id.idx()) {
+                first = Some(appid);
+            }
+            if appid.idx() > last && after.map_or(true, |id| appid.idx() < id.idx()) {
+                after = Some(appid);
+            }
+        }
+        after.or(first)
+    }
+
+    /// Starts transmitting the next queued packet, if the radio is idle.
+    /// Apps whose packet cannot be sent get a callback with the error.
+    fn start_next(&self) {
+        while self.current_app.get().is_none() {
+            let appid = match self.next_pending() {
+                Some(appid) => appid,
+                None => return,
+            };
+            let _ = self.apps.enter(appid, |app, _| {
+                let (dst, len) = match app.pending_tx.take() {
+                    Some(pending) => pending,
+                    None => return,
+                };
+                let rval = self.transmit(app, dst, len);
+                if rval == ReturnCode::SUCCESS {
+                    self.current_app.set(Some(appid));
+                } else {
+                    app.tx_callback.map(|mut cb| { cb.schedule(usize::from(rval), 0, 0); });
+                }
+            });
+            self.last_app.set(appid.idx());
+        }
+    }
+
+    /// Builds a data frame with an app's packet in the kernel buffer and
+    /// hands it to the radio.
+    fn transmit(&self, app: &mut App, dst: MacAddress, len: usize) -> ReturnCode {
+        if !self.radio.ready() {
+            return ReturnCode::EOFF;
+        }
+        // Without a short address, send from the extended address
+        let src = match self.radio.get_address() {
+            ieee802154::NO_SHORT_ADDR | ieee802154::BROADCAST_ADDR => {
+                MacAddress::Long(self.radio.get_address_long())
+            }
+            addr => MacAddress::Short(addr),
+        };
+        let pan = self.radio.get_pan();
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_requested: dst != MacAddress::Short(ieee802154::BROADCAST_ADDR),
+            version: FrameVersion::V2003,
+            seq: self.seq.get(),
+            dst_pan: Some(pan),
+            dst_addr: Some(dst),
+            src_pan: Some(pan),
+            src_addr: Some(src),
+        };
+
+        let offset = self.radio.psdu_offset() as usize;
+        let kbuf = match self.kernel_tx.take() {
+            Some(kbuf) => kbuf,
+            None => return ReturnCode::ENOMEM,
+        };
+        // Leave room for the CRC, which must also fit in the frame
+        let frame_len = header.size() + len;
+        if offset + frame_len + ieee802154::FCS_SIZE > kbuf.len() ||
+           frame_len + ieee802154::FCS_SIZE > ieee802154::MAX_FRAME_SIZE {
+            self.kernel_tx.replace(kbuf);
+            return ReturnCode::ESIZE;
+        }
+        let payload_offset = match header.encode(&mut kbuf[offset..]) {
+            Some(header_len) => offset + header_len,
+            None => {
+                self.kernel_tx.replace(kbuf);
+                return ReturnCode::FAIL;
+            }
+        };
+        match app.app_write {
+            Some(ref src) if src.len() >= len => {
+                for (i, c) in src.as_ref()[0..len].iter().enumerate() {
+                    kbuf[i + payload_offset] = *c;
+                }
+            }
+            _ => {
+                self.kernel_tx.replace(kbuf);
+                return ReturnCode::ESIZE;
+            }
+        }
+        self.seq.set(self.seq.get().wrapping_add(1));
+        self.radio.transmit(kbuf, frame_len as u8)
+    }
 }
 
 impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
-    fn allow(&self, _appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
         match allow_num {
-            0 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            tx_callback: None,
-                            rx_callback: None,
-                            app_read: Some(slice),
-                            app_write: None,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_read = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
-            }
-            1 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            tx_callback: None,
-                            rx_callback: None,
-                            app_read: None,
-                            app_write: Some(slice),
+            0 | 1 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        if allow_num == 0 {
+                            app.app_read = Some(slice);
+                        } else {
+                            app.app_write = Some(slice);
                         }
-                    }
-                    Some(mut appc) => {
-                        appc.app_write = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
             }
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -87,37 +220,19 @@ impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
 
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
-            0 /* transmit done*/  => {
-                let appc = match self.app.take() {
-                    None => App {
-                        tx_callback: Some(callback),
-                        rx_callback: None,
-                        app_read: None,
-                        app_write: None,
-                    },
-                    Some(mut appc) => {
-                        appc.tx_callback = Some(callback);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
-            },
-            1 /* receive */ => {
-                let appc = match self.app.take() {
-                    None => App {
-                        tx_callback: None,
-                        rx_callback: Some(callback),
-                        app_read: None,
-                        app_write: None,
-                    },
-                    Some(mut appc) => {
-                        appc.rx_callback = Some(callback);
-                        appc
+            0 /* transmit done */ | 1 /* receive */ | 2 /* energy detect done */ => {
+                self.apps.enter(callback.app_id(), |app, _| {
+                    match subscribe_num {
+                        0 => app.tx_callback = Some(callback),
+                        1 => app.rx_callback = Some(callback),
+                        _ => app.energy_callback = Some(callback),
                     }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
             },
             _ => ReturnCode::ENOSUPPORT
         }
@@ -129,8 +244,36 @@ impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
     // 3: set channel
     // 4: set tx power
     // 5: transmit packet
+    // 6: check if on
+    // 7: set receive source address filter
+    // 8: set receive PAN filter
+    // 9: get channel
+    // 10: get tx power (returned as a signed value in the second return value)
+    // 11: measure energy on the channel (clear channel assessment)
+    // 12: set the number of retries for unacknowledged packets
+    // 13: set the extended destination address (command2: the upper 32
+    //     bits in the first argument, the lower 32 bits in the second)
+
+    fn command2(&self, cmd_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            13 /* set extended destination */ => {
+                self.apps.enter(appid, |app, _| {
+                    let value = (arg1 as u64) << 32 | (arg2 as u32 as u64);
+                    for (i, byte) in app.dst_long.iter_mut().enumerate() {
+                        *byte = (value >> (56 - 8 * i)) as u8;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            _ => self.command(cmd_num, arg1, appid),
+        }
+    }
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> ReturnCode {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             1 /* set 16-bit address */ => {
@@ -139,57 +282,62 @@ impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
             2 /* set PAN id */ => {
                 self.radio.set_pan(arg1 as u16)
             },
-            3 /* set channel */ => { // not yet supported
-                ReturnCode::ENOSUPPORT
+            3 /* set channel */ => {
+                if arg1 > 0xff {
+                    return ReturnCode::ERANGE;
+                }
+                self.radio.set_channel(arg1 as u8)
             },
-            4 /* set tx power */ => { // not yet supported
-                ReturnCode::ENOSUPPORT
+            4 /* set tx power */ => {
+                // The power in dBm is a signed argument
+                let power = arg1 as isize;
+                if power < -128 || power > 127 {
+                    return ReturnCode::ERANGE;
+                }
+                self.radio.set_tx_power(power as i8)
             },
             5 /* tx packet */ => {
-                // Don't transmit if we're busy, the radio is off, or
-                // we don't have a buffer yet.
-                if self.busy.get() {
-                    return ReturnCode::EBUSY;
-                } else if !self.radio.ready() {
+                // Don't queue a packet if the radio is off.
+                if !self.radio.ready() && self.current_app.get().is_none() {
                     return ReturnCode::EOFF;
-                } else if self.kernel_tx.is_none() {
-                    return ReturnCode::ENOMEM;
                 }
 
                 // The argument packs the 16-bit destination address
                 // and length in the 32-bit argument. Bits 0-15 are
-                // the address and bits 16-23 are the length.
-                self.app.map(|app| {
-                    let mut blen = 0;
-                    // If write buffer too small, return
-                    app.app_write.as_mut().map(|w| {
-                        blen = w.len();
-                    });
-                    let len: usize = (arg1 >> 16) & 0xff;
-                    let addr: u16 = (arg1 & 0xffff) as u16;
-                    if blen < len {
-                        return ReturnCode::ESIZE;
+                // the address and bits 16-23 are the length. If bit 24
+                // is set, the packet goes to the extended address set
+                // with command 13 instead.
+                let len: usize = (arg1 >> 16) & 0xff;
+                let addr: u16 = (arg1 & 0xffff) as u16;
+                let extended = arg1 & (1 << 24) != 0;
+                let rval = self.apps.enter(appid, |app, _| {
+                    let sending = self.current_app
+                        .get()
+                        .map_or(false, |id| id.idx() == appid.idx());
+                    if app.pending_tx.is_some() || sending {
+                        return ReturnCode::EBUSY;
                     }
-                    let offset = self.radio.payload_offset() as usize;
-                    // Copy the packet into the kernel buffer
-                    self.kernel_tx.map(|kbuf| {
-                        app.app_write.as_mut().map(|src| {
-                            for (i, c) in src.as_ref()[0..len].iter().enumerate() {
-                                kbuf[i + offset] = *c;
-                            }
-                        });
-                    });
-                    let transmit_len = len as u8 + self.radio.header_size();
-                    let kbuf = self.kernel_tx.take().unwrap();
-
-                    let rval = self.radio.transmit(addr, kbuf, transmit_len);
-                    if rval == ReturnCode::SUCCESS {
-                        self.busy.set(true);
-                        return ReturnCode::SUCCESS
+                    match app.app_write {
+                        Some(ref w) if w.len() >= len => {}
+                        Some(_) => return ReturnCode::ESIZE,
+                        None => return ReturnCode::ERESERVE,
                     }
-                    return rval;
+                    let dst = if extended {
+                        MacAddress::Long(app.dst_long)
+                    } else {
+                        MacAddress::Short(addr)
+                    };
+                    app.pending_tx = Some((dst, len));
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
                 });
-                return ReturnCode::ERESERVE;
+                if rval == ReturnCode::SUCCESS {
+                    self.start_next();
+                }
+                rval
             },
             6 /* check if on */ => {
                 if self.radio.ready() {
@@ -198,6 +346,58 @@ impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
                     ReturnCode::EOFF
                 }
             }
+            7 /* set source filter */ | 8 /* set PAN filter */ => {
+                // Bits 0-15 are the address or PAN to accept; bit 16
+                // enables the filter. Without bit 16 the filter is removed.
+                let filter = if arg1 & (1 << 16) != 0 {
+                    Some((arg1 & 0xffff) as u16)
+                } else {
+                    None
+                };
+                self.apps.enter(appid, |app, _| {
+                    if cmd_num == 7 {
+                        app.src_filter = filter;
+                    } else {
+                        app.pan_filter = filter;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            9 /* get channel */ => {
+                ReturnCode::SuccessWithValue { value: self.radio.get_channel() as usize }
+            }
+            10 /* get tx power */ => {
+                // The power may be negative, so it can't be the first
+                // return value
+                ReturnCode::SuccessWithValues {
+                    value0: 0,
+                    value1: self.radio.get_tx_power() as isize as usize,
+                }
+            }
+            11 /* energy detect */ => {
+                let rval = self.radio.energy_detect();
+                if rval != ReturnCode::SUCCESS {
+                    return rval;
+                }
+                self.apps.enter(appid, |app, _| {
+                    app.energy_pending = true;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            12 /* set retries */ => {
+                if arg1 > 0xff {
+                    return ReturnCode::ERANGE;
+                }
+                self.radio.set_max_retries(arg1 as u8)
+            }
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -205,33 +405,76 @@ impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
 
 impl<'a, R: radio::Radio> radio::TxClient for RadioDriver<'a, R> {
     fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
-        self.app.map(move |app| {
-            self.kernel_tx.replace(buf);
-            self.busy.set(false);
-            app.tx_callback.take().map(|mut cb| { cb.schedule(usize::from(result), 0, 0); });
+        self.kernel_tx.replace(buf);
+        self.current_app.get().map(|appid| {
+            self.current_app.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback.map(|mut cb| { cb.schedule(usize::from(result), 0, 0); });
+            });
         });
+        self.start_next();
     }
 }
 
 impl<'a, R: radio::Radio> radio::RxClient for RadioDriver<'a, R> {
-    fn receive(&self, buf: &'static mut [u8], len: u8, result: ReturnCode) {
-        if self.app.is_some() {
-            self.app.map(move |app| {
-                if app.app_read.is_some() {
-                    let offset = self.radio.payload_offset() as usize;
-                    let dest = app.app_read.as_mut().unwrap();
-                    let d = &mut dest.as_mut();
-                    for (i, c) in buf[offset..len as usize].iter().enumerate() {
-                        d[i] = *c;
-                    }
-                    app.rx_callback
-                        .take()
-                        .map(|mut cb| { cb.schedule(usize::from(result), 0, 0); });
-                }
-                self.radio.set_receive_buffer(buf);
-            });
+    fn receive(&self,
+               buf: &'static mut [u8],
+               frame_len: u8,
+               info: radio::RxInfo,
+               result: ReturnCode) {
+        let start = self.radio.psdu_offset() as usize;
+        let len = start + frame_len as usize;
+        let decoded = if len <= buf.len() {
+            Header::decode(&buf[start..len])
         } else {
-            self.radio.set_receive_buffer(buf);
+            None
+        };
+        match decoded {
+            // Only data frames carry packets for apps
+            Some((ref header, header_len)) if header.frame_type == FrameType::Data => {
+                let offset = start + header_len;
+                let pan = header.dst_pan.or(header.src_pan);
+                let src = header.src_addr;
+                self.apps.each(|app| {
+                    let src_ok = app.src_filter
+                        .map_or(true, |filter| src == Some(MacAddress::Short(filter)));
+                    let pan_ok = app.pan_filter.map_or(true, |filter| pan == Some(filter));
+                    if !src_ok || !pan_ok {
+                        return;
+                    }
+                    let delivered = app.app_read.as_mut().map_or(false, |dest| {
+                        let d = dest.as_mut();
+                        for (to, from) in d.iter_mut().zip(buf[offset..len].iter()) {
+                            *to = *from;
+                        }
+                        true
+                    });
+                    if delivered {
+                        // Bits 0-7 are the payload length, 8-15 the LQI and
+                        // 16-23 the RSSI (signed)
+                        let packed = (len - offset) | (info.lqi as usize) << 8 |
+                                     (info.rssi as u8 as usize) << 16;
+                        let timestamp = info.timestamp as usize;
+                        app.rx_callback
+                            .map(|mut cb| { cb.schedule(usize::from(result), packed, timestamp); });
+                    }
+                });
+            }
+            _ => {}
         }
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+impl<'a, R: radio::Radio> radio::EnergyClient for RadioDriver<'a, R> {
+    fn energy_detect_done(&self, rssi: i8, clear: bool, result: ReturnCode) {
+        self.apps.each(|app| {
+            if app.energy_pending {
+                app.energy_pending = false;
+                app.energy_callback.map(|mut cb| {
+                    cb.schedule(usize::from(result), rssi as isize as usize, clear as usize);
+                });
+            }
+        });
     }
 }
@@ -9,11 +9,13 @@
 /// handling with requests and radio state management. See the SPI
 /// read_write_done handler for details.
 ///
-/// To do items:
-///    - Support TX power control
-///    - Support channel selection
-///    - Support link-layer acknowledgements
-///    - Support power management (turning radio off)
+/// Frames that request an acknowledgement are retried by the radio
+/// hardware (extended operating mode); the TRAC status it reports
+/// after a transmission is passed to the transmit client.
+///
+/// stop() puts the radio to sleep once it is idle and start() wakes it.
+/// The radio keeps its registers while asleep, so settings changed in
+/// the meantime are written when it wakes.
 // Capsule for sending 802.15.4 packets with an Atmel RF233.
 //
 // Author: Philip Levis
@@ -25,6 +27,7 @@ use kernel::common::take_cell::TakeCell;
 use kernel::hil::gpio;
 use kernel::hil::radio;
 use kernel::hil::spi;
+use kernel::hil::time;
 use kernel::returncode::ReturnCode;
 use rf233_const::*;
 
@@ -33,13 +36,14 @@ const INTERRUPT_ID: usize = 0x2154;
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq)]
 enum InternalState {
-    // There are 6 high-level states:
+    // There are 7 high-level states:
     // START -- the initialization sequence
     // ON    -- turning the radio on to receive
     // READY -- waiting to receive packets
     // RX    -- receiving a packet
     // TX    -- transmitting a packet
     // CONFIG -- reconfiguring the radio
+    // SLEEP -- turned off by stop(), or waking up
     START,
     START_PART_READ,
     START_STATUS_READ,
@@ -71,6 +75,12 @@ enum InternalState {
     ON_PLL_WAITING,
     ON_PLL_SET,
 
+    // Turning the transceiver off before sleeping, asleep, and waiting
+    // for the transceiver to wake up.
+    SLEEP_TRX_OFF,
+    SLEEP,
+    SLEEP_WAKING,
+
     // Radio is in the RX_AACK_ON state, ready to receive packets.
     READY,
 
@@ -87,6 +97,7 @@ enum InternalState {
     TX_ARET_ON,
     TX_TRANSMITTING,
     TX_DONE,
+    TX_READ_TRAC,
     TX_RETURN_TO_RX,
 
     // This state denotes we began a transmission, but
@@ -97,10 +108,29 @@ enum InternalState {
     TX_PENDING,
 
     // Intermediate states when setting the short address
-    // and PAN ID.
+    // and PAN ID, which reconfiguring also writes.
     CONFIG_SHORT0_SET,
     CONFIG_SHORT1_SET,
     CONFIG_PAN0_SET,
+    CONFIG_PAN1_SET,
+
+    // Intermediate states when setting the channel, transmit
+    // power, retries and extended address.
+    CONFIG_CCA_SET,
+    CONFIG_PWR_SET,
+    CONFIG_XAH0_SET,
+    CONFIG_IEEE0_SET,
+    CONFIG_IEEE1_SET,
+    CONFIG_IEEE2_SET,
+    CONFIG_IEEE3_SET,
+    CONFIG_IEEE4_SET,
+    CONFIG_IEEE5_SET,
+    CONFIG_IEEE6_SET,
+    CONFIG_IEEE7_SET,
+
+    // Reading the RSSI register to measure the energy on
+    // the channel.
+    ED_RSSI_READ,
 
     // RX is a short-lived state for when software has detected
     // the chip is receiving a packet (by internal state) but has
@@ -158,8 +188,9 @@ enum InternalState {
 // and waits for the interrupt specifying the entire packet has been
 // received.
 
-pub struct RF233<'a, S: spi::SpiMasterDevice + 'a> {
+pub struct RF233<'a, S: spi::SpiMasterDevice + 'a, A: time::Alarm + 'a> {
     spi: &'a S,
+    alarm: &'a A,
     radio_on: Cell<bool>,
     transmitting: Cell<bool>,
     receiving: Cell<bool>,
@@ -176,9 +207,23 @@ pub struct RF233<'a, S: spi::SpiMasterDevice + 'a> {
     tx_len: Cell<u8>,
     tx_client: Cell<Option<&'static radio::TxClient>>,
     rx_client: Cell<Option<&'static radio::RxClient>>,
+    energy_client: Cell<Option<&'static radio::EnergyClient>>,
+    power_client: Cell<Option<&'static radio::PowerClient>>,
     addr: Cell<u16>,
     pan: Cell<u16>,
-    seq: Cell<u8>,
+    addr_long: Cell<[u8; 8]>,
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+    max_retries: Cell<u8>,
+    // TRAC_STATUS of the last transmission
+    trac_status: Cell<u8>,
+    config_pending: Cell<bool>,
+    energy_pending: Cell<bool>,
+    // stop() was called and the radio has not gone to sleep yet
+    sleep_pending: Cell<bool>,
+    // Time of the last interrupt, and of the last start of frame
+    irq_time: Cell<u32>,
+    rx_timestamp: Cell<u32>,
     spi_rx: TakeCell<'static, [u8]>,
     spi_tx: TakeCell<'static, [u8]>,
     spi_buf: TakeCell<'static, [u8]>,
@@ -188,7 +233,55 @@ fn interrupt_included(mask: u8, interrupt: u8) -> bool {
     (mask & interrupt) == interrupt
 }
 
-impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
+/// The XAH_CTRL_0 value for `retries` frame retries.
+fn xah_ctrl_0(retries: u8) -> u8 {
+    (retries << XAH_CTRL_0_MAX_FRAME_RETRIES_SHIFT) | XAH_CTRL_0_MAX_CSMA_RETRIES
+}
+
+/// Whether the radio is writing the channel, power, retries or addresses.
+fn configuring(state: InternalState) -> bool {
+    match state {
+        InternalState::CONFIG_SHORT0_SET |
+        InternalState::CONFIG_SHORT1_SET |
+        InternalState::CONFIG_PAN0_SET |
+        InternalState::CONFIG_CCA_SET |
+        InternalState::CONFIG_PWR_SET |
+        InternalState::CONFIG_XAH0_SET |
+        InternalState::CONFIG_IEEE0_SET |
+        InternalState::CONFIG_IEEE1_SET |
+        InternalState::CONFIG_IEEE2_SET |
+        InternalState::CONFIG_IEEE3_SET |
+        InternalState::CONFIG_IEEE4_SET |
+        InternalState::CONFIG_IEEE5_SET |
+        InternalState::CONFIG_IEEE6_SET |
+        InternalState::CONFIG_IEEE7_SET => true,
+        _ => false,
+    }
+}
+
+/// Converts an energy level (PHY_ED_LEVEL) to dBm. 0xFF means the radio
+/// made no measurement.
+fn ed_to_rssi(ed: u8) -> i8 {
+    if ed > ED_LEVEL_MAX {
+        RSSI_BASE_VAL
+    } else {
+        RSSI_BASE_VAL + ed as i8
+    }
+}
+
+/// Converts a PHY_RSSI reading to dBm. Each step is 3 dB.
+fn rssi_to_dbm(rssi: u8) -> i8 {
+    if rssi == 0 {
+        RSSI_BASE_VAL
+    } else {
+        RSSI_BASE_VAL + 3 * (rssi as i8 - 1)
+    }
+}
+
+impl<'a, S, A> spi::SpiMasterClient for RF233<'a, S, A>
+    where S: spi::SpiMasterDevice + 'a,
+          A: time::Alarm + 'a
+{
     fn read_write_done(&self,
                        mut _write: &'static mut [u8],
                        mut read: Option<&'static mut [u8]>,
@@ -258,8 +351,16 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                       interrupt_included(interrupt, IRQ_3_TRX_END) {
                 self.state.set(InternalState::TX_DONE);
             }
-            if interrupt_included(interrupt, IRQ_2_RX_START) {
-                // Start of frame
+            // A frame that starts while the transceiver is turning off is
+            // not received.
+            if interrupt_included(interrupt, IRQ_2_RX_START) &&
+               state != InternalState::SLEEP_TRX_OFF {
+                // Start of frame. If we were writing the channel
+                // and power, do it again after the packet.
+                if configuring(state) {
+                    self.config_pending.set(true);
+                }
+                self.rx_timestamp.set(self.irq_time.get());
                 self.receiving.set(true);
                 self.state.set(InternalState::RX);
             }
@@ -289,7 +390,31 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
             // Default on state; wait for transmit() call or receive
             // interrupt
             InternalState::READY => {
-                self.radio_on.set(true);
+                if self.sleep_pending.get() {
+                    self.state_transition_write(RF233Register::TRX_STATE,
+                                                RF233TrxCmd::OFF as u8,
+                                                InternalState::SLEEP_TRX_OFF);
+                    return;
+                }
+                // The channel or power changed while the radio was
+                // busy or asleep: apply it now.
+                if self.config_pending.get() {
+                    self.config_pending.set(false);
+                    self.state_transition_write(RF233Register::PHY_CC_CCA,
+                                                PHY_CC_CCA_MODE | self.channel.get(),
+                                                InternalState::CONFIG_CCA_SET);
+                    return;
+                }
+                if self.energy_pending.get() {
+                    self.state_transition_read(RF233Register::PHY_RSSI,
+                                               InternalState::ED_RSSI_READ);
+                    return;
+                }
+                // Done turning on: the client may transmit right away
+                if !self.radio_on.get() {
+                    self.radio_on.set(true);
+                    self.power_client.get().map(|client| client.changed(true));
+                }
                 // Useful debug code to test radio can transmit without
                 // an app/calling system calls
                 //unsafe {
@@ -329,12 +454,12 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
             }
             InternalState::START_CTRL1_SET => {
                 self.state_transition_write(RF233Register::PHY_CC_CCA,
-                                            PHY_CC_CCA,
+                                            PHY_CC_CCA_MODE | self.channel.get(),
                                             InternalState::START_CCA_SET);
             }
             InternalState::START_CCA_SET => {
                 self.state_transition_write(RF233Register::PHY_TX_PWR,
-                                            PHY_TX_PWR,
+                                            self.tx_power_register(),
                                             InternalState::START_PWR_SET);
             }
             InternalState::START_PWR_SET => {
@@ -358,67 +483,67 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                 // This encapsulates the frame retry and CSMA retry
                 // settings in the RF233 C code
                 self.state_transition_write(RF233Register::XAH_CTRL_0,
-                                            XAH_CTRL_0,
+                                            xah_ctrl_0(self.max_retries.get()),
                                             InternalState::START_XAH0_SET);
             }
             InternalState::START_XAH0_SET => {
                 self.state_transition_write(RF233Register::PAN_ID_0,
-                                            (self.pan.get() >> 8) as u8,
+                                            (self.pan.get() & 0xff) as u8,
                                             InternalState::START_PANID0_SET);
             }
             InternalState::START_PANID0_SET => {
                 self.state_transition_write(RF233Register::PAN_ID_1,
-                                            (self.pan.get() & 0xff) as u8,
+                                            (self.pan.get() >> 8) as u8,
                                             InternalState::START_PANID1_SET);
             }
             InternalState::START_PANID1_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_0,
-                                            IEEE_ADDR_0,
+                                            self.ieee_addr_byte(0),
                                             InternalState::START_IEEE0_SET);
             }
             InternalState::START_IEEE0_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_1,
-                                            IEEE_ADDR_1,
+                                            self.ieee_addr_byte(1),
                                             InternalState::START_IEEE1_SET);
             }
             InternalState::START_IEEE1_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_2,
-                                            IEEE_ADDR_2,
+                                            self.ieee_addr_byte(2),
                                             InternalState::START_IEEE2_SET);
             }
             InternalState::START_IEEE2_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_3,
-                                            IEEE_ADDR_3,
+                                            self.ieee_addr_byte(3),
                                             InternalState::START_IEEE3_SET);
             }
             InternalState::START_IEEE3_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_4,
-                                            IEEE_ADDR_4,
+                                            self.ieee_addr_byte(4),
                                             InternalState::START_IEEE4_SET);
             }
             InternalState::START_IEEE4_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_5,
-                                            IEEE_ADDR_5,
+                                            self.ieee_addr_byte(5),
                                             InternalState::START_IEEE5_SET);
             }
             InternalState::START_IEEE5_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_6,
-                                            IEEE_ADDR_6,
+                                            self.ieee_addr_byte(6),
                                             InternalState::START_IEEE6_SET);
             }
             InternalState::START_IEEE6_SET => {
                 self.state_transition_write(RF233Register::IEEE_ADDR_7,
-                                            IEEE_ADDR_7,
+                                            self.ieee_addr_byte(7),
                                             InternalState::START_IEEE7_SET);
             }
             InternalState::START_IEEE7_SET => {
                 self.state_transition_write(RF233Register::SHORT_ADDR_0,
-                                            (self.addr.get() >> 8) as u8,
+                                            (self.addr.get() & 0xff) as u8,
                                             InternalState::START_SHORT0_SET);
             }
             InternalState::START_SHORT0_SET => {
                 self.state_transition_write(RF233Register::SHORT_ADDR_1,
-                                            (self.addr.get() & 0xff) as u8,
+                                            (self.addr.get() >> 8) as u8,
                                             InternalState::START_SHORT1_SET);
             }
             InternalState::START_SHORT1_SET => {
@@ -455,6 +580,31 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                                             RF233TrxCmd::RX_AACK_ON as u8,
                                             InternalState::READY);
             }
+            InternalState::SLEEP_TRX_OFF => {
+                if self.sleep_pending.get() {
+                    self.sleep_pending.set(false);
+                    self.sleep_pin.set();
+                    self.state.set(InternalState::SLEEP);
+                    self.power_client.get().map(|client| client.changed(false));
+                } else {
+                    // start() was called while turning off
+                    self.state_transition_write(RF233Register::TRX_STATE,
+                                                RF233TrxCmd::PLL_ON as u8,
+                                                InternalState::ON_PLL_WAITING);
+                }
+            }
+            InternalState::SLEEP => {}
+            InternalState::SLEEP_WAKING => {
+                // The transceiver is off once it is awake
+                if status == ExternalState::TRX_OFF as u8 {
+                    self.state_transition_write(RF233Register::TRX_STATE,
+                                                RF233TrxCmd::PLL_ON as u8,
+                                                InternalState::ON_PLL_WAITING);
+                } else {
+                    self.state_transition_read(RF233Register::TRX_STATUS,
+                                               InternalState::SLEEP_WAKING);
+                }
+            }
             InternalState::TX_STATUS_PRECHECK1 => {
                 if (status == ExternalState::BUSY_RX_AACK as u8 ||
                     status == ExternalState::BUSY_TX_ARET as u8 ||
@@ -514,6 +664,11 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                 // interrupt handling will transition to the TX_DONE state.
             }
             InternalState::TX_DONE => {
+                // Find out whether the frame was acknowledged
+                self.state_transition_read(RF233Register::TRX_STATE, InternalState::TX_READ_TRAC);
+            }
+            InternalState::TX_READ_TRAC => {
+                self.trac_status.set(result >> TRX_STATE_TRAC_SHIFT);
                 self.state_transition_write(RF233Register::TRX_STATE,
                                             RF233TrxCmd::RX_AACK_ON as u8,
                                             InternalState::TX_RETURN_TO_RX);
@@ -524,9 +679,15 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                     let buf = self.tx_buf.take();
                     self.state_transition_read(RF233Register::TRX_STATUS, InternalState::READY);
 
+                    let result = match self.trac_status.get() {
+                        TRAC_SUCCESS | TRAC_SUCCESS_DATA_PENDING => ReturnCode::SUCCESS,
+                        TRAC_CHANNEL_ACCESS_FAILURE => ReturnCode::EBUSY,
+                        TRAC_NO_ACK => ReturnCode::ENOACK,
+                        _ => ReturnCode::FAIL,
+                    };
                     self.tx_client
                         .get()
-                        .map(|c| { c.send_done(buf.unwrap(), ReturnCode::SUCCESS); });
+                        .map(|c| { c.send_done(buf.unwrap(), result); });
                 } else {
                     self.register_read(RF233Register::TRX_STATUS);
                 }
@@ -554,13 +715,13 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                 // Because the first byte of a frame read is
                 // the status of the chip, the first byte of the
                 // packet, the length field, is at index 1.
-                // Subtract 2 for CRC, 1 for length byte.
-                let len = result - 2 + 1;
-                // If the packet isn't too long, read it
-                if (len <= radio::MAX_PACKET_SIZE && len >= radio::MIN_PACKET_SIZE) {
+                let len = result;
+                // If the packet isn't too long, read it, including
+                // the CRC and the LQI and ED bytes that follow it.
+                if (len <= radio::MAX_FRAME_SIZE && len >= radio::MIN_FRAME_SIZE) {
                     self.state.set(InternalState::RX_READING_FRAME);
                     let rbuf = self.rx_buf.take().unwrap();
-                    self.frame_read(rbuf, len);
+                    self.frame_read(rbuf, result + 3);
                 } else if self.transmitting.get() {
                     // Packet was too long and a transmission is pending,
                     // start the transmission
@@ -585,9 +746,16 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
                 }
                 self.rx_client.get().map(|client| {
                     let rbuf = self.rx_buf.take().unwrap();
-                    // Subtract the CRC and add the length byte
-                    let len = rbuf[1] - 2 + 1;
-                    client.receive(rbuf, len, ReturnCode::SUCCESS);
+                    // Subtract the CRC
+                    let len = rbuf[1] - 2;
+                    // The radio appends the LQI and energy level
+                    // after the CRC
+                    let info = radio::RxInfo {
+                        rssi: ed_to_rssi(rbuf[rbuf[1] as usize + 3]),
+                        lqi: rbuf[rbuf[1] as usize + 2],
+                        timestamp: self.rx_timestamp.get(),
+                    };
+                    client.receive(rbuf, len, info, ReturnCode::SUCCESS);
                 });
             }
 
@@ -604,30 +772,118 @@ impl<'a, S: spi::SpiMasterDevice + 'a> spi::SpiMasterClient for RF233<'a, S> {
             InternalState::CONFIG_PAN0_SET => {
                 self.state_transition_write(RF233Register::PAN_ID_1,
                                             (self.pan.get() >> 8) as u8,
-                                            InternalState::READY);
+                                            InternalState::CONFIG_PAN1_SET);
+            }
+            InternalState::CONFIG_PAN1_SET => {
+                // A transmit() call while we were reconfiguring
+                // is waiting for us to return to READY.
+                if self.transmitting.get() {
+                    self.state_transition_read(RF233Register::TRX_STATUS,
+                                               InternalState::TX_STATUS_PRECHECK1);
+                } else {
+                    self.state_transition_read(RF233Register::TRX_STATUS, InternalState::READY);
+                }
+            }
+            InternalState::CONFIG_CCA_SET => {
+                self.state_transition_write(RF233Register::PHY_TX_PWR,
+                                            self.tx_power_register(),
+                                            InternalState::CONFIG_PWR_SET);
+            }
+            InternalState::CONFIG_PWR_SET => {
+                self.state_transition_write(RF233Register::XAH_CTRL_0,
+                                            xah_ctrl_0(self.max_retries.get()),
+                                            InternalState::CONFIG_XAH0_SET);
+            }
+            InternalState::CONFIG_XAH0_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_0,
+                                            self.ieee_addr_byte(0),
+                                            InternalState::CONFIG_IEEE0_SET);
+            }
+            InternalState::CONFIG_IEEE0_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_1,
+                                            self.ieee_addr_byte(1),
+                                            InternalState::CONFIG_IEEE1_SET);
+            }
+            InternalState::CONFIG_IEEE1_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_2,
+                                            self.ieee_addr_byte(2),
+                                            InternalState::CONFIG_IEEE2_SET);
+            }
+            InternalState::CONFIG_IEEE2_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_3,
+                                            self.ieee_addr_byte(3),
+                                            InternalState::CONFIG_IEEE3_SET);
+            }
+            InternalState::CONFIG_IEEE3_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_4,
+                                            self.ieee_addr_byte(4),
+                                            InternalState::CONFIG_IEEE4_SET);
+            }
+            InternalState::CONFIG_IEEE4_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_5,
+                                            self.ieee_addr_byte(5),
+                                            InternalState::CONFIG_IEEE5_SET);
+            }
+            InternalState::CONFIG_IEEE5_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_6,
+                                            self.ieee_addr_byte(6),
+                                            InternalState::CONFIG_IEEE6_SET);
+            }
+            InternalState::CONFIG_IEEE6_SET => {
+                self.state_transition_write(RF233Register::IEEE_ADDR_7,
+                                            self.ieee_addr_byte(7),
+                                            InternalState::CONFIG_IEEE7_SET);
+            }
+            InternalState::CONFIG_IEEE7_SET => {
+                self.state_transition_write(RF233Register::SHORT_ADDR_0,
+                                            (self.addr.get() & 0xff) as u8,
+                                            InternalState::CONFIG_SHORT0_SET);
+            }
+            InternalState::ED_RSSI_READ => {
+                self.energy_pending.set(false);
+                if self.transmitting.get() {
+                    self.state_transition_read(RF233Register::TRX_STATUS,
+                                               InternalState::TX_STATUS_PRECHECK1);
+                } else {
+                    self.state_transition_read(RF233Register::TRX_STATUS, InternalState::READY);
+                }
+                let rssi = rssi_to_dbm(result & PHY_RSSI_MASK);
+                let clear = rssi < CCA_ED_THRESHOLD;
+                self.energy_client.get().map(|client| {
+                    client.energy_detect_done(rssi, clear, ReturnCode::SUCCESS);
+                });
             }
             InternalState::UNKNOWN => {}
         }
     }
 }
 
-impl<'a, S: spi::SpiMasterDevice + 'a> gpio::Client for RF233<'a, S> {
+impl<'a, S, A> gpio::Client for RF233<'a, S, A>
+    where S: spi::SpiMasterDevice + 'a,
+          A: time::Alarm + 'a
+{
     fn fired(&self, identifier: usize) {
         if identifier == INTERRUPT_ID {
+            self.irq_time.set(self.alarm.now());
             self.handle_interrupt();
         }
     }
 }
 
-impl<'a, S: spi::SpiMasterDevice + 'a> RF233<'a, S> {
+impl<'a, S, A> RF233<'a, S, A>
+    where S: spi::SpiMasterDevice + 'a,
+          A: time::Alarm + 'a
+{
     pub fn new(spi: &'a S,
+               alarm: &'a A,
                reset: &'a gpio::Pin,
                sleep: &'a gpio::Pin,
                irq: &'a gpio::Pin,
                ctl: &'a gpio::PinCtl)
-               -> RF233<'a, S> {
+               -> RF233<'a, S, A> {
         RF233 {
             spi: spi,
+            alarm: alarm,
             reset_pin: reset,
             sleep_pin: sleep,
             irq_pin: irq,
@@ -644,9 +900,20 @@ impl<'a, S: spi::SpiMasterDevice + 'a> RF233<'a, S> {
             tx_len: Cell::new(0),
             tx_client: Cell::new(None),
             rx_client: Cell::new(None),
+            energy_client: Cell::new(None),
+            power_client: Cell::new(None),
             addr: Cell::new(0),
             pan: Cell::new(0),
-            seq: Cell::new(0),
+            addr_long: Cell::new(IEEE_ADDR),
+            channel: Cell::new(DEFAULT_CHANNEL),
+            tx_power: Cell::new(DEFAULT_TX_POWER),
+            max_retries: Cell::new(DEFAULT_MAX_RETRIES),
+            trac_status: Cell::new(TRAC_SUCCESS),
+            config_pending: Cell::new(false),
+            energy_pending: Cell::new(false),
+            sleep_pending: Cell::new(false),
+            irq_time: Cell::new(0),
+            rx_timestamp: Cell::new(0),
             spi_rx: TakeCell::empty(),
             spi_tx: TakeCell::empty(),
             spi_buf: TakeCell::empty(),
@@ -721,6 +988,28 @@ impl<'a, S: spi::SpiMasterDevice + 'a> RF233<'a, S> {
     }
 
 
+    /// The PHY_TX_PWR setting for the current transmit power.
+    fn tx_power_register(&self) -> u8 {
+        let power = self.tx_power.get();
+        PHY_TX_PWR_TABLE.iter()
+            .find(|&&(dbm, _)| dbm <= power)
+            .map_or(PHY_TX_PWR, |&(_, reg)| reg)
+    }
+
+    /// Writes the channel, transmit power, retries and addresses to the
+    /// radio now if it is idle, or once it becomes idle or wakes up. Before
+    /// start() is called the start sequence writes them.
+    fn reconfigure(&self) {
+        let state = self.state.get();
+        if state == InternalState::READY && !self.spi_busy.get() {
+            self.state_transition_write(RF233Register::PHY_CC_CCA,
+                                        PHY_CC_CCA_MODE | self.channel.get(),
+                                        InternalState::CONFIG_CCA_SET);
+        } else if state != InternalState::START {
+            self.config_pending.set(true);
+        }
+    }
+
     fn state_transition_write(&self, reg: RF233Register, val: u8, state: InternalState) {
         self.state.set(state);
         self.register_write(reg, val);
@@ -731,30 +1020,29 @@ impl<'a, S: spi::SpiMasterDevice + 'a> RF233<'a, S> {
         self.register_read(reg);
     }
 
-    /// Generate the 802.15.4 header and set up the radio's state to
-    /// be able to send the packet (store reference, etc.).
-    // For details on frame format, the old CC2420 datasheet is a
-    // very good guide. -pal
-    fn prepare_packet(&self, buf: &'static mut [u8], len: u8, dest: u16) {
+    /// Set up the radio's state to be able to send the frame
+    /// (store reference, etc.). The MAC header is already in the
+    /// buffer; only the length field is filled in here.
+    fn prepare_packet(&self, buf: &'static mut [u8], frame_len: u8) {
         buf[0] = 0x00; // Where the frame command will go.
-        buf[1] = len + 2 - 1; // plus 2 for CRC, - 1 for length byte  1/6/17 PAL
-        buf[2] = 0x61; // 0x40: intra-PAN; 0x20: ack requested; 0x01: data frame
-        buf[3] = 0x88; // 0x80: 16-bit src addr; 0x08: 16-bit dest addr
-        buf[4] = self.seq.get();
-        buf[5] = (self.pan.get() & 0xFF) as u8; // PAN id is 16 bits
-        buf[6] = (self.pan.get() >> 8) as u8;
-        buf[7] = (dest & 0xff) as u8;
-        buf[8] = (dest >> 8) as u8;
-        buf[9] = (self.addr.get() & 0xFF) as u8;
-        buf[10] = (self.addr.get() >> 8) as u8;
-
-        self.seq.set(self.seq.get() + 1);
+        buf[1] = frame_len + 2; // plus 2 for CRC
+
         self.tx_buf.replace(buf);
-        self.tx_len.set(len);
+        // The SPI write covers the length byte and the frame
+        self.tx_len.set(frame_len + 1);
+    }
+
+    /// Byte `n` of the extended address, as in the IEEE_ADDR_n register
+    /// (least significant first).
+    fn ieee_addr_byte(&self, n: usize) -> u8 {
+        self.addr_long.get()[7 - n]
     }
 }
 
-impl<'a, S: spi::SpiMasterDevice + 'a> radio::Radio for RF233<'a, S> {
+impl<'a, S, A> radio::Radio for RF233<'a, S, A>
+    where S: spi::SpiMasterDevice + 'a,
+          A: time::Alarm + 'a
+{
     fn initialize(&self,
                   buf: &'static mut [u8],
                   reg_write: &'static mut [u8],
@@ -785,15 +1073,36 @@ impl<'a, S: spi::SpiMasterDevice + 'a> radio::Radio for RF233<'a, S> {
     }
 
     fn start(&self) -> ReturnCode {
-        if self.state.get() != InternalState::START {
-            return ReturnCode::FAIL;
+        let state = self.state.get();
+        if state == InternalState::START {
+            self.register_read(RF233Register::PART_NUM);
+        } else if self.sleep_pending.get() {
+            // Stopped but not asleep yet: stay on
+            self.sleep_pending.set(false);
+        } else if state == InternalState::SLEEP {
+            self.sleep_pin.clear();
+            self.state_transition_read(RF233Register::TRX_STATUS, InternalState::SLEEP_WAKING);
+        } else {
+            return ReturnCode::EALREADY;
         }
-        self.register_read(RF233Register::PART_NUM);
         ReturnCode::SUCCESS
     }
 
     fn stop(&self) -> ReturnCode {
-        ReturnCode::FAIL
+        let state = self.state.get();
+        if state == InternalState::START || state == InternalState::SLEEP ||
+           self.sleep_pending.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.radio_on.set(false);
+        self.sleep_pending.set(true);
+        // Otherwise the radio goes to sleep when it returns to READY
+        if state == InternalState::READY && !self.spi_busy.get() {
+            self.state_transition_write(RF233Register::TRX_STATE,
+                                        RF233TrxCmd::OFF as u8,
+                                        InternalState::SLEEP_TRX_OFF);
+        }
+        ReturnCode::SUCCESS
     }
 
     fn set_transmit_client(&self, client: &'static radio::TxClient) {
@@ -808,35 +1117,70 @@ impl<'a, S: spi::SpiMasterDevice + 'a> radio::Radio for RF233<'a, S> {
         self.rx_buf.replace(buffer);
     }
 
+    fn set_energy_client(&self, client: &'static radio::EnergyClient) {
+        self.energy_client.set(Some(client));
+    }
+
+    fn set_power_client(&self, client: &'static radio::PowerClient) {
+        self.power_client.set(Some(client));
+    }
+
 
     // Setting the address also sets the panx
     fn set_address(&self, addr: u16) -> ReturnCode {
         let state = self.state.get();
         // The start state will push addr into hardware on initialization;
-        // the ready state needs to do so immediately.
-        if state == InternalState::READY || state == InternalState::START {
+        // the ready state needs to do so immediately and the sleep state
+        // once it wakes.
+        if state == InternalState::READY || state == InternalState::START ||
+           state == InternalState::SLEEP {
             self.addr.set(addr);
             if state == InternalState::READY {
                 self.state_transition_write(RF233Register::SHORT_ADDR_0,
                                             (self.addr.get() & 0xff) as u8,
                                             InternalState::CONFIG_SHORT0_SET);
+            } else if state == InternalState::SLEEP {
+                self.config_pending.set(true);
             }
             ReturnCode::SUCCESS
         } else {
             ReturnCode::EBUSY
         }
     }
+
+    fn set_address_long(&self, addr: [u8; 8]) -> ReturnCode {
+        self.addr_long.set(addr);
+        self.reconfigure();
+        ReturnCode::SUCCESS
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
     // Setting the PAN also sets the address
     fn set_pan(&self, addr: u16) -> ReturnCode {
         let state = self.state.get();
         // The start state will push addr into hardware on initialization;
-        // the ready state needs to do so immediately.
-        if state == InternalState::READY || state == InternalState::START {
+        // the ready state needs to do so immediately and the sleep state
+        // once it wakes.
+        if state == InternalState::READY || state == InternalState::START ||
+           state == InternalState::SLEEP {
             self.pan.set(addr);
             if state == InternalState::READY {
                 self.state_transition_write(RF233Register::SHORT_ADDR_0,
                                             (self.addr.get() & 0xff) as u8,
                                             InternalState::CONFIG_SHORT0_SET);
+            } else if state == InternalState::SLEEP {
+                self.config_pending.set(true);
             }
             ReturnCode::SUCCESS
         } else {
@@ -844,33 +1188,75 @@ impl<'a, S: spi::SpiMasterDevice + 'a> radio::Radio for RF233<'a, S> {
         }
     }
 
-    // + 1 because we need space for the frame read/write byte for
-    // the SPI command. Otherwise, if the packet begins at byte 0, we
-    // have to copy it into a buffer whose byte 0 is the frame read/write
-    // command.
-    fn payload_offset(&self) -> u8 {
-        radio::HEADER_SIZE + 1
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan < radio::MIN_CHANNEL || chan > radio::MAX_CHANNEL {
+            return ReturnCode::ERANGE;
+        }
+        self.channel.set(chan);
+        self.reconfigure();
+        ReturnCode::SUCCESS
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        let weakest = PHY_TX_PWR_TABLE[PHY_TX_PWR_TABLE.len() - 1].0;
+        if power < weakest {
+            return ReturnCode::ERANGE;
+        }
+        // Stronger than the radio supports: use its maximum
+        let dbm = PHY_TX_PWR_TABLE.iter()
+            .find(|&&(dbm, _)| dbm <= power)
+            .map_or(weakest, |&(dbm, _)| dbm);
+        self.tx_power.set(dbm);
+        self.reconfigure();
+        ReturnCode::SUCCESS
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
     }
-    fn header_size(&self) -> u8 {
-        radio::HEADER_SIZE
+
+    fn set_max_retries(&self, retries: u8) -> ReturnCode {
+        if retries > radio::MAX_RETRIES {
+            return ReturnCode::ERANGE;
+        }
+        self.max_retries.set(retries);
+        self.reconfigure();
+        ReturnCode::SUCCESS
+    }
+
+    fn get_max_retries(&self) -> u8 {
+        self.max_retries.get()
+    }
+
+    // 2 because we need space for the frame read/write byte for
+    // the SPI command and the length byte. Otherwise, if the frame
+    // begins at byte 0, we have to copy it into a buffer whose byte 0
+    // is the frame read/write command.
+    fn psdu_offset(&self) -> u8 {
+        2
     }
 
     fn ready(&self) -> bool {
         self.radio_on.get() && self.state.get() == InternalState::READY
     }
 
-    fn transmit(&self, dest: u16, payload: &'static mut [u8], len: u8) -> ReturnCode {
+    fn transmit(&self, frame: &'static mut [u8], frame_len: u8) -> ReturnCode {
         let state = self.state.get();
         if !self.radio_on.get() {
             return ReturnCode::EOFF;
         } else if self.tx_buf.is_some() || self.transmitting.get() {
             return ReturnCode::EBUSY;
-        } else if (len + 2) as usize >= payload.len() {
-            // Not enough room for CRC
+        } else if frame_len as usize + 2 > frame.len() ||
+                  frame_len as usize + 2 > radio::MAX_FRAME_SIZE as usize {
+            // The frame with its CRC must fit in 127 bytes
             return ReturnCode::ESIZE;
         }
 
-        self.prepare_packet(payload, len, dest);
+        self.prepare_packet(frame, frame_len);
         self.transmitting.set(true);
         if !self.receiving.get() && state == InternalState::READY {
             self.state_transition_read(RF233Register::TRX_STATUS,
@@ -878,4 +1264,17 @@ impl<'a, S: spi::SpiMasterDevice + 'a> radio::Radio for RF233<'a, S> {
         }
         return ReturnCode::SUCCESS;
     }
+
+    // The RSSI register is read once the radio is idle; if a frame
+    // arrives first, the READY state reads it afterwards.
+    fn energy_detect(&self) -> ReturnCode {
+        if !self.radio_on.get() {
+            return ReturnCode::EOFF;
+        }
+        self.energy_pending.set(true);
+        if self.state.get() == InternalState::READY && !self.spi_busy.get() {
+            self.state_transition_read(RF233Register::PHY_RSSI, InternalState::ED_RSSI_READ);
+        }
+        ReturnCode::SUCCESS
+    }
 }
@@ -66,6 +66,8 @@ pub const PHY_CC_CCA_MODE_CS_OR_ED: u8 = 0 << 5;
 pub const PHY_CC_CCA_MODE_ED: u8 = 1 << 5;
 pub const PHY_CC_CCA_MODE_CS: u8 = 2 << 5;
 pub const PHY_CC_CCA_MODE_CS_AND_ED: u8 = 3 << 5;
+pub const PHY_CC_CCA_CHANNEL_MASK: u8 = 0x1f;
+pub const PHY_RSSI_MASK: u8 = 0x1f;
 pub const TRX_CTRL_2_RX_SAFE_MODE: u8 = 1 << 7;
 pub const TRX_CTRL_2_DATA_RATE_250: u8 = 0;
 pub const IRQ_TRXBUF_ACCESS_VIOLATION: u8 = 1 << 6;
@@ -80,11 +82,49 @@ pub const TRX_CTRL_1: u8 = (TRX_CTRL_1_DIG34_RXTX_INDICATOR | TRX_CTRL_1_SPI_CMD
 pub const TRX_CTRL_2: u8 = (TRX_CTRL_2_RX_SAFE_MODE | TRX_CTRL_2_DATA_RATE_250);
 pub const PHY_CC_CCA: u8 = 26 | PHY_CC_CCA_MODE_CS_OR_ED;
 pub const PHY_TX_PWR: u8 = PHY_TX_PWR_4;
+pub const PHY_CC_CCA_MODE: u8 = PHY_CC_CCA_MODE_CS_OR_ED;
+
+// Default channel and transmit power (dBm), matching PHY_CC_CCA and PHY_TX_PWR.
+pub const DEFAULT_CHANNEL: u8 = 26;
+pub const DEFAULT_TX_POWER: i8 = 4;
+
+// Received power in dBm for an RSSI or energy level of 0, and the largest
+// energy level the radio reports (datasheet, Section 8.3-8.4).
+pub const RSSI_BASE_VAL: i8 = -94;
+pub const ED_LEVEL_MAX: u8 = 84;
+// Energy above which the channel is busy: RSSI_BASE_VAL + 2 * CCA_ED_THRES,
+// with the reset value CCA_ED_THRES = 7.
+pub const CCA_ED_THRESHOLD: i8 = -80;
+
+// Transmit power in dBm for PHY_TX_PWR settings, from strongest to weakest
+// (RF233 datasheet, Table 9-1). Fractional powers are rounded down.
+pub const PHY_TX_PWR_TABLE: [(i8, u8); 13] = [(4, 0x0),
+                                              (3, 0x3),
+                                              (2, 0x5),
+                                              (1, 0x6),
+                                              (0, 0x7),
+                                              (-1, 0x8),
+                                              (-2, 0x9),
+                                              (-3, 0xA),
+                                              (-4, 0xB),
+                                              (-6, 0xC),
+                                              (-8, 0xD),
+                                              (-12, 0xE),
+                                              (-17, 0xF)];
 pub const IRQ_MASK: u8 = (IRQ_TRXBUF_ACCESS_VIOLATION | IRQ_TRX_DONE | IRQ_PLL_LOCK | IRQ_RX_START);
 pub const XAH_CTRL_1: u8 = XAH_CTRL_1_AACK_PROM_MODE;
-pub const XAH_CTRL_0: u8 = 0;
+pub const XAH_CTRL_0_MAX_FRAME_RETRIES_SHIFT: u8 = 4;
+pub const XAH_CTRL_0_MAX_CSMA_RETRIES: u8 = 4 << 1;
+pub const DEFAULT_MAX_RETRIES: u8 = 3;
 pub const TRX_RPC: u8 = 0xFF;
 
+// Outcome of a transmission in TX_ARET mode, in TRX_STATE bits 7-5.
+pub const TRX_STATE_TRAC_SHIFT: u8 = 5;
+pub const TRAC_SUCCESS: u8 = 0;
+pub const TRAC_SUCCESS_DATA_PENDING: u8 = 1;
+pub const TRAC_CHANNEL_ACCESS_FAILURE: u8 = 3;
+pub const TRAC_NO_ACK: u8 = 5;
+
 // Default address settings.
 pub const PAN_ID_0: u8 = 0x22;
 pub const PAN_ID_1: u8 = 0x22;
@@ -96,6 +136,15 @@ pub const IEEE_ADDR_4: u8 = 0x55;
 pub const IEEE_ADDR_5: u8 = 0x66;
 pub const IEEE_ADDR_6: u8 = 0x77;
 pub const IEEE_ADDR_7: u8 = 0x88;
+// Default extended address, most significant byte first
+pub const IEEE_ADDR: [u8; 8] = [IEEE_ADDR_7,
+                                IEEE_ADDR_6,
+                                IEEE_ADDR_5,
+                                IEEE_ADDR_4,
+                                IEEE_ADDR_3,
+                                IEEE_ADDR_2,
+                                IEEE_ADDR_1,
+                                IEEE_ADDR_0];
 pub const SHORT_ADDR_0: u8 = 0x11;
 pub const SHORT_ADDR_1: u8 = 0x22;
 
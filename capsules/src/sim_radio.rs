@@ -0,0 +1,836 @@
+//! Simulated 802.15.4 radios, so that radio capsules and MAC layers can be
+//! exercised without an RF233.
+//!
+//! A `SimMedium` connects any number of `SimRadio`s, each of which
+//! implements `hil::radio::Radio`. A frame sent by one radio reaches every
+//! other radio that is on, on the same channel and has a receive buffer,
+//! after its airtime at 250 kbit/s plus the medium's latency. Like the
+//! RF233 in promiscuous mode, radios receive every frame, but only
+//! acknowledge frames sent to their short or extended address (or to the
+//! broadcast address, which is never acknowledged) on their PAN. Frames
+//! that request an acknowledgement and do not get one are sent again up to
+//! the sender's maximum number of retries.
+//!
+//! The medium can lose and corrupt frames. Each copy of a frame (and each
+//! acknowledgement) is lost with the loss probability, and each copy that
+//! arrives has one bit flipped with the corruption probability, as if the
+//! FCS had missed it. Both are driven by a pseudo-random generator with a
+//! settable seed, so a run can be repeated. There are no collisions: frames
+//! in flight at the same time all arrive.
+//!
+//! The medium works with any `Alarm`. The host tests at the end of this file
+//! drive it with a `ManualAlarm` that only advances when told to.
+//!
+//! ```ignore
+//! let medium = static_init!(
+//!     SimMedium<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     SimMedium::new(sim_alarm),
+//!     160/8);
+//! sim_alarm.set_client(medium);
+//! medium.set_loss(10);
+//! medium.set_latency(500);
+//!
+//! let node = static_init!(
+//!     SimRadio<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     SimRadio::new(medium, 0x1001),
+//!     736/8);
+//! node.init();
+//! node.set_transmit_client(radio_capsule);
+//! node.set_receive_client(radio_capsule, &mut RADIO_RX_BUF);
+//! node.start();
+//! ```
+
+use core::cell::Cell;
+use ieee802154::{self, Header, MacAddress};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::returncode::ReturnCode;
+
+/// Offset of the MAC header in frame buffers, the same as the RF233's, so
+/// that buffers sized for it work unchanged
+const PSDU_OFFSET: u8 = 2;
+/// Microseconds to send a byte at 250 kbit/s
+const BYTE_TIME_US: u32 = 32;
+/// Preamble, start of frame delimiter, length and FCS bytes sent with each
+/// frame
+const PHY_OVERHEAD: u32 = 8;
+/// Signal strength lost between any two radios, in dB
+const PATH_LOSS: i8 = 50;
+/// Energy measured on a quiet channel, in dBm
+const NOISE_FLOOR: i8 = -94;
+/// Energy above which the channel is busy, in dBm
+const CCA_THRESHOLD: i8 = -80;
+const MIN_TX_POWER: i8 = -17;
+const MAX_TX_POWER: i8 = 4;
+const DEFAULT_TX_POWER: i8 = 4;
+const DEFAULT_CHANNEL: u8 = 26;
+const DEFAULT_PAN: u16 = 0xabcd;
+const DEFAULT_MAX_RETRIES: u8 = 3;
+
+pub struct SimMedium<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    radios: List<'a, SimRadio<'a, A>>,
+    loss: Cell<u8>,
+    corruption: Cell<u8>,
+    latency_us: Cell<u32>,
+    // xorshift32 state; never 0
+    random: Cell<u32>,
+}
+
+impl<'a, A: Alarm> SimMedium<'a, A> {
+    pub const fn new(alarm: &'a A) -> SimMedium<'a, A> {
+        SimMedium {
+            alarm: alarm,
+            radios: List::new(),
+            loss: Cell::new(0),
+            corruption: Cell::new(0),
+            latency_us: Cell::new(0),
+            random: Cell::new(0x2545f491),
+        }
+    }
+
+    /// Sets the percentage (0-100) of frames and acknowledgements lost.
+    pub fn set_loss(&self, percent: u8) -> ReturnCode {
+        if percent > 100 {
+            return ReturnCode::EINVAL;
+        }
+        self.loss.set(percent);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn get_loss(&self) -> u8 {
+        self.loss.get()
+    }
+
+    /// Sets the percentage (0-100) of received frames that are corrupted.
+    pub fn set_corruption(&self, percent: u8) -> ReturnCode {
+        if percent > 100 {
+            return ReturnCode::EINVAL;
+        }
+        self.corruption.set(percent);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn get_corruption(&self) -> u8 {
+        self.corruption.get()
+    }
+
+    /// Sets how long a frame takes to arrive, in microseconds, on top of
+    /// its airtime.
+    pub fn set_latency(&self, us: u32) {
+        self.latency_us.set(us);
+    }
+
+    pub fn get_latency(&self) -> u32 {
+        self.latency_us.get()
+    }
+
+    /// Restarts the sequence of losses and corruptions.
+    pub fn set_seed(&self, seed: u32) {
+        self.random.set(if seed == 0 { 1 } else { seed });
+    }
+
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    /// Returns true with probability `percent`/100.
+    fn chance(&self, percent: u8) -> bool {
+        percent > 0 && self.next_random() % 100 < percent as u32
+    }
+
+    /// When a frame of `frame_len` bytes sent now finishes arriving
+    fn arrival(&self, frame_len: u8) -> u32 {
+        let us = (frame_len as u32 + PHY_OVERHEAD) * BYTE_TIME_US + self.latency_us.get();
+        let tics = us as u64 * <A::Frequency>::frequency() as u64 / 1000000;
+        self.alarm.now().wrapping_add(tics as u32)
+    }
+
+    /// Arms the alarm for the earliest pending event.
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let mut next: Option<u32> = None;
+        for radio in self.radios.iter() {
+            let at = if radio.power_pending.get() || radio.energy_pending.get() {
+                Some(now)
+            } else if radio.tx_buf.is_some() {
+                let arrival = radio.tx_arrival.get();
+                Some(if self.due(arrival) { now } else { arrival })
+            } else {
+                None
+            };
+            at.map(|at| {
+                // Compare times relative to now, as the counter wraps
+                let earlier = next.map_or(true, |n| at.wrapping_sub(now) < n.wrapping_sub(now));
+                if earlier {
+                    next = Some(at);
+                }
+            });
+        }
+        match next {
+            // An alarm set to now might not fire until the counter wraps
+            Some(at) if at == now => self.alarm.set_alarm(now.wrapping_add(1)),
+            Some(at) => self.alarm.set_alarm(at),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Whether `at` is now or has passed. Events are never more than half
+    /// the counter's range in the future.
+    fn due(&self, at: u32) -> bool {
+        (self.alarm.now().wrapping_sub(at) as i32) >= 0
+    }
+
+    /// Strongest signal on `radio`'s channel from the other radios
+    fn channel_energy(&self, radio: &SimRadio<'a, A>) -> i8 {
+        self.radios
+            .iter()
+            .filter(|other| {
+                !other.is(radio) && other.channel.get() == radio.channel.get() &&
+                other.tx_buf.is_some()
+            })
+            .map(|other| other.tx_power.get() - PATH_LOSS)
+            .fold(NOISE_FLOOR, |max, rssi| if rssi > max { rssi } else { max })
+    }
+
+    /// Delivers `frame` from `sender` to the other radios, returning
+    /// whether its destination acknowledged it.
+    fn deliver(&self, sender: &SimRadio<'a, A>, frame: &[u8], header: Option<Header>) -> bool {
+        let offset = PSDU_OFFSET as usize;
+        let frame_len = frame.len() - offset;
+        let mut acked = false;
+        for radio in self.radios.iter() {
+            if radio.is(sender) || radio.state.get() != State::On ||
+               radio.channel.get() != sender.channel.get() {
+                continue;
+            }
+            if self.chance(self.loss.get()) {
+                continue;
+            }
+            let addressed = header.map_or(false, |header| radio.acknowledges(&header));
+            if addressed && !self.chance(self.loss.get()) {
+                acked = true;
+            }
+
+            let client = match radio.rx_client.get() {
+                Some(client) => client,
+                None => continue,
+            };
+            // Radios that are still holding the previous frame miss this one
+            let buf = match radio.rx_buf.take() {
+                Some(buf) => buf,
+                None => continue,
+            };
+            if buf.len() < frame.len() {
+                radio.rx_buf.replace(buf);
+                continue;
+            }
+            buf[..frame.len()].copy_from_slice(frame);
+            if self.chance(self.corruption.get()) {
+                let bit = self.next_random() as usize % (frame_len * 8);
+                buf[offset + bit / 8] ^= 1 << (bit % 8);
+            }
+            let rssi = sender.tx_power.get() - PATH_LOSS;
+            let info = radio::RxInfo {
+                rssi: rssi,
+                lqi: 255,
+                timestamp: self.alarm.now(),
+            };
+            client.receive(buf, frame_len as u8, info, ReturnCode::SUCCESS);
+        }
+        acked
+    }
+
+    fn finish_transmission(&self, radio: &SimRadio<'a, A>) {
+        let mut ack_requested = false;
+        let acked = radio.tx_buf.map_or(false, |buf| {
+            let frame = &buf[..PSDU_OFFSET as usize + radio.tx_len.get() as usize];
+            let header = Header::decode(&frame[PSDU_OFFSET as usize..]).map(|(header, _)| header);
+            ack_requested = header.map_or(false, |header| header.ack_requested);
+            self.deliver(radio, frame, header)
+        });
+        if ack_requested && !acked && radio.retries_left.get() > 0 {
+            radio.retries_left.set(radio.retries_left.get() - 1);
+            radio.tx_arrival.set(self.arrival(radio.tx_len.get()));
+            return;
+        }
+        let result = if ack_requested && !acked {
+            ReturnCode::ENOACK
+        } else {
+            ReturnCode::SUCCESS
+        };
+        radio.tx_buf.take().map(|buf| {
+            radio.tx_client.get().map(move |client| client.send_done(buf, result));
+        });
+    }
+}
+
+impl<'a, A: Alarm> time::Client for SimMedium<'a, A> {
+    fn fired(&self) {
+        for radio in self.radios.iter() {
+            if radio.power_pending.get() {
+                radio.finish_power_change();
+            }
+            if radio.energy_pending.get() {
+                radio.energy_pending.set(false);
+                let rssi = self.channel_energy(radio);
+                radio.energy_client
+                    .get()
+                    .map(|client| {
+                        client.energy_detect_done(rssi, rssi < CCA_THRESHOLD, ReturnCode::SUCCESS)
+                    });
+            }
+            if radio.tx_buf.is_some() && self.due(radio.tx_arrival.get()) {
+                self.finish_transmission(radio);
+                // A radio turning off waits for its frame to be sent
+                let state = radio.state.get();
+                if (state == State::TurningOff || state == State::TurningOn) &&
+                   radio.tx_buf.is_none() {
+                    radio.power_pending.set(true);
+                }
+            }
+        }
+        self.schedule();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Off,
+    TurningOn,
+    On,
+    TurningOff,
+}
+
+pub struct SimRadio<'a, A: Alarm + 'a> {
+    medium: &'a SimMedium<'a, A>,
+    next: ListLink<'a, SimRadio<'a, A>>,
+    tx_client: Cell<Option<&'static radio::TxClient>>,
+    rx_client: Cell<Option<&'static radio::RxClient>>,
+    energy_client: Cell<Option<&'static radio::EnergyClient>>,
+    power_client: Cell<Option<&'static radio::PowerClient>>,
+    state: Cell<State>,
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+    max_retries: Cell<u8>,
+    // The frame in flight, when it arrives and how many more times it may
+    // be sent
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<u8>,
+    tx_arrival: Cell<u32>,
+    retries_left: Cell<u8>,
+    rx_buf: TakeCell<'static, [u8]>,
+    power_pending: Cell<bool>,
+    energy_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm> SimRadio<'a, A> {
+    /// Creates a radio with the short address `addr`. Its extended address
+    /// is derived from `addr`, and it starts off.
+    pub const fn new(medium: &'a SimMedium<'a, A>, addr: u16) -> SimRadio<'a, A> {
+        SimRadio {
+            medium: medium,
+            next: ListLink::empty(),
+            tx_client: Cell::new(None),
+            rx_client: Cell::new(None),
+            energy_client: Cell::new(None),
+            power_client: Cell::new(None),
+            state: Cell::new(State::Off),
+            addr: Cell::new(addr),
+            addr_long: Cell::new([0x02, 0, 0, 0xff, 0xfe, 0, (addr >> 8) as u8, addr as u8]),
+            pan: Cell::new(DEFAULT_PAN),
+            channel: Cell::new(DEFAULT_CHANNEL),
+            tx_power: Cell::new(DEFAULT_TX_POWER),
+            max_retries: Cell::new(DEFAULT_MAX_RETRIES),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_arrival: Cell::new(0),
+            retries_left: Cell::new(0),
+            rx_buf: TakeCell::empty(),
+            power_pending: Cell::new(false),
+            energy_pending: Cell::new(false),
+        }
+    }
+
+    /// Connects the radio to its medium.
+    pub fn init(&'a self) {
+        self.medium.radios.push_head(self);
+    }
+
+    fn is(&self, other: &SimRadio<'a, A>) -> bool {
+        self as *const SimRadio<'a, A> == other as *const SimRadio<'a, A>
+    }
+
+    /// Whether this radio acknowledges a frame with `header`
+    fn acknowledges(&self, header: &Header) -> bool {
+        let pan_matches = header.dst_pan.map_or(false, |pan| pan == self.pan.get());
+        let addr_matches = match header.dst_addr {
+            Some(MacAddress::Short(addr)) => addr == self.addr.get(),
+            Some(MacAddress::Long(addr)) => addr == self.addr_long.get(),
+            None => false,
+        };
+        header.ack_requested && pan_matches && addr_matches &&
+        header.dst_addr != Some(MacAddress::Short(ieee802154::BROADCAST_ADDR))
+    }
+
+    fn finish_power_change(&self) {
+        self.power_pending.set(false);
+        let on = match self.state.get() {
+            State::TurningOn => true,
+            State::TurningOff => false,
+            _ => return,
+        };
+        self.state.set(if on { State::On } else { State::Off });
+        self.power_client.get().map(|client| client.changed(on));
+    }
+}
+
+impl<'a, A: Alarm> ListNode<'a, SimRadio<'a, A>> for SimRadio<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, SimRadio<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: Alarm> radio::Radio for SimRadio<'a, A> {
+    // There is no hardware to set up
+    fn initialize(&self,
+                  _spi_buf: &'static mut [u8],
+                  _reg_write: &'static mut [u8],
+                  _reg_read: &'static mut [u8])
+                  -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn start(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Off => {
+                self.state.set(State::TurningOn);
+                self.power_pending.set(true);
+                self.medium.schedule();
+                ReturnCode::SUCCESS
+            }
+            State::TurningOff => {
+                // Not off yet: stay on
+                self.state.set(State::TurningOn);
+                ReturnCode::SUCCESS
+            }
+            State::TurningOn | State::On => ReturnCode::EALREADY,
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        match self.state.get() {
+            State::On => {
+                self.state.set(State::TurningOff);
+                if self.tx_buf.is_none() {
+                    self.power_pending.set(true);
+                    self.medium.schedule();
+                }
+                ReturnCode::SUCCESS
+            }
+            State::TurningOn => {
+                self.state.set(State::TurningOff);
+                ReturnCode::SUCCESS
+            }
+            State::Off | State::TurningOff => ReturnCode::EALREADY,
+        }
+    }
+
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn ready(&self) -> bool {
+        self.state.get() == State::On
+    }
+
+    fn set_transmit_client(&self, client: &'static radio::TxClient) {
+        self.tx_client.set(Some(client));
+    }
+
+    fn set_receive_client(&self, client: &'static radio::RxClient, buffer: &'static mut [u8]) {
+        self.rx_client.set(Some(client));
+        self.rx_buf.replace(buffer);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.rx_buf.replace(buffer);
+    }
+
+    fn set_energy_client(&self, client: &'static radio::EnergyClient) {
+        self.energy_client.set(Some(client));
+    }
+
+    fn set_power_client(&self, client: &'static radio::PowerClient) {
+        self.power_client.set(Some(client));
+    }
+
+    fn set_address(&self, addr: u16) -> ReturnCode {
+        self.addr.set(addr);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) -> ReturnCode {
+        self.addr_long.set(addr);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_pan(&self, pan: u16) -> ReturnCode {
+        self.pan.set(pan);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan < radio::MIN_CHANNEL || chan > radio::MAX_CHANNEL {
+            return ReturnCode::ERANGE;
+        }
+        self.channel.set(chan);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        if power < MIN_TX_POWER {
+            return ReturnCode::ERANGE;
+        }
+        self.tx_power.set(if power > MAX_TX_POWER { MAX_TX_POWER } else { power });
+        ReturnCode::SUCCESS
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn set_max_retries(&self, retries: u8) -> ReturnCode {
+        if retries > radio::MAX_RETRIES {
+            return ReturnCode::ERANGE;
+        }
+        self.max_retries.set(retries);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_max_retries(&self) -> u8 {
+        self.max_retries.get()
+    }
+
+    fn psdu_offset(&self) -> u8 {
+        PSDU_OFFSET
+    }
+
+    fn transmit(&self, frame: &'static mut [u8], frame_len: u8) -> ReturnCode {
+        if self.state.get() != State::On {
+            return ReturnCode::EOFF;
+        } else if self.tx_buf.is_some() {
+            return ReturnCode::EBUSY;
+        } else if frame_len == 0 ||
+                  PSDU_OFFSET as usize + frame_len as usize > frame.len() ||
+                  frame_len as usize + 2 > radio::MAX_FRAME_SIZE as usize {
+            // The frame with its CRC must fit in 127 bytes
+            return ReturnCode::ESIZE;
+        }
+        self.tx_buf.replace(frame);
+        self.tx_len.set(frame_len);
+        self.tx_arrival.set(self.medium.arrival(frame_len));
+        self.retries_left.set(self.max_retries.get());
+        self.medium.schedule();
+        ReturnCode::SUCCESS
+    }
+
+    fn energy_detect(&self) -> ReturnCode {
+        if self.state.get() != State::On {
+            return ReturnCode::EOFF;
+        }
+        self.energy_pending.set(true);
+        self.medium.schedule();
+        ReturnCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use ieee802154::{FrameType, FrameVersion};
+    use kernel::hil::radio::Radio;
+    use kernel::hil::time::Time;
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use super::*;
+    use virtual_alarm::tests::ManualAlarm;
+
+    type Node = SimRadio<'static, ManualAlarm<'static>>;
+
+    const PAYLOAD: &'static [u8] = b"hello";
+    const SENDER: u16 = 0x0001;
+    const MISSING: u16 = 0x0009;
+
+    fn leak<T>(value: T) -> &'static T {
+        unsafe { &*Box::into_raw(Box::new(value)) }
+    }
+
+    fn buffer() -> &'static mut [u8] {
+        unsafe { &mut *Box::into_raw(vec![0; radio::MAX_BUF_SIZE].into_boxed_slice()) }
+    }
+
+    /// Keeps what a radio received and the results of its transmissions.
+    struct Client {
+        radio: &'static Node,
+        received: RefCell<Vec<(Vec<u8>, radio::RxInfo)>>,
+        sent: RefCell<Vec<ReturnCode>>,
+        tx_buf: TakeCell<'static, [u8]>,
+    }
+
+    impl radio::RxClient for Client {
+        fn receive(&self, buf: &'static mut [u8], len: u8, info: radio::RxInfo, _: ReturnCode) {
+            let offset = PSDU_OFFSET as usize;
+            self.received.borrow_mut().push((buf[offset..offset + len as usize].to_vec(), info));
+            self.radio.set_receive_buffer(buf);
+        }
+    }
+
+    impl radio::TxClient for Client {
+        fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
+            self.tx_buf.replace(buf);
+            self.sent.borrow_mut().push(result);
+        }
+    }
+
+    impl Client {
+        /// Sends `PAYLOAD` to `dst`, with an acknowledgement requested
+        /// unless `dst` is the broadcast address. Returns the frame.
+        fn send(&self, dst: u16) -> Vec<u8> {
+            let header = Header {
+                frame_type: FrameType::Data,
+                frame_pending: false,
+                ack_requested: dst != ieee802154::BROADCAST_ADDR,
+                version: FrameVersion::V2006,
+                seq: self.sent.borrow().len() as u8,
+                dst_pan: Some(DEFAULT_PAN),
+                dst_addr: Some(MacAddress::Short(dst)),
+                src_pan: Some(DEFAULT_PAN),
+                src_addr: Some(MacAddress::Short(self.radio.get_address())),
+            };
+            let buf = self.tx_buf.take().unwrap();
+            let offset = PSDU_OFFSET as usize;
+            let len = header.encode(&mut buf[offset..]).unwrap();
+            buf[offset + len..offset + len + PAYLOAD.len()].copy_from_slice(PAYLOAD);
+            let frame = buf[offset..offset + len + PAYLOAD.len()].to_vec();
+            assert_eq!(self.radio.transmit(buf, frame.len() as u8), ReturnCode::SUCCESS);
+            frame
+        }
+
+        fn copies(&self) -> usize {
+            self.received.borrow().len()
+        }
+    }
+
+    /// A medium with `count` radios that are on, with addresses 1, 2, ...
+    fn network(count: u16) -> (&'static ManualAlarm<'static>,
+                               &'static SimMedium<'static, ManualAlarm<'static>>,
+                               Vec<&'static Client>) {
+        let alarm = leak(ManualAlarm::new());
+        let medium = leak(SimMedium::new(alarm));
+        alarm.set_client(medium);
+        let clients: Vec<_> = (1..count + 1)
+            .map(|addr| {
+                let radio = leak(SimRadio::new(medium, addr));
+                let client = leak(Client {
+                    radio: radio,
+                    received: RefCell::new(Vec::new()),
+                    sent: RefCell::new(Vec::new()),
+                    tx_buf: TakeCell::new(buffer()),
+                });
+                radio.init();
+                radio.set_transmit_client(client);
+                radio.set_receive_client(client, buffer());
+                assert_eq!(radio.start(), ReturnCode::SUCCESS);
+                client
+            })
+            .collect();
+        alarm.advance(1);
+        assert!(clients.iter().all(|client| client.radio.ready()));
+        (alarm, medium, clients)
+    }
+
+    /// Lets every frame in flight arrive.
+    fn run(alarm: &ManualAlarm) {
+        while alarm.is_armed() {
+            alarm.advance(alarm.get_alarm().wrapping_sub(alarm.now()));
+        }
+    }
+
+    /// Microseconds a frame of `len` bytes takes to arrive
+    fn airtime(len: usize) -> u32 {
+        (len as u32 + PHY_OVERHEAD) * BYTE_TIME_US
+    }
+
+    #[test]
+    fn unicast_is_acknowledged() {
+        let (alarm, _, clients) = network(3);
+        let frame = clients[0].send(0x0002);
+        run(alarm);
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::SUCCESS]);
+        // The other radios are promiscuous, and the sender does not hear
+        // itself
+        assert_eq!(clients[0].copies(), 0);
+        for client in clients[1..].iter() {
+            assert_eq!(client.copies(), 1);
+            assert_eq!(client.received.borrow()[0].0, frame);
+        }
+    }
+
+    #[test]
+    fn missing_node_is_retried() {
+        let (alarm, _, clients) = network(3);
+        clients[0].send(MISSING);
+        run(alarm);
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::ENOACK]);
+        // Sent once and retried DEFAULT_MAX_RETRIES times
+        for client in clients[1..].iter() {
+            assert_eq!(client.copies(), 1 + DEFAULT_MAX_RETRIES as usize);
+        }
+    }
+
+    #[test]
+    fn broadcast_is_not_acknowledged() {
+        let (alarm, _, clients) = network(4);
+        clients[0].send(ieee802154::BROADCAST_ADDR);
+        run(alarm);
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::SUCCESS]);
+        assert!(clients[1..].iter().all(|client| client.copies() == 1));
+    }
+
+    #[test]
+    fn latency() {
+        let (alarm, medium, clients) = network(2);
+        medium.set_latency(1000);
+        let start = alarm.now();
+        let frame = clients[0].send(0x0002);
+        let arrival = airtime(frame.len()) + 1000;
+        alarm.advance(arrival - 1);
+        assert_eq!(clients[1].copies(), 0);
+        assert!(clients[0].sent.borrow().is_empty());
+
+        alarm.advance(1);
+        assert_eq!(clients[1].copies(), 1);
+        assert_eq!(clients[1].received.borrow()[0].1.timestamp, start + arrival);
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::SUCCESS]);
+    }
+
+    #[test]
+    fn total_loss() {
+        let (alarm, medium, clients) = network(3);
+        assert_eq!(medium.set_loss(101), ReturnCode::EINVAL);
+        assert_eq!(medium.set_loss(100), ReturnCode::SUCCESS);
+        clients[0].send(0x0002);
+        clients[1].send(ieee802154::BROADCAST_ADDR);
+        run(alarm);
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::ENOACK]);
+        assert_eq!(*clients[1].sent.borrow(), [ReturnCode::SUCCESS]);
+        assert!(clients.iter().all(|client| client.copies() == 0));
+    }
+
+    /// Sends `count` broadcasts from the first of four radios and returns
+    /// how many copies each of the others received.
+    fn lossy_broadcasts(seed: u32, count: usize) -> Vec<usize> {
+        let (alarm, medium, clients) = network(4);
+        medium.set_seed(seed);
+        medium.set_loss(25);
+        for _ in 0..count {
+            clients[0].send(ieee802154::BROADCAST_ADDR);
+            run(alarm);
+        }
+        clients[1..].iter().map(|client| client.copies()).collect()
+    }
+
+    #[test]
+    fn partial_loss() {
+        let copies = lossy_broadcasts(1, 400);
+        // Each copy is lost independently, with probability 1/4
+        for &received in copies.iter() {
+            assert!(received > 260 && received < 340, "received {}", received);
+        }
+        assert!(copies.iter().any(|&received| received != copies[0]));
+        // The same seed loses the same copies
+        assert_eq!(lossy_broadcasts(1, 400), copies);
+        assert!(lossy_broadcasts(2, 400) != copies);
+    }
+
+    #[test]
+    fn corruption() {
+        let (alarm, medium, clients) = network(3);
+        assert_eq!(medium.set_corruption(101), ReturnCode::EINVAL);
+        medium.set_corruption(100);
+        let frame = clients[0].send(0x0002);
+        run(alarm);
+        // Acknowledgements are not corrupted, and every copy has exactly
+        // one bit flipped
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::SUCCESS]);
+        for client in clients[1..].iter() {
+            let received = &client.received.borrow()[0].0;
+            let flipped: u32 = received.iter()
+                .zip(frame.iter())
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            assert_eq!(flipped, 1);
+        }
+    }
+
+    #[test]
+    fn other_channels_and_radios_that_are_off() {
+        let (alarm, _, clients) = network(4);
+        assert_eq!(clients[2].radio.set_channel(11), ReturnCode::SUCCESS);
+        assert_eq!(clients[3].radio.stop(), ReturnCode::SUCCESS);
+        alarm.advance(1);
+        assert!(!clients[3].radio.ready());
+
+        clients[0].send(ieee802154::BROADCAST_ADDR);
+        run(alarm);
+        assert_eq!(clients[1].copies(), 1);
+        assert_eq!(clients[2].copies(), 0);
+        assert_eq!(clients[3].copies(), 0);
+        assert_eq!(clients[3].radio.transmit(buffer(), 10), ReturnCode::EOFF);
+    }
+
+    #[test]
+    fn frames_in_flight_at_once() {
+        let (alarm, medium, clients) = network(3);
+        medium.set_latency(500);
+        let frame = clients[0].send(0x0002);
+        alarm.advance(100);
+        clients[1].send(SENDER);
+        assert_eq!(clients[0].radio.transmit(buffer(), frame.len() as u8),
+                   ReturnCode::EBUSY);
+        run(alarm);
+        // There are no collisions
+        assert_eq!(*clients[0].sent.borrow(), [ReturnCode::SUCCESS]);
+        assert_eq!(*clients[1].sent.borrow(), [ReturnCode::SUCCESS]);
+        assert_eq!(clients[0].copies(), 1);
+        assert_eq!(clients[1].copies(), 1);
+        assert_eq!(clients[2].copies(), 2);
+    }
+}
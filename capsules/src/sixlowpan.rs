@@ -0,0 +1,1212 @@
+//! 6LoWPAN adaptation layer: carries IPv6 datagrams in 802.15.4 frames.
+//!
+//! IPv6 headers, and the UDP headers that follow them, are compressed with
+//! IPHC (RFC 6282). Datagrams that do not fit in one frame are split into
+//! fragments and reassembled on the other side (RFC 4944). Compression is
+//! stateless: link-local addresses are elided or shortened when they are
+//! derived from MAC addresses, and multicast addresses are shortened, but
+//! no contexts are used and packets that refer to one are dropped. The UDP
+//! checksum is always carried inline.
+//!
+//! `compress`, `decompress` and `FragHeader` do not depend on a radio, so
+//! captured packets are checked by the host tests at the end of this file.
+//! `Sixlowpan` uses them to send and receive datagrams
+//! over a radio. It sends one datagram at a time and reassembles one
+//! fragmented datagram at a time; a reassembly is abandoned if its remaining
+//! fragments do not arrive within `REASSEMBLY_TIMEOUT_S` seconds.
+//!
+//! ```ignore
+//! let sixlowpan = static_init!(
+//!     Sixlowpan<'static, RF233<'static, ...>, VirtualMuxAlarm<'static, Ast>>,
+//!     Sixlowpan::new(rf233, sixlowpan_alarm, &mut FRAME_BUF, &mut PACKET_BUF,
+//!                    &mut REASSEMBLY_BUF),
+//!     992/8);
+//! sixlowpan_alarm.set_client(sixlowpan);
+//! rf233.set_transmit_client(sixlowpan);
+//! rf233.set_receive_client(sixlowpan, &mut RF233_RX_BUF);
+//! sixlowpan.set_client(udp);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use ieee802154::{self, FrameType, FrameVersion, Header, MacAddress};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::returncode::ReturnCode;
+
+pub const IP6_HEADER_SIZE: usize = 40;
+pub const UDP_HEADER_SIZE: usize = 8;
+/// IPv6 next header value for UDP
+pub const IP6_NH_UDP: u8 = 17;
+/// Largest datagram `Sixlowpan` sends or reassembles: the IPv6 minimum MTU.
+pub const MAX_DATAGRAM_SIZE: usize = 1280;
+/// Largest datagram that can arrive in a single frame, once decompressed.
+/// The packet buffer passed to `Sixlowpan::new` must be this large.
+pub const MAX_PACKET_SIZE: usize = ieee802154::MAX_FRAME_SIZE + IP6_HEADER_SIZE +
+                                   UDP_HEADER_SIZE;
+/// How long to wait for the rest of a fragmented datagram
+pub const REASSEMBLY_TIMEOUT_S: u32 = 60;
+
+// Dispatch values (RFC 4944 and RFC 6282)
+const DISPATCH_IP6: u8 = 0x41;
+const DISPATCH_IPHC: u8 = 0x60;
+const DISPATCH_IPHC_MASK: u8 = 0xe0;
+const DISPATCH_FRAG1: u8 = 0xc0;
+const DISPATCH_FRAGN: u8 = 0xe0;
+const DISPATCH_FRAG_MASK: u8 = 0xf8;
+const FRAG1_HEADER_SIZE: usize = 4;
+const FRAGN_HEADER_SIZE: usize = 5;
+
+// IPHC header bits. The first byte holds TF (bits 3-4), NH and HLIM (bits
+// 0-1); the second CID, SAC, SAM (bits 4-5), M, DAC and DAM (bits 0-1).
+const IPHC_TF_SHIFT: u8 = 3;
+const IPHC_NH: u8 = 1 << 2;
+const IPHC_HLIM_MASK: u8 = 0x3;
+const IPHC_CID: u8 = 1 << 7;
+const IPHC_SAC: u8 = 1 << 6;
+const IPHC_SAM_SHIFT: u8 = 4;
+const IPHC_M: u8 = 1 << 3;
+const IPHC_DAC: u8 = 1 << 2;
+const IPHC_DAM_MASK: u8 = 0x3;
+
+// Bytes carried inline for each TF, SAM/DAM and multicast DAM value
+const TF_SIZE: [usize; 4] = [4, 3, 1, 0];
+const UNICAST_SIZE: [usize; 4] = [16, 8, 2, 0];
+const MULTICAST_SIZE: [usize; 4] = [16, 6, 4, 1];
+// Hop limits that HLIM values 1-3 stand for
+const HOP_LIMITS: [u8; 4] = [0, 1, 64, 255];
+
+// UDP next header compression: 11110CPP
+const NHC_UDP: u8 = 0xf0;
+const NHC_UDP_MASK: u8 = 0xf8;
+const NHC_UDP_CHECKSUM: u8 = 1 << 2;
+const NHC_UDP_PORTS_MASK: u8 = 0x3;
+const UDP_PORTS_SIZE: [usize; 4] = [4, 3, 3, 1];
+
+const LINK_LOCAL_PREFIX: [u8; 8] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0];
+// Interface IDs of addresses derived from a short address start with this
+const SHORT_IID_PREFIX: [u8; 6] = [0, 0, 0, 0xff, 0xfe, 0];
+
+// One bit for each 8-byte block of the datagram being reassembled
+const BLOCK_MAP_SIZE: usize = MAX_DATAGRAM_SIZE / 64;
+
+fn read_be16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) << 8 | buf[offset + 1] as u16
+}
+
+fn write_be16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = (value >> 8) as u8;
+    buf[offset + 1] = value as u8;
+}
+
+/// The interface ID a node with this MAC address uses in its link-local
+/// address (RFC 4944 section 6, RFC 6282 section 3.2.2).
+fn interface_id(mac: &MacAddress) -> [u8; 8] {
+    match *mac {
+        MacAddress::Short(addr) => [0, 0, 0, 0xff, 0xfe, 0, (addr >> 8) as u8, addr as u8],
+        MacAddress::Long(addr) => {
+            // Invert the universal/local bit
+            let mut iid = addr;
+            iid[0] ^= 0x02;
+            iid
+        }
+    }
+}
+
+/// The link-local IPv6 address (fe80::/64) of a node with this MAC address.
+pub fn link_local_address(mac: &MacAddress) -> [u8; 16] {
+    let mut addr = [0; 16];
+    addr[..8].copy_from_slice(&LINK_LOCAL_PREFIX);
+    addr[8..].copy_from_slice(&interface_id(mac));
+    addr
+}
+
+/// The MAC address of the node with this link-local IPv6 address, or `None`
+/// if the address is not link-local.
+pub fn link_local_mac(addr: &[u8]) -> Option<MacAddress> {
+    if addr.len() != 16 || addr[..8] != LINK_LOCAL_PREFIX[..] {
+        return None;
+    }
+    if addr[8..14] == SHORT_IID_PREFIX[..] {
+        Some(MacAddress::Short(read_be16(addr, 14)))
+    } else {
+        let mut mac = [0; 8];
+        mac.copy_from_slice(&addr[8..]);
+        mac[0] ^= 0x02;
+        Some(MacAddress::Long(mac))
+    }
+}
+
+/// Writes the inline part of a compressed unicast address to `out` and
+/// returns the SAM/DAM value and the number of bytes written.
+fn compress_unicast(addr: &[u8], mac: &MacAddress, out: &mut [u8]) -> (u8, usize) {
+    if addr[..8] != LINK_LOCAL_PREFIX[..] {
+        out[..16].copy_from_slice(addr);
+        (0, 16)
+    } else if addr[8..] == interface_id(mac)[..] {
+        (3, 0)
+    } else if addr[8..14] == SHORT_IID_PREFIX[..] {
+        out[..2].copy_from_slice(&addr[14..]);
+        (2, 2)
+    } else {
+        out[..8].copy_from_slice(&addr[8..]);
+        (1, 8)
+    }
+}
+
+/// Like `compress_unicast`, for a multicast (ff00::/8) address.
+fn compress_multicast(addr: &[u8], out: &mut [u8]) -> (u8, usize) {
+    if addr[1] == 0x02 && addr[2..15].iter().all(|b| *b == 0) {
+        // ff02::00XX
+        out[0] = addr[15];
+        (3, 1)
+    } else if addr[2..13].iter().all(|b| *b == 0) {
+        // ffXX::00XX:XXXX
+        out[0] = addr[1];
+        out[1..4].copy_from_slice(&addr[13..]);
+        (2, 4)
+    } else if addr[2..11].iter().all(|b| *b == 0) {
+        // ffXX::00XX:XXXX:XXXX
+        out[0] = addr[1];
+        out[1..6].copy_from_slice(&addr[11..]);
+        (1, 6)
+    } else {
+        out[..16].copy_from_slice(addr);
+        (0, 16)
+    }
+}
+
+/// Rebuilds a unicast address from its SAM/DAM value and inline bytes.
+fn decompress_unicast(inline: &[u8], mode: u8, mac: &MacAddress, addr: &mut [u8]) {
+    if mode == 0 {
+        addr.copy_from_slice(inline);
+        return;
+    }
+    addr[..8].copy_from_slice(&LINK_LOCAL_PREFIX);
+    match mode {
+        1 => addr[8..].copy_from_slice(inline),
+        2 => {
+            addr[8..14].copy_from_slice(&SHORT_IID_PREFIX);
+            addr[14..].copy_from_slice(inline);
+        }
+        _ => addr[8..].copy_from_slice(&interface_id(mac)),
+    }
+}
+
+/// Rebuilds a multicast address from its DAM value and inline bytes.
+fn decompress_multicast(inline: &[u8], mode: u8, addr: &mut [u8]) {
+    for byte in addr.iter_mut() {
+        *byte = 0;
+    }
+    match mode {
+        0 => addr.copy_from_slice(inline),
+        1 => {
+            addr[0] = 0xff;
+            addr[1] = inline[0];
+            addr[11..].copy_from_slice(&inline[1..]);
+        }
+        2 => {
+            addr[0] = 0xff;
+            addr[1] = inline[0];
+            addr[13..].copy_from_slice(&inline[1..]);
+        }
+        _ => {
+            addr[0] = 0xff;
+            addr[1] = 0x02;
+            addr[15] = inline[0];
+        }
+    }
+}
+
+/// Compresses the IPv6 header at the start of `ip6`, and the UDP header
+/// after it if there is one, into an IPHC header in `buf`. `src_mac` and
+/// `dst_mac` are the MAC addresses of the frame that will carry the packet.
+/// Returns the size of the IPHC header and the number of bytes of `ip6` it
+/// replaces, or `None` if `ip6` is not an IPv6 packet or `buf` is too short.
+pub fn compress(ip6: &[u8],
+                src_mac: &MacAddress,
+                dst_mac: &MacAddress,
+                buf: &mut [u8])
+                -> Option<(usize, usize)> {
+    if ip6.len() < IP6_HEADER_SIZE || ip6[0] >> 4 != 6 {
+        return None;
+    }
+    let mut out = [0; IP6_HEADER_SIZE + UDP_HEADER_SIZE];
+    let mut pos = 2;
+    let mut iphc0 = DISPATCH_IPHC;
+    let mut iphc1 = 0;
+
+    // Traffic class and flow label. Inline, the traffic class is written
+    // ECN first, then DSCP.
+    let tc = ip6[0] << 4 | ip6[1] >> 4;
+    let ecn_dscp = tc << 6 | tc >> 2;
+    let flow = (ip6[1] as u32 & 0xf) << 16 | (ip6[2] as u32) << 8 | ip6[3] as u32;
+    let tf = if tc == 0 && flow == 0 {
+        3
+    } else if flow == 0 {
+        out[pos] = ecn_dscp;
+        2
+    } else if tc >> 2 == 0 {
+        out[pos] = tc << 6 | (flow >> 16) as u8;
+        out[pos + 1] = (flow >> 8) as u8;
+        out[pos + 2] = flow as u8;
+        1
+    } else {
+        out[pos] = ecn_dscp;
+        out[pos + 1] = (flow >> 16) as u8;
+        out[pos + 2] = (flow >> 8) as u8;
+        out[pos + 3] = flow as u8;
+        0
+    };
+    pos += TF_SIZE[tf as usize];
+    iphc0 |= tf << IPHC_TF_SHIFT;
+
+    let udp = ip6[6] == IP6_NH_UDP && ip6.len() >= IP6_HEADER_SIZE + UDP_HEADER_SIZE;
+    if udp {
+        iphc0 |= IPHC_NH;
+    } else {
+        out[pos] = ip6[6];
+        pos += 1;
+    }
+
+    match HOP_LIMITS.iter().skip(1).position(|hlim| *hlim == ip6[7]) {
+        Some(i) => iphc0 |= i as u8 + 1,
+        None => {
+            out[pos] = ip6[7];
+            pos += 1;
+        }
+    }
+
+    if ip6[8..24].iter().all(|b| *b == 0) {
+        // The unspecified address
+        iphc1 |= IPHC_SAC;
+    } else {
+        let (sam, len) = compress_unicast(&ip6[8..24], src_mac, &mut out[pos..]);
+        iphc1 |= sam << IPHC_SAM_SHIFT;
+        pos += len;
+    }
+
+    if ip6[24] == 0xff {
+        let (dam, len) = compress_multicast(&ip6[24..40], &mut out[pos..]);
+        iphc1 |= IPHC_M | dam;
+        pos += len;
+    } else {
+        let (dam, len) = compress_unicast(&ip6[24..40], dst_mac, &mut out[pos..]);
+        iphc1 |= dam;
+        pos += len;
+    }
+
+    if udp {
+        let src_port = read_be16(ip6, IP6_HEADER_SIZE);
+        let dst_port = read_be16(ip6, IP6_HEADER_SIZE + 2);
+        let nhc = pos;
+        pos += 1;
+        let ports = if src_port & 0xfff0 == 0xf0b0 && dst_port & 0xfff0 == 0xf0b0 {
+            out[pos] = ((src_port & 0xf) << 4 | dst_port & 0xf) as u8;
+            3
+        } else if dst_port & 0xff00 == 0xf000 {
+            write_be16(&mut out, pos, src_port);
+            out[pos + 2] = dst_port as u8;
+            1
+        } else if src_port & 0xff00 == 0xf000 {
+            out[pos] = src_port as u8;
+            write_be16(&mut out, pos + 1, dst_port);
+            2
+        } else {
+            write_be16(&mut out, pos, src_port);
+            write_be16(&mut out, pos + 2, dst_port);
+            0
+        };
+        out[nhc] = NHC_UDP | ports;
+        pos += UDP_PORTS_SIZE[ports as usize];
+        // Checksum
+        out[pos] = ip6[IP6_HEADER_SIZE + 6];
+        out[pos + 1] = ip6[IP6_HEADER_SIZE + 7];
+        pos += 2;
+    }
+
+    if buf.len() < pos {
+        return None;
+    }
+    out[0] = iphc0;
+    out[1] = iphc1;
+    buf[..pos].copy_from_slice(&out[..pos]);
+    let replaced = if udp {
+        IP6_HEADER_SIZE + UDP_HEADER_SIZE
+    } else {
+        IP6_HEADER_SIZE
+    };
+    Some((pos, replaced))
+}
+
+/// Decompresses the IPHC header at the start of `buf` into an IPv6 header,
+/// followed by a UDP header if one was compressed, at the start of `ip6`.
+/// `src_mac` and `dst_mac` are the MAC addresses of the frame that carried
+/// the packet. `datagram_size` is the size of the whole datagram if a
+/// fragment header gave it; otherwise the datagram ends with `buf`. Returns
+/// the size of the IPHC header and the number of bytes written to `ip6`, or
+/// `None` if the header is malformed, refers to a context or elides the UDP
+/// checksum.
+pub fn decompress(buf: &[u8],
+                  src_mac: &MacAddress,
+                  dst_mac: &MacAddress,
+                  datagram_size: Option<usize>,
+                  ip6: &mut [u8])
+                  -> Option<(usize, usize)> {
+    if buf.len() < 2 || buf[0] & DISPATCH_IPHC_MASK != DISPATCH_IPHC {
+        return None;
+    }
+    let tf = (buf[0] >> IPHC_TF_SHIFT) & 0x3;
+    let nhc = buf[0] & IPHC_NH != 0;
+    let hlim = buf[0] & IPHC_HLIM_MASK;
+    let sac = buf[1] & IPHC_SAC != 0;
+    let sam = (buf[1] >> IPHC_SAM_SHIFT) & 0x3;
+    let multicast = buf[1] & IPHC_M != 0;
+    let dam = buf[1] & IPHC_DAM_MASK;
+    // Without contexts, the only stateful address is the unspecified one
+    if buf[1] & IPHC_CID != 0 || buf[1] & IPHC_DAC != 0 || (sac && sam != 0) {
+        return None;
+    }
+
+    let src_size = if sac { 0 } else { UNICAST_SIZE[sam as usize] };
+    let dst_size = if multicast {
+        MULTICAST_SIZE[dam as usize]
+    } else {
+        UNICAST_SIZE[dam as usize]
+    };
+    let inline_size = TF_SIZE[tf as usize] + if nhc { 0 } else { 1 } +
+                      if hlim == 0 { 1 } else { 0 } + src_size + dst_size;
+    if buf.len() < 2 + inline_size {
+        return None;
+    }
+
+    let mut header = [0; IP6_HEADER_SIZE + UDP_HEADER_SIZE];
+    let mut pos = 2;
+    let (ecn_dscp, flow) = match tf {
+        0 => {
+            (buf[pos],
+             (buf[pos + 1] as u32 & 0xf) << 16 | (buf[pos + 2] as u32) << 8 | buf[pos + 3] as u32)
+        }
+        1 => {
+            (buf[pos] & 0xc0,
+             (buf[pos] as u32 & 0xf) << 16 | (buf[pos + 1] as u32) << 8 | buf[pos + 2] as u32)
+        }
+        2 => (buf[pos], 0),
+        _ => (0, 0),
+    };
+    pos += TF_SIZE[tf as usize];
+    let tc = ecn_dscp << 2 | ecn_dscp >> 6;
+    header[0] = 0x60 | tc >> 4;
+    header[1] = tc << 4 | (flow >> 16) as u8;
+    header[2] = (flow >> 8) as u8;
+    header[3] = flow as u8;
+
+    if !nhc {
+        header[6] = buf[pos];
+        pos += 1;
+    }
+    if hlim == 0 {
+        header[7] = buf[pos];
+        pos += 1;
+    } else {
+        header[7] = HOP_LIMITS[hlim as usize];
+    }
+
+    if !sac {
+        decompress_unicast(&buf[pos..pos + src_size], sam, src_mac, &mut header[8..24]);
+        pos += src_size;
+    }
+    if multicast {
+        decompress_multicast(&buf[pos..pos + dst_size], dam, &mut header[24..40]);
+    } else {
+        decompress_unicast(&buf[pos..pos + dst_size], dam, dst_mac, &mut header[24..40]);
+    }
+    pos += dst_size;
+
+    let mut written = IP6_HEADER_SIZE;
+    if nhc {
+        // UDP is the only next header that can be compressed
+        if buf.len() <= pos || buf[pos] & NHC_UDP_MASK != NHC_UDP ||
+           buf[pos] & NHC_UDP_CHECKSUM != 0 {
+            return None;
+        }
+        let ports = buf[pos] & NHC_UDP_PORTS_MASK;
+        pos += 1;
+        if buf.len() < pos + UDP_PORTS_SIZE[ports as usize] + 2 {
+            return None;
+        }
+        let (src_port, dst_port) = match ports {
+            0 => (read_be16(buf, pos), read_be16(buf, pos + 2)),
+            1 => (read_be16(buf, pos), 0xf000 | buf[pos + 2] as u16),
+            2 => (0xf000 | buf[pos] as u16, read_be16(buf, pos + 1)),
+            _ => (0xf0b0 | (buf[pos] >> 4) as u16, 0xf0b0 | (buf[pos] & 0xf) as u16),
+        };
+        pos += UDP_PORTS_SIZE[ports as usize];
+        header[6] = IP6_NH_UDP;
+        write_be16(&mut header, IP6_HEADER_SIZE, src_port);
+        write_be16(&mut header, IP6_HEADER_SIZE + 2, dst_port);
+        header[IP6_HEADER_SIZE + 6] = buf[pos];
+        header[IP6_HEADER_SIZE + 7] = buf[pos + 1];
+        pos += 2;
+        written += UDP_HEADER_SIZE;
+    }
+
+    // The payload length, and the UDP length, are always elided
+    let size = datagram_size.unwrap_or(written + buf.len() - pos);
+    if size < written || size - IP6_HEADER_SIZE > 0xffff || ip6.len() < written {
+        return None;
+    }
+    let payload_len = (size - IP6_HEADER_SIZE) as u16;
+    write_be16(&mut header, 4, payload_len);
+    if nhc {
+        write_be16(&mut header, IP6_HEADER_SIZE + 4, payload_len);
+    }
+    ip6[..written].copy_from_slice(&header[..written]);
+    Some((pos, written))
+}
+
+/// Unpacks an IPHC-compressed or uncompressed IPv6 packet into `out`.
+/// Returns the number of bytes of the datagram written.
+fn unpack(payload: &[u8],
+          src_mac: &MacAddress,
+          dst_mac: &MacAddress,
+          datagram_size: Option<usize>,
+          out: &mut [u8])
+          -> Option<usize> {
+    let (consumed, written) = if payload.first() == Some(&DISPATCH_IP6) {
+        (1, 0)
+    } else {
+        match decompress(payload, src_mac, dst_mac, datagram_size, out) {
+            Some(sizes) => sizes,
+            None => return None,
+        }
+    };
+    let end = written + payload.len() - consumed;
+    if end < IP6_HEADER_SIZE || end > out.len() {
+        return None;
+    }
+    out[written..end].copy_from_slice(&payload[consumed..]);
+    Some(end)
+}
+
+/// A fragmentation header. `size` is the size of the whole uncompressed
+/// datagram and `offset` where in it the fragment belongs, in bytes. The
+/// first fragment has offset 0 and carries the compressed headers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FragHeader {
+    pub size: u16,
+    pub tag: u16,
+    pub offset: u16,
+}
+
+impl FragHeader {
+    /// Number of bytes `encode` writes.
+    pub fn size(&self) -> usize {
+        if self.offset == 0 {
+            FRAG1_HEADER_SIZE
+        } else {
+            FRAGN_HEADER_SIZE
+        }
+    }
+
+    /// Parses the fragmentation header at the start of `buf`, returning it
+    /// and the offset of the fragment's contents.
+    pub fn decode(buf: &[u8]) -> Option<(FragHeader, usize)> {
+        if buf.len() < FRAG1_HEADER_SIZE {
+            return None;
+        }
+        let mut header = FragHeader {
+            size: (buf[0] as u16 & 0x7) << 8 | buf[1] as u16,
+            tag: read_be16(buf, 2),
+            offset: 0,
+        };
+        match buf[0] & DISPATCH_FRAG_MASK {
+            DISPATCH_FRAG1 => Some((header, FRAG1_HEADER_SIZE)),
+            DISPATCH_FRAGN if buf.len() >= FRAGN_HEADER_SIZE && buf[4] != 0 => {
+                header.offset = buf[4] as u16 * 8;
+                Some((header, FRAGN_HEADER_SIZE))
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes the header at the start of `buf`, returning its size, or
+    /// `None` if `buf` is too short or a field does not fit.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        if self.size > 0x7ff || self.offset % 8 != 0 || self.offset / 8 > 0xff ||
+           buf.len() < self.size() {
+            return None;
+        }
+        let dispatch = if self.offset == 0 {
+            DISPATCH_FRAG1
+        } else {
+            DISPATCH_FRAGN
+        };
+        buf[0] = dispatch | (self.size >> 8) as u8;
+        buf[1] = self.size as u8;
+        write_be16(buf, 2, self.tag);
+        if self.offset != 0 {
+            buf[4] = (self.offset / 8) as u8;
+        }
+        Some(self.size())
+    }
+}
+
+pub trait Client {
+    /// A datagram passed to `Sixlowpan::send` was sent, or sending one of
+    /// its fragments failed with `result`.
+    fn send_done(&self, datagram: &'static mut [u8], result: ReturnCode);
+    /// An IPv6 datagram arrived in frames from `src` to `dst`.
+    fn receive(&self, datagram: &[u8], src: MacAddress, dst: MacAddress);
+}
+
+pub struct Sixlowpan<'a, R: radio::Radio + 'a, A: Alarm + 'a> {
+    radio: &'a R,
+    alarm: &'a A,
+    client: Cell<Option<&'a Client>>,
+    seq: Cell<u8>,
+    frame: TakeCell<'static, [u8]>,
+    // The datagram being sent, its length and destination, and where its
+    // next fragment starts (0 before the first)
+    tx_datagram: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_dst: Cell<MacAddress>,
+    tx_offset: Cell<usize>,
+    tx_tag: Cell<u16>,
+    // Decompressed unfragmented datagrams
+    rx_packet: TakeCell<'static, [u8]>,
+    // The datagram being reassembled: its size (0 if none), tag and
+    // addresses, and which 8-byte blocks of it have arrived
+    reassembly: TakeCell<'static, [u8]>,
+    rx_size: Cell<usize>,
+    rx_tag: Cell<u16>,
+    rx_src: Cell<MacAddress>,
+    rx_dst: Cell<MacAddress>,
+    rx_blocks: Cell<[u8; BLOCK_MAP_SIZE]>,
+}
+
+impl<'a, R: radio::Radio, A: Alarm> Sixlowpan<'a, R, A> {
+    /// `frame` must be at least `radio::MAX_BUF_SIZE` bytes, `packet` at
+    /// least `MAX_PACKET_SIZE` and `reassembly` at least `MAX_DATAGRAM_SIZE`.
+    pub fn new(radio: &'a R,
+               alarm: &'a A,
+               frame: &'static mut [u8],
+               packet: &'static mut [u8],
+               reassembly: &'static mut [u8])
+               -> Sixlowpan<'a, R, A> {
+        Sixlowpan {
+            radio: radio,
+            alarm: alarm,
+            client: Cell::new(None),
+            seq: Cell::new(0),
+            frame: TakeCell::new(frame),
+            tx_datagram: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_dst: Cell::new(MacAddress::Short(ieee802154::BROADCAST_ADDR)),
+            tx_offset: Cell::new(0),
+            tx_tag: Cell::new(0),
+            rx_packet: TakeCell::new(packet),
+            reassembly: TakeCell::new(reassembly),
+            rx_size: Cell::new(0),
+            rx_tag: Cell::new(0),
+            rx_src: Cell::new(MacAddress::Short(ieee802154::BROADCAST_ADDR)),
+            rx_dst: Cell::new(MacAddress::Short(ieee802154::BROADCAST_ADDR)),
+            rx_blocks: Cell::new([0; BLOCK_MAP_SIZE]),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a Client) {
+        self.client.set(Some(client));
+    }
+
+    /// The address frames are sent from: the short address if the radio
+    /// has one, otherwise the extended address.
+    pub fn mac_address(&self) -> MacAddress {
+        match self.radio.get_address() {
+            ieee802154::NO_SHORT_ADDR | ieee802154::BROADCAST_ADDR => {
+                MacAddress::Long(self.radio.get_address_long())
+            }
+            addr => MacAddress::Short(addr),
+        }
+    }
+
+    /// Sends the IPv6 datagram in the first `len` bytes of `datagram` to
+    /// `dst`, in fragments if it does not fit in one frame. The datagram is
+    /// returned through `Client::send_done`. If it cannot be sent it is
+    /// returned right away with EBUSY if another datagram is being sent,
+    /// EOFF if the radio is off, ESIZE if it is larger than
+    /// `MAX_DATAGRAM_SIZE` and EINVAL if it is not an IPv6 datagram.
+    pub fn send(&self,
+                dst: MacAddress,
+                datagram: &'static mut [u8],
+                len: usize)
+                -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.tx_datagram.is_some() || self.frame.is_none() {
+            return Err((ReturnCode::EBUSY, datagram));
+        } else if !self.radio.ready() {
+            return Err((ReturnCode::EOFF, datagram));
+        } else if len > datagram.len() || len > MAX_DATAGRAM_SIZE {
+            return Err((ReturnCode::ESIZE, datagram));
+        } else if len < IP6_HEADER_SIZE || datagram[0] >> 4 != 6 {
+            return Err((ReturnCode::EINVAL, datagram));
+        }
+        self.tx_len.set(len);
+        self.tx_dst.set(dst);
+        self.tx_offset.set(0);
+        self.tx_tag.set(self.tx_tag.get().wrapping_add(1));
+        self.tx_datagram.replace(datagram);
+        let rval = self.send_fragment();
+        if rval != ReturnCode::SUCCESS {
+            if let Some(datagram) = self.tx_datagram.take() {
+                return Err((rval, datagram));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the next frame of the datagram being sent.
+    fn send_fragment(&self) -> ReturnCode {
+        // A failed transmission loses the frame buffer, so make sure the
+        // radio will take it
+        if !self.radio.ready() {
+            return ReturnCode::EOFF;
+        }
+        let frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => return ReturnCode::EBUSY,
+        };
+        let len = self.tx_len.get();
+        let frame_len = self.tx_datagram
+            .map(|datagram| self.build_fragment(frame, &datagram[..len]))
+            .unwrap_or(None);
+        match frame_len {
+            Some(frame_len) => self.radio.transmit(frame, frame_len as u8),
+            None => {
+                self.frame.replace(frame);
+                ReturnCode::FAIL
+            }
+        }
+    }
+
+    /// Writes the MAC header and the next fragment of `datagram` into
+    /// `frame`, or the whole datagram if it fits. Returns the length of the
+    /// MAC frame.
+    fn build_fragment(&self, frame: &mut [u8], datagram: &[u8]) -> Option<usize> {
+        let src = self.mac_address();
+        let dst = self.tx_dst.get();
+        let pan = self.radio.get_pan();
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_requested: dst != MacAddress::Short(ieee802154::BROADCAST_ADDR),
+            version: FrameVersion::V2003,
+            seq: self.seq.get(),
+            dst_pan: Some(pan),
+            dst_addr: Some(dst),
+            src_pan: Some(pan),
+            src_addr: Some(src),
+        };
+        let start = self.radio.psdu_offset() as usize;
+        let end = min(frame.len(),
+                      start + ieee802154::MAX_FRAME_SIZE - ieee802154::FCS_SIZE);
+        let mut pos = match header.encode(&mut frame[start..end]) {
+            Some(header_len) => start + header_len,
+            None => return None,
+        };
+
+        let len = datagram.len();
+        let offset = self.tx_offset.get();
+        let next;
+        if offset == 0 {
+            let mut iphc = [0; IP6_HEADER_SIZE + UDP_HEADER_SIZE];
+            let (iphc_len, replaced) = match compress(datagram, &src, &dst, &mut iphc) {
+                Some(sizes) => sizes,
+                None => return None,
+            };
+            if pos + iphc_len + len - replaced <= end {
+                // The whole datagram fits in this frame
+                next = len;
+            } else {
+                // The first fragment ends on an 8-byte boundary of the
+                // uncompressed datagram
+                let frag = FragHeader {
+                    size: len as u16,
+                    tag: self.tx_tag.get(),
+                    offset: 0,
+                };
+                pos += match frag.encode(&mut frame[pos..end]) {
+                    Some(frag_len) => frag_len,
+                    None => return None,
+                };
+                if pos + iphc_len > end {
+                    return None;
+                }
+                next = (replaced + end - pos - iphc_len) / 8 * 8;
+                if next <= replaced {
+                    return None;
+                }
+            }
+            frame[pos..pos + iphc_len].copy_from_slice(&iphc[..iphc_len]);
+            pos += iphc_len;
+            frame[pos..pos + next - replaced].copy_from_slice(&datagram[replaced..next]);
+            pos += next - replaced;
+        } else {
+            let frag = FragHeader {
+                size: len as u16,
+                tag: self.tx_tag.get(),
+                offset: offset as u16,
+            };
+            pos += match frag.encode(&mut frame[pos..end]) {
+                Some(frag_len) => frag_len,
+                None => return None,
+            };
+            next = min(len, offset + (end - pos) / 8 * 8);
+            if next <= offset {
+                return None;
+            }
+            frame[pos..pos + next - offset].copy_from_slice(&datagram[offset..next]);
+            pos += next - offset;
+        }
+        self.tx_offset.set(next);
+        self.seq.set(self.seq.get().wrapping_add(1));
+        Some(pos - start)
+    }
+
+    /// Handles the payload of a data frame from `src` to `dst`.
+    fn receive_frame(&self, payload: &[u8], src: MacAddress, dst: MacAddress) {
+        match payload.first() {
+            Some(&dispatch) if dispatch == DISPATCH_IP6 ||
+                               dispatch & DISPATCH_IPHC_MASK == DISPATCH_IPHC => {
+                self.rx_packet.map(|packet| {
+                    if let Some(len) = unpack(payload, &src, &dst, None, packet) {
+                        self.client.get().map(|client| client.receive(&packet[..len], src, dst));
+                    }
+                });
+            }
+            Some(_) => {
+                if let Some((frag, frag_len)) = FragHeader::decode(payload) {
+                    self.receive_fragment(frag, &payload[frag_len..], src, dst);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Adds a fragment to the datagram being reassembled, and passes the
+    /// datagram to the client once all its fragments have arrived.
+    fn receive_fragment(&self, frag: FragHeader, data: &[u8], src: MacAddress, dst: MacAddress) {
+        let size = frag.size as usize;
+        if size < IP6_HEADER_SIZE || size > MAX_DATAGRAM_SIZE {
+            return;
+        }
+        if self.rx_size.get() == 0 {
+            self.rx_size.set(size);
+            self.rx_tag.set(frag.tag);
+            self.rx_src.set(src);
+            self.rx_dst.set(dst);
+            self.rx_blocks.set([0; BLOCK_MAP_SIZE]);
+            let timeout = REASSEMBLY_TIMEOUT_S * <A::Frequency>::frequency();
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(timeout));
+        } else if self.rx_size.get() != size || self.rx_tag.get() != frag.tag ||
+                  self.rx_src.get() != src {
+            // Only one datagram is reassembled at a time
+            return;
+        }
+
+        let complete = self.reassembly.map_or(false, |buf| {
+            if size > buf.len() {
+                return false;
+            }
+            let start = frag.offset as usize;
+            let end = if start == 0 {
+                match unpack(data, &src, &dst, Some(size), &mut buf[..size]) {
+                    Some(end) => end,
+                    None => return false,
+                }
+            } else {
+                let end = start + data.len();
+                if end > size {
+                    return false;
+                }
+                buf[start..end].copy_from_slice(data);
+                end
+            };
+            // Every fragment but the last covers whole blocks
+            if end != size && end % 8 != 0 {
+                return false;
+            }
+            let mut blocks = self.rx_blocks.get();
+            for block in start / 8..(end + 7) / 8 {
+                blocks[block / 8] |= 1 << (block % 8);
+            }
+            self.rx_blocks.set(blocks);
+            (0..(size + 7) / 8).all(|block| blocks[block / 8] & 1 << (block % 8) != 0)
+        });
+
+        if complete {
+            self.rx_size.set(0);
+            self.alarm.disable();
+            let dst = self.rx_dst.get();
+            self.reassembly.map(|buf| {
+                self.client.get().map(|client| client.receive(&buf[..size], src, dst));
+            });
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::TxClient for Sixlowpan<'a, R, A> {
+    fn send_done(&self, frame: &'static mut [u8], result: ReturnCode) {
+        self.frame.replace(frame);
+        if self.tx_datagram.is_none() {
+            return;
+        }
+        let mut result = result;
+        if result == ReturnCode::SUCCESS && self.tx_offset.get() < self.tx_len.get() {
+            result = self.send_fragment();
+            if result == ReturnCode::SUCCESS {
+                return;
+            }
+        }
+        if let Some(datagram) = self.tx_datagram.take() {
+            self.client.get().map(move |client| client.send_done(datagram, result));
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> radio::RxClient for Sixlowpan<'a, R, A> {
+    fn receive(&self,
+               buf: &'static mut [u8],
+               frame_len: u8,
+               _info: radio::RxInfo,
+               result: ReturnCode) {
+        let start = self.radio.psdu_offset() as usize;
+        let end = start + frame_len as usize;
+        if result == ReturnCode::SUCCESS && end <= buf.len() {
+            if let Some((header, header_len)) = Header::decode(&buf[start..end]) {
+                match (header.frame_type, header.src_addr, header.dst_addr) {
+                    (FrameType::Data, Some(src), Some(dst)) => {
+                        self.receive_frame(&buf[start + header_len..end], src, dst);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> time::Client for Sixlowpan<'a, R, A> {
+    fn fired(&self) {
+        // The rest of the datagram being reassembled did not arrive in time
+        self.rx_size.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::{Cell, RefCell};
+    use ieee802154::MacAddress;
+    use kernel::hil::time::{Alarm, Frequency, Time};
+    use kernel::returncode::ReturnCode;
+    use sim_radio::{SimMedium, SimRadio};
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use super::*;
+    use virtual_alarm::tests::ManualAlarm;
+
+    const NODE_A: MacAddress = MacAddress::Long([0x00, 0x12, 0x4b, 0x00, 0x0a, 0x0b, 0x0c, 0x0d]);
+    const NODE_B: MacAddress = MacAddress::Long([0x00, 0x12, 0x4b, 0x00, 0x01, 0x02, 0x03, 0x04]);
+
+    // UDP between link-local addresses derived from the MAC addresses:
+    // everything but the ports and checksum is elided
+    const UDP: [u8; 53] = [0x60, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x11, 0x40,
+                           0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                           0x02, 0x12, 0x4b, 0x00, 0x0a, 0x0b, 0x0c, 0x0d,
+                           0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                           0x02, 0x12, 0x4b, 0x00, 0x01, 0x02, 0x03, 0x04,
+                           0x04, 0xd2, 0x16, 0x2e, 0x00, 0x0d, 0x5a, 0x3c,
+                           0x68, 0x65, 0x6c, 0x6c, 0x6f];
+    const UDP_IPHC: [u8; 14] = [0x7e, 0x33, 0xf0, 0x04, 0xd2, 0x16, 0x2e, 0x5a, 0x3c,
+                                0x68, 0x65, 0x6c, 0x6c, 0x6f];
+
+    /// Checks that the IPHC packet `compressed`, sent from `src` to `dst`,
+    /// decompresses to `ip6` and that `ip6` compresses back to it.
+    fn round_trip(compressed: &[u8], ip6: &[u8], src: MacAddress, dst: MacAddress) {
+        let mut buf = [0; 256];
+        let (consumed, written) = decompress(compressed, &src, &dst, None, &mut buf).unwrap();
+        let len = written + compressed.len() - consumed;
+        buf[written..len].copy_from_slice(&compressed[consumed..]);
+        assert_eq!(&buf[..len], ip6);
+
+        let mut buf = [0; 256];
+        let (iphc_len, replaced) = compress(ip6, &src, &dst, &mut buf).unwrap();
+        let len = iphc_len + ip6.len() - replaced;
+        buf[iphc_len..len].copy_from_slice(&ip6[replaced..]);
+        assert_eq!(&buf[..len], compressed);
+    }
+
+    #[test]
+    fn link_local_udp() {
+        round_trip(&UDP_IPHC, &UDP, NODE_A, NODE_B);
+    }
+
+    #[test]
+    fn multicast_icmp() {
+        // RPL DIS to the all-RPL-nodes multicast address (ff02::1a). ICMPv6
+        // is not compressed, so the next header is inline
+        let dis = [0x60, 0x00, 0x00, 0x00, 0x00, 0x06, 0x3a, 0xff,
+                   0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x02, 0x12, 0x4b, 0x00, 0x0a, 0x0b, 0x0c, 0x0d,
+                   0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1a,
+                   0x9b, 0x00, 0x6b, 0x1f, 0x00, 0x00];
+        let dis_iphc = [0x7b, 0x3b, 0x3a, 0x1a, 0x9b, 0x00, 0x6b, 0x1f, 0x00, 0x00];
+        round_trip(&dis_iphc,
+                   &dis,
+                   NODE_A,
+                   MacAddress::Short(ieee802154::BROADCAST_ADDR));
+    }
+
+    #[test]
+    fn global_inline_fields() {
+        // From a short-address node to a global address, with a traffic
+        // class, flow label and hop limit inline and 4-bit UDP ports
+        let global = [0x6b, 0x81, 0x23, 0x45, 0x00, 0x0a, 0x11, 0x20,
+                      0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                      0x00, 0x00, 0x00, 0xff, 0xfe, 0x00, 0x12, 0x34,
+                      0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                      0xf0, 0xb1, 0xf0, 0xb2, 0x00, 0x0a, 0xbe, 0xef,
+                      0x01, 0x02];
+        let global_iphc = [0x64, 0x20, 0x2e, 0x01, 0x23, 0x45, 0x20, 0x12, 0x34,
+                           0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+                           0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                           0xf3, 0x12, 0xbe, 0xef, 0x01, 0x02];
+        round_trip(&global_iphc, &global, NODE_B, MacAddress::Short(0x0001));
+    }
+
+    #[test]
+    fn reject_unsupported_headers() {
+        let mut buf = [0; 64];
+        // A context identifier, and an elided UDP checksum
+        let context = [0x7e, 0xb3, 0x00, 0xf0, 0x04, 0xd2, 0x16, 0x2e, 0x5a, 0x3c];
+        let no_checksum = [0x7e, 0x33, 0xf4, 0x04, 0xd2, 0x16, 0x2e];
+        assert_eq!(decompress(&context, &NODE_A, &NODE_B, None, &mut buf), None);
+        assert_eq!(decompress(&no_checksum, &NODE_A, &NODE_B, None, &mut buf), None);
+        // Truncated inline fields
+        assert_eq!(decompress(&UDP_IPHC[..6], &NODE_A, &NODE_B, None, &mut buf), None);
+    }
+
+    #[test]
+    fn fragment_headers() {
+        let first = FragHeader {
+            size: 200,
+            tag: 0x1234,
+            offset: 0,
+        };
+        let next = FragHeader { offset: 96, ..first };
+        for &(frag, captured) in [(first, &[0xc0, 0xc8, 0x12, 0x34][..]),
+                                  (next, &[0xe0, 0xc8, 0x12, 0x34, 0x0c][..])]
+            .iter() {
+            let mut buf = [0; 8];
+            assert_eq!(FragHeader::decode(captured), Some((frag, captured.len())));
+            assert_eq!(frag.encode(&mut buf), Some(captured.len()));
+            assert_eq!(&buf[..captured.len()], captured);
+        }
+
+        let mut buf = [0; 8];
+        assert_eq!(FragHeader { offset: 100, ..first }.encode(&mut buf), None);
+        assert_eq!(FragHeader { size: 0x800, ..first }.encode(&mut buf), None);
+        // Fragments other than the first cannot start at offset 0
+        assert_eq!(FragHeader::decode(&[0xe0, 0xc8, 0x12, 0x34, 0x00]), None);
+    }
+
+    #[test]
+    fn first_fragment_lengths() {
+        // A first fragment gives the datagram size, which the IPv6 and UDP
+        // lengths are rebuilt from
+        let mut buf = [0; 64];
+        assert_eq!(decompress(&UDP_IPHC, &NODE_A, &NODE_B, Some(200), &mut buf),
+                   Some((9, 48)));
+        assert_eq!(buf[4..6], [0x00, 0xa0]);
+        assert_eq!(buf[44..46], [0x00, 0xa0]);
+    }
+
+    /// A UDP datagram of `size` bytes from `NODE_A` to `NODE_B`
+    fn datagram(size: usize) -> Vec<u8> {
+        let mut datagram = UDP[..IP6_HEADER_SIZE + UDP_HEADER_SIZE].to_vec();
+        let len = (size - IP6_HEADER_SIZE) as u16;
+        datagram[4] = (len >> 8) as u8;
+        datagram[5] = len as u8;
+        datagram[IP6_HEADER_SIZE + 4] = (len >> 8) as u8;
+        datagram[IP6_HEADER_SIZE + 5] = len as u8;
+        datagram.extend((0..size - IP6_HEADER_SIZE - UDP_HEADER_SIZE).map(|i| i as u8));
+        datagram
+    }
+
+    /// Splits `datagram` into fragment payloads covering 64 bytes of it each,
+    /// the first with compressed headers.
+    fn fragments(datagram: &[u8], tag: u16) -> Vec<Vec<u8>> {
+        let mut iphc = [0; IP6_HEADER_SIZE + UDP_HEADER_SIZE];
+        let (iphc_len, replaced) = compress(datagram, &NODE_A, &NODE_B, &mut iphc).unwrap();
+        let mut fragments = Vec::new();
+        let mut offset = 0;
+        while offset < datagram.len() {
+            let end = min(datagram.len(), offset + 64);
+            let header = FragHeader {
+                size: datagram.len() as u16,
+                tag: tag,
+                offset: offset as u16,
+            };
+            let mut fragment = vec![0; header.size()];
+            header.encode(&mut fragment).unwrap();
+            if offset == 0 {
+                fragment.extend_from_slice(&iphc[..iphc_len]);
+                fragment.extend_from_slice(&datagram[replaced..end]);
+            } else {
+                fragment.extend_from_slice(&datagram[offset..end]);
+            }
+            fragments.push(fragment);
+            offset = end;
+        }
+        fragments
+    }
+
+    fn buffer(len: usize) -> &'static mut [u8] {
+        unsafe { &mut *Box::into_raw(vec![0; len].into_boxed_slice()) }
+    }
+
+    /// Keeps the datagrams a `Sixlowpan` receives.
+    struct Receiver {
+        datagrams: RefCell<Vec<Vec<u8>>>,
+        src: Cell<Option<MacAddress>>,
+    }
+
+    impl Client for Receiver {
+        fn send_done(&self, _datagram: &'static mut [u8], _result: ReturnCode) {}
+
+        fn receive(&self, datagram: &[u8], src: MacAddress, _dst: MacAddress) {
+            self.datagrams.borrow_mut().push(datagram.to_vec());
+            self.src.set(Some(src));
+        }
+    }
+
+    /// Passes `f` a `Sixlowpan` that delivers datagrams to a `Receiver`,
+    /// and the alarm it times reassemblies out with.
+    fn with_sixlowpan<F>(f: F)
+        where F: for<'b> FnOnce(&Sixlowpan<'b, SimRadio<'b, ManualAlarm<'b>>, ManualAlarm<'b>>,
+                                &ManualAlarm<'b>,
+                                &Receiver)
+    {
+        let alarm = ManualAlarm::new();
+        let medium = SimMedium::new(&alarm);
+        let radio = SimRadio::new(&medium, 0x1002);
+        let sixlowpan = Sixlowpan::new(&radio,
+                                       &alarm,
+                                       buffer(radio::MAX_BUF_SIZE),
+                                       buffer(MAX_PACKET_SIZE),
+                                       buffer(MAX_DATAGRAM_SIZE));
+        alarm.set_client(&sixlowpan);
+        let receiver = Receiver {
+            datagrams: RefCell::new(Vec::new()),
+            src: Cell::new(None),
+        };
+        sixlowpan.set_client(&receiver);
+        f(&sixlowpan, &alarm, &receiver);
+    }
+
+    #[test]
+    fn reassemble_in_order() {
+        with_sixlowpan(|sixlowpan, _, receiver| {
+            let datagram = datagram(200);
+            let fragments = fragments(&datagram, 7);
+            assert_eq!(fragments.len(), 4);
+            for fragment in fragments.iter() {
+                assert!(receiver.datagrams.borrow().is_empty());
+                sixlowpan.receive_frame(fragment, NODE_A, NODE_B);
+            }
+            assert_eq!(*receiver.datagrams.borrow(), [datagram]);
+            assert_eq!(receiver.src.get(), Some(NODE_A));
+        });
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        with_sixlowpan(|sixlowpan, _, receiver| {
+            let datagram = datagram(200);
+            let fragments = fragments(&datagram, 7);
+            for &i in [3, 1, 1, 0, 2].iter() {
+                assert!(receiver.datagrams.borrow().is_empty());
+                sixlowpan.receive_frame(&fragments[i], NODE_A, NODE_B);
+            }
+            assert_eq!(*receiver.datagrams.borrow(), [datagram]);
+        });
+    }
+
+    #[test]
+    fn ignore_other_datagrams_while_reassembling() {
+        with_sixlowpan(|sixlowpan, _, receiver| {
+            let datagram = datagram(200);
+            let other = fragments(&datagram, 8);
+            let fragments = fragments(&datagram, 7);
+            sixlowpan.receive_frame(&fragments[0], NODE_A, NODE_B);
+            // Another tag, and the same tag from another node
+            sixlowpan.receive_frame(&other[1], NODE_A, NODE_B);
+            sixlowpan.receive_frame(&fragments[1], MacAddress::Short(0x0003), NODE_B);
+            for fragment in other[2..].iter() {
+                sixlowpan.receive_frame(fragment, NODE_A, NODE_B);
+            }
+            assert!(receiver.datagrams.borrow().is_empty());
+
+            for fragment in fragments[1..].iter() {
+                sixlowpan.receive_frame(fragment, NODE_A, NODE_B);
+            }
+            assert_eq!(*receiver.datagrams.borrow(), [datagram]);
+        });
+    }
+
+    #[test]
+    fn reassembly_times_out() {
+        with_sixlowpan(|sixlowpan, alarm, receiver| {
+            let timeout = REASSEMBLY_TIMEOUT_S * <<ManualAlarm as Alarm>::Frequency>::frequency();
+            let datagram = datagram(200);
+            let fragments = fragments(&datagram, 7);
+            sixlowpan.receive_frame(&fragments[0], NODE_A, NODE_B);
+            sixlowpan.receive_frame(&fragments[1], NODE_A, NODE_B);
+            alarm.advance(timeout - 1);
+            assert!(alarm.is_armed());
+            alarm.advance(1);
+            assert!(!alarm.is_armed());
+
+            // The fragments that arrived before the timeout were dropped
+            sixlowpan.receive_frame(&fragments[2], NODE_A, NODE_B);
+            sixlowpan.receive_frame(&fragments[3], NODE_A, NODE_B);
+            assert!(receiver.datagrams.borrow().is_empty());
+
+            // The late fragments started a new reassembly, which holds off
+            // other nodes until it times out as well
+            let other = MacAddress::Short(0x0003);
+            for fragment in fragments.iter() {
+                sixlowpan.receive_frame(fragment, other, NODE_B);
+            }
+            assert!(receiver.datagrams.borrow().is_empty());
+            alarm.advance(timeout);
+            for fragment in fragments.iter() {
+                sixlowpan.receive_frame(fragment, other, NODE_B);
+            }
+            assert_eq!(receiver.datagrams.borrow().len(), 1);
+            assert_eq!(receiver.src.get(), Some(other));
+            assert!(!alarm.is_armed());
+        });
+    }
+
+    #[test]
+    fn unfragmented_datagram() {
+        with_sixlowpan(|sixlowpan, alarm, receiver| {
+            sixlowpan.receive_frame(&UDP_IPHC, NODE_A, NODE_B);
+            assert_eq!(*receiver.datagrams.borrow(), [UDP.to_vec()]);
+            assert!(!alarm.is_armed());
+        });
+    }
+}
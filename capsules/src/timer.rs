@@ -75,7 +75,22 @@ impl<'a, A: Alarm> Driver for TimerDriver<'a, A> {
             })
     }
 
-    fn command(&self, cmd_type: usize, interval: usize, caller_id: AppId) -> ReturnCode {
+    // 0: check if present
+    // 1: start a one-shot timer, firing after `interval` ms
+    // 2: start a repeating timer, firing every `interval` ms
+    // 3: stop the timer
+    // 4: read the current time in clock ticks
+    // 5: start the timer, firing after `interval` ms; `mode` (the second
+    //    argument of command2) selects a one-shot (0) or repeating (1) timer.
+    //    Commands 1 and 2 ignore the second argument, which apps built before
+    //    it existed leave undefined.
+
+    fn command2(&self,
+                cmd_type: usize,
+                interval: usize,
+                mode: usize,
+                caller_id: AppId)
+                -> ReturnCode {
         // First, convert from milliseconds to native clock frequency
         let interval = (interval as u32) * <A::Frequency>::frequency() / 1000;
 
@@ -110,8 +125,12 @@ impl<'a, A: Alarm> Driver for TimerDriver<'a, A> {
                         (ReturnCode::EINVAL, false)
                     }
                 },
-                /* 1 for Oneshot, 2 for Repeat */
-                cmd_type if cmd_type <= 2 && cmd_type > 0 => {
+                1 | 2 | 5 /* Start */ => {
+                    let repeating = match (cmd_type, mode) {
+                        (1, _) | (5, 0) => false,
+                        (2, _) | (5, 1) => true,
+                        _ => return (ReturnCode::EINVAL, false),
+                    };
                     if interval == 0 {
                         // Request for zero-length timer
                         return (ReturnCode::EINVAL, false);
@@ -125,8 +144,7 @@ impl<'a, A: Alarm> Driver for TimerDriver<'a, A> {
                     td.t0 = self.alarm.now();
                     td.interval = interval;
 
-                    // Repeat if cmd_type was 2
-                    td.repeating = cmd_type == 2;
+                    td.repeating = repeating;
                     if self.alarm.is_armed() {
                         (ReturnCode::SUCCESS, true)
                     } else {
@@ -0,0 +1,468 @@
+//! The UDP capsule lets applications send and receive UDP datagrams over
+//! 6LoWPAN/IPv6.
+
+// System call interface for UDP sockets.
+//
+// An application binds a port, then sends datagrams from that port and
+// receives the datagrams sent to it. Each port can be bound by one
+// application at a time. Datagrams to link-local and multicast addresses go
+// straight to their destination, from this node's link-local address, which
+// is derived from the radio's MAC address. Datagrams to other addresses go
+// to the gateway the board configured (usually a border router). They are
+// sent from this node's address in the global prefix the board configured
+// with the gateway, as routers do not forward datagrams from link-local
+// addresses. Without a gateway, only on-link destinations can be reached.
+//
+// Like the radio driver, each application can have one datagram waiting
+// to be sent, and waiting applications are served in round-robin order.
+//
+// Allow:
+//   0: receive buffer, for the payload of received datagrams
+//   1: transmit buffer, with the payload to send
+//   2: destination: a 16-byte IPv6 address followed by a 2-byte port, both
+//      in network byte order
+//   3: source: filled in with the address and port (as in allow 2) a
+//      datagram was received from, and by command 3
+//
+// Subscribe:
+//   0: datagram sent, with the result
+//   1: datagram received, with the payload length (which may be larger
+//      than the receive buffer) and the source port
+
+use core::cell::Cell;
+use ieee802154::{self, MacAddress};
+use kernel::{AppId, Driver, Callback, AppSlice, Container, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::process::Error;
+use kernel::returncode::ReturnCode;
+use sixlowpan::{self, Sixlowpan, IP6_HEADER_SIZE, IP6_NH_UDP, UDP_HEADER_SIZE};
+
+/// Size of an address and port in the destination and source buffers
+const ENDPOINT_SIZE: usize = 18;
+/// Hop limit of the datagrams sent
+const HOP_LIMIT: u8 = 64;
+
+/// Computes the UDP checksum of the UDP header and payload in `udp`, sent
+/// from `src` to `dst`. Over a datagram that carries its checksum, the
+/// result is zero if the checksum is correct.
+fn checksum(src: &[u8], dst: &[u8], udp: &[u8]) -> u16 {
+    // The pseudo-header: addresses, length and next header
+    let mut sum: u32 = udp.len() as u32 + IP6_NH_UDP as u32;
+    for addr in [src, dst].iter() {
+        for word in addr.chunks(2) {
+            sum += (word[0] as u32) << 8 | word[1] as u32;
+        }
+    }
+    for word in udp.chunks(2) {
+        sum += (word[0] as u32) << 8 | if word.len() > 1 { word[1] as u32 } else { 0 };
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn read_be16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) << 8 | buf[offset + 1] as u16
+}
+
+fn write_be16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = (value >> 8) as u8;
+    buf[offset + 1] = value as u8;
+}
+
+/// A datagram waiting to be sent
+#[derive(Copy, Clone)]
+struct PendingTx {
+    len: usize,
+    dst_addr: [u8; 16],
+    dst_port: u16,
+    next_hop: MacAddress,
+}
+
+#[derive(Default)]
+pub struct App {
+    tx_callback: Option<Callback>,
+    rx_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_dst: Option<AppSlice<Shared, u8>>,
+    app_src: Option<AppSlice<Shared, u8>>,
+    // The port this app is bound to
+    port: Option<u16>,
+    pending_tx: Option<PendingTx>,
+}
+
+pub struct UDPDriver<'a, R: radio::Radio + 'a, A: Alarm + 'a> {
+    ip: &'a Sixlowpan<'a, R, A>,
+    apps: Container<App>,
+    // The app whose datagram is being sent
+    current_app: Cell<Option<AppId>>,
+    // Index of the app that sent last, for round-robin
+    last_app: Cell<usize>,
+    kernel_tx: TakeCell<'static, [u8]>,
+    gateway: Cell<Option<MacAddress>>,
+    // The /64 prefix of this node's global address, set with the gateway
+    prefix: Cell<Option<[u8; 8]>>,
+}
+
+impl<'a, R: radio::Radio, A: Alarm> UDPDriver<'a, R, A> {
+    pub fn new(ip: &'a Sixlowpan<'a, R, A>, container: Container<App>) -> UDPDriver<'a, R, A> {
+        UDPDriver {
+            ip: ip,
+            apps: container,
+            current_app: Cell::new(None),
+            last_app: Cell::new(0),
+            kernel_tx: TakeCell::empty(),
+            gateway: Cell::new(None),
+            prefix: Cell::new(None),
+        }
+    }
+
+    /// `tx_buf` holds outgoing datagrams, so its size limits the payload
+    /// apps can send to `tx_buf.len() - 48` bytes.
+    pub fn config_buffer(&mut self, tx_buf: &'static mut [u8]) {
+        self.kernel_tx.replace(tx_buf);
+    }
+
+    /// Datagrams to addresses that are not link-local or multicast are sent
+    /// to the node with MAC address `gateway`, from this node's address in
+    /// the /64 `prefix`.
+    pub fn set_gateway(&self, gateway: MacAddress, prefix: [u8; 8]) {
+        self.gateway.set(Some(gateway));
+        self.prefix.set(Some(prefix));
+    }
+
+    /// This node's link-local address.
+    fn local_address(&self) -> [u8; 16] {
+        sixlowpan::link_local_address(&self.ip.mac_address())
+    }
+
+    /// This node's address in the prefix configured with the gateway, if
+    /// there is one. It has the same interface ID as the link-local address.
+    fn global_address(&self) -> Option<[u8; 16]> {
+        self.prefix.get().map(|prefix| {
+            let mut addr = self.local_address();
+            addr[..8].copy_from_slice(&prefix);
+            addr
+        })
+    }
+
+    /// The address datagrams to `dst` are sent from: the link-local address
+    /// for destinations on the link, or else the global address.
+    fn source_address(&self, dst: &[u8]) -> [u8; 16] {
+        if dst[0] == 0xff || sixlowpan::link_local_mac(dst).is_some() {
+            self.local_address()
+        } else {
+            self.global_address().unwrap_or(self.local_address())
+        }
+    }
+
+    /// The node a datagram to `addr` is sent to over the radio.
+    fn next_hop(&self, addr: &[u8]) -> Option<MacAddress> {
+        if addr[0] == 0xff {
+            Some(MacAddress::Short(ieee802154::BROADCAST_ADDR))
+        } else {
+            sixlowpan::link_local_mac(addr).or(self.gateway.get())
+        }
+    }
+
+    /// Picks the next app with a queued datagram: the first one after the
+    /// app that sent last, wrapping around to the lowest index.
+    fn next_pending(&self) -> Option<AppId> {
+        let last = self.last_app.get();
+        let mut first: Option<AppId> = None;
+        let mut after: Option<AppId> = None;
+        for cntr in self.apps.iter() {
+            let appid = match cntr.enter(|app, _| app.pending_tx.map(|_| app.appid())) {
+                Some(appid) => appid,
+                None => continue,
+            };
+            if first.map_or(true, |id| appid.idx() < id.idx()) {
+                first = Some(appid);
+            }
+            if appid.idx() > last && after.map_or(true, |id| appid.idx() < id.idx()) {
+                after = Some(appid);
+            }
+        }
+        after.or(first)
+    }
+
+    /// Starts sending the next queued datagram, if none is being sent.
+    /// Apps whose datagram cannot be sent get a callback with the error.
+    fn start_next(&self) {
+        while self.current_app.get().is_none() {
+            let appid = match self.next_pending() {
+                Some(appid) => appid,
+                None => return,
+            };
+            let _ = self.apps.enter(appid, |app, _| {
+                let pending = match app.pending_tx.take() {
+                    Some(pending) => pending,
+                    None => return,
+                };
+                let rval = self.transmit(app, pending);
+                if rval == ReturnCode::SUCCESS {
+                    self.current_app.set(Some(appid));
+                } else {
+                    app.tx_callback.map(|mut cb| { cb.schedule(usize::from(rval), 0, 0); });
+                }
+            });
+            self.last_app.set(appid.idx());
+        }
+    }
+
+    /// Builds the IPv6 datagram for an app's payload in the kernel buffer
+    /// and passes it to 6LoWPAN.
+    fn transmit(&self, app: &mut App, pending: PendingTx) -> ReturnCode {
+        let src_port = match app.port {
+            Some(port) => port,
+            None => return ReturnCode::EINVAL,
+        };
+        let kbuf = match self.kernel_tx.take() {
+            Some(kbuf) => kbuf,
+            None => return ReturnCode::ENOMEM,
+        };
+        let udp_len = UDP_HEADER_SIZE + pending.len;
+        let len = IP6_HEADER_SIZE + udp_len;
+        let copied = match app.app_write {
+            Some(ref payload) if payload.len() >= pending.len && len <= kbuf.len() => {
+                let start = IP6_HEADER_SIZE + UDP_HEADER_SIZE;
+                kbuf[start..len].copy_from_slice(&payload.as_ref()[..pending.len]);
+                true
+            }
+            _ => false,
+        };
+        if !copied {
+            self.kernel_tx.replace(kbuf);
+            return ReturnCode::ESIZE;
+        }
+
+        kbuf[0] = 0x60;
+        kbuf[1] = 0;
+        kbuf[2] = 0;
+        kbuf[3] = 0;
+        write_be16(kbuf, 4, udp_len as u16);
+        kbuf[6] = IP6_NH_UDP;
+        kbuf[7] = HOP_LIMIT;
+        kbuf[8..24].copy_from_slice(&self.source_address(&pending.dst_addr));
+        kbuf[24..40].copy_from_slice(&pending.dst_addr);
+        write_be16(kbuf, IP6_HEADER_SIZE, src_port);
+        write_be16(kbuf, IP6_HEADER_SIZE + 2, pending.dst_port);
+        write_be16(kbuf, IP6_HEADER_SIZE + 4, udp_len as u16);
+        write_be16(kbuf, IP6_HEADER_SIZE + 6, 0);
+        // A zero checksum means "no checksum", which IPv6 does not allow
+        let sum = match checksum(&kbuf[8..24], &kbuf[24..40], &kbuf[IP6_HEADER_SIZE..len]) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        write_be16(kbuf, IP6_HEADER_SIZE + 6, sum);
+
+        match self.ip.send(pending.next_hop, kbuf, len) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((rval, kbuf)) => {
+                self.kernel_tx.replace(kbuf);
+                rval
+            }
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> Driver for UDPDriver<'a, R, A> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 | 3 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        match allow_num {
+                            0 => app.app_read = Some(slice),
+                            1 => app.app_write = Some(slice),
+                            2 => app.app_dst = Some(slice),
+                            _ => app.app_src = Some(slice),
+                        }
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
+        match subscribe_num {
+            0 /* datagram sent */ | 1 /* datagram received */ => {
+                self.apps.enter(callback.app_id(), |app, _| {
+                    if subscribe_num == 0 {
+                        app.tx_callback = Some(callback);
+                    } else {
+                        app.rx_callback = Some(callback);
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    // 0: check if present
+    // 1: bind to the port in the argument, or unbind if it is 0
+    // 2: send a datagram with the payload length in the argument
+    // 3: write this node's link-local address to the source buffer
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* bind */ => {
+                if arg1 > 0xffff {
+                    return ReturnCode::EINVAL;
+                }
+                let port = arg1 as u16;
+                if port != 0 {
+                    for cntr in self.apps.iter() {
+                        let taken = cntr.enter(|app, _| {
+                            app.port == Some(port) && app.appid().idx() != appid.idx()
+                        });
+                        if taken {
+                            return ReturnCode::EBUSY;
+                        }
+                    }
+                }
+                self.apps.enter(appid, |app, _| {
+                    app.port = if port == 0 { None } else { Some(port) };
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            2 /* send */ => {
+                let rval = self.apps.enter(appid, |app, _| {
+                    let sending = self.current_app
+                        .get()
+                        .map_or(false, |current| current.idx() == appid.idx());
+                    if app.pending_tx.is_some() || sending {
+                        return ReturnCode::EBUSY;
+                    } else if app.port.is_none() {
+                        return ReturnCode::EINVAL;
+                    }
+                    match app.app_write {
+                        Some(ref payload) if payload.len() >= arg1 => {}
+                        Some(_) => return ReturnCode::ESIZE,
+                        None => return ReturnCode::ERESERVE,
+                    }
+                    let mut dst_addr = [0; 16];
+                    let dst_port = match app.app_dst {
+                        Some(ref dst) if dst.len() >= ENDPOINT_SIZE => {
+                            dst_addr.copy_from_slice(&dst.as_ref()[..16]);
+                            read_be16(dst.as_ref(), 16)
+                        }
+                        _ => return ReturnCode::ERESERVE,
+                    };
+                    let next_hop = match self.next_hop(&dst_addr) {
+                        Some(next_hop) => next_hop,
+                        // Off-link, and there is no gateway to route through
+                        None => return ReturnCode::EINVAL,
+                    };
+                    app.pending_tx = Some(PendingTx {
+                        len: arg1,
+                        dst_addr: dst_addr,
+                        dst_port: dst_port,
+                        next_hop: next_hop,
+                    });
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                });
+                if rval == ReturnCode::SUCCESS {
+                    self.start_next();
+                }
+                rval
+            }
+            3 /* get local address */ => {
+                let addr = self.local_address();
+                self.apps.enter(appid, |app, _| {
+                    match app.app_src {
+                        Some(ref mut src) if src.len() >= 16 => {
+                            src.as_mut()[..16].copy_from_slice(&addr);
+                            ReturnCode::SUCCESS
+                        }
+                        _ => ReturnCode::ERESERVE,
+                    }
+                }).unwrap_or_else(|err| match err {
+                    Error::OutOfMemory => ReturnCode::ENOMEM,
+                    Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                    Error::NoSuchApp => ReturnCode::EINVAL,
+                })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm> sixlowpan::Client for UDPDriver<'a, R, A> {
+    fn send_done(&self, datagram: &'static mut [u8], result: ReturnCode) {
+        self.kernel_tx.replace(datagram);
+        self.current_app.get().map(|appid| {
+            self.current_app.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback.map(|mut cb| { cb.schedule(usize::from(result), 0, 0); });
+            });
+        });
+        self.start_next();
+    }
+
+    fn receive(&self, datagram: &[u8], _src: MacAddress, dst: MacAddress) {
+        if datagram.len() < IP6_HEADER_SIZE + UDP_HEADER_SIZE || datagram[6] != IP6_NH_UDP {
+            return;
+        }
+        // Accept datagrams to multicast addresses, to our link-local and
+        // global addresses and to the link-local address of the MAC address
+        // the frames were sent to
+        let dst_addr = &datagram[24..40];
+        let global = self.global_address().map_or(false, |addr| dst_addr == &addr[..]);
+        if dst_addr[0] != 0xff && dst_addr != &self.local_address()[..] && !global &&
+           dst_addr != &sixlowpan::link_local_address(&dst)[..] {
+            return;
+        }
+        let udp_len = read_be16(datagram, IP6_HEADER_SIZE + 4) as usize;
+        if udp_len < UDP_HEADER_SIZE || IP6_HEADER_SIZE + udp_len > datagram.len() {
+            return;
+        }
+        let udp = &datagram[IP6_HEADER_SIZE..IP6_HEADER_SIZE + udp_len];
+        if read_be16(udp, 6) == 0 || checksum(&datagram[8..24], dst_addr, udp) != 0 {
+            return;
+        }
+
+        let src_port = read_be16(udp, 0);
+        let dst_port = read_be16(udp, 2);
+        let payload = &udp[UDP_HEADER_SIZE..];
+        self.apps.each(|app| {
+            if app.port != Some(dst_port) {
+                return;
+            }
+            app.app_read.as_mut().map(|dest| {
+                for (to, from) in dest.as_mut().iter_mut().zip(payload.iter()) {
+                    *to = *from;
+                }
+            });
+            app.app_src.as_mut().map(|src| if src.len() >= ENDPOINT_SIZE {
+                let src = src.as_mut();
+                src[..16].copy_from_slice(&datagram[8..24]);
+                write_be16(src, 16, src_port);
+            });
+            app.rx_callback.map(|mut cb| { cb.schedule(payload.len(), src_port as usize, 0); });
+        });
+    }
+}
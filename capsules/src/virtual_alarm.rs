@@ -172,3 +172,126 @@ impl<'a, Alrm: Alarm> time::Client for MuxAlarm<'a, Alrm> {
         next.map(|valrm| self.alarm.set_alarm(valrm.when.get()));
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use core::cell::Cell;
+    use kernel::hil::time::{self, Alarm, Frequency, Time};
+    use std::vec::Vec;
+    use super::{MuxAlarm, VirtualMuxAlarm};
+
+    /// Frequency of `ManualAlarm`: one tic per microsecond.
+    pub struct Freq1MHz;
+
+    impl Frequency for Freq1MHz {
+        fn frequency() -> u32 {
+            1000000
+        }
+    }
+
+    /// An alarm whose counter only moves when a test advances it.
+    pub struct ManualAlarm<'a> {
+        now: Cell<u32>,
+        when: Cell<u32>,
+        armed: Cell<bool>,
+        client: Cell<Option<&'a time::Client>>,
+    }
+
+    impl<'a> ManualAlarm<'a> {
+        pub fn new() -> ManualAlarm<'a> {
+            ManualAlarm {
+                now: Cell::new(0),
+                when: Cell::new(0),
+                armed: Cell::new(false),
+                client: Cell::new(None),
+            }
+        }
+
+        pub fn set_client(&self, client: &'a time::Client) {
+            self.client.set(Some(client));
+        }
+
+        /// Moves the counter forward by `tics`, stopping at the alarm to
+        /// fire the client each time the counter reaches it.
+        pub fn advance(&self, tics: u32) {
+            let end = self.now.get().wrapping_add(tics);
+            while self.armed.get() &&
+                  self.when.get().wrapping_sub(self.now.get()) <= end.wrapping_sub(self.now.get()) {
+                self.now.set(self.when.get());
+                self.armed.set(false);
+                self.client.get().map(|client| client.fired());
+            }
+            self.now.set(end);
+        }
+    }
+
+    impl<'a> Time for ManualAlarm<'a> {
+        fn disable(&self) {
+            self.armed.set(false);
+        }
+
+        fn is_armed(&self) -> bool {
+            self.armed.get()
+        }
+    }
+
+    impl<'a> Alarm for ManualAlarm<'a> {
+        type Frequency = Freq1MHz;
+
+        fn now(&self) -> u32 {
+            self.now.get()
+        }
+
+        fn set_alarm(&self, tics: u32) {
+            self.when.set(tics);
+            self.armed.set(true);
+        }
+
+        fn get_alarm(&self) -> u32 {
+            self.when.get()
+        }
+    }
+
+    /// Records the times at which it fired.
+    struct Recorder<'a> {
+        alarm: &'a ManualAlarm<'a>,
+        fired: Cell<Option<u32>>,
+    }
+
+    impl<'a> time::Client for Recorder<'a> {
+        fn fired(&self) {
+            self.fired.set(Some(self.alarm.now()));
+        }
+    }
+
+    #[test]
+    fn virtual_alarms_fire_in_order() {
+        let alarm = ManualAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_client(&mux);
+        let virtual_alarms: Vec<_> = (0..3).map(|_| VirtualMuxAlarm::new(&mux)).collect();
+        let recorders: Vec<_> = (0..3)
+            .map(|_| {
+                Recorder {
+                    alarm: &alarm,
+                    fired: Cell::new(None),
+                }
+            })
+            .collect();
+        for (virtual_alarm, recorder) in virtual_alarms.iter().zip(recorders.iter()) {
+            virtual_alarm.set_client(recorder);
+        }
+
+        virtual_alarms[0].set_alarm(3000);
+        virtual_alarms[1].set_alarm(1000);
+        virtual_alarms[2].set_alarm(2000);
+        virtual_alarms[2].disable();
+        alarm.advance(5000);
+
+        assert_eq!(recorders[0].fired.get(), Some(3000));
+        assert_eq!(recorders[1].fired.get(), Some(1000));
+        assert_eq!(recorders[2].fired.get(), None);
+        assert!(virtual_alarms.iter().all(|virtual_alarm| !virtual_alarm.is_armed()));
+        assert!(!alarm.is_armed());
+    }
+}
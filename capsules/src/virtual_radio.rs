@@ -0,0 +1,416 @@
+//! Virtualize an 802.15.4 radio so that several capsules can use it, for
+//! example the radio system call driver and a 6LoWPAN stack.
+//!
+//! Each `VirtualRadio` implements `hil::radio::Radio`. Every user may have
+//! one frame waiting to be sent; the mux sends them one at a time, and hands a
+//! frame back with `EOFF` if the radio was stopped while it waited. Each
+//! received frame is copied into the receive buffer of every user that has
+//! one. Addresses, PAN, channel, power, retries and whether the radio is on
+//! are shared by all users, so setting them through one user changes them
+//! for all.
+//!
+//! ```ignore
+//! let mux_radio = static_init!(MuxRadio<'static, Radio>, MuxRadio::new(rf233), 12);
+//! rf233.set_transmit_client(mux_radio);
+//! rf233.set_receive_client(mux_radio, &mut RF233_RX_BUF);
+//! rf233.set_energy_client(mux_radio);
+//! rf233.set_power_client(mux_radio);
+//!
+//! let radio_user = static_init!(VirtualRadio<'static, Radio>, VirtualRadio::new(mux_radio), 64);
+//! radio_user.init();
+//! radio_user.set_transmit_client(radio_capsule);
+//! radio_user.set_receive_client(radio_capsule, &mut RADIO_RX_BUF);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::radio;
+use kernel::returncode::ReturnCode;
+
+pub struct MuxRadio<'a, R: radio::Radio + 'a> {
+    radio: &'a R,
+    users: List<'a, VirtualRadio<'a, R>>,
+    inflight: Cell<Option<&'a VirtualRadio<'a, R>>>,
+}
+
+impl<'a, R: radio::Radio> MuxRadio<'a, R> {
+    pub const fn new(radio: &'a R) -> MuxRadio<'a, R> {
+        MuxRadio {
+            radio: radio,
+            users: List::new(),
+            inflight: Cell::new(None),
+        }
+    }
+
+    fn do_next_op(&self) {
+        // A user's send_done may queue another frame and start it
+        while self.inflight.get().is_none() {
+            let user = match self.users.iter().find(|user| user.tx_buf.is_some()) {
+                Some(user) => user,
+                None => return,
+            };
+            let buf = match user.tx_buf.take() {
+                Some(buf) => buf,
+                None => return,
+            };
+            if !self.radio.ready() {
+                // The radio was stopped while the frame was queued
+                user.send_done(buf, ReturnCode::EOFF);
+                continue;
+            }
+            self.inflight.set(Some(user));
+            let rval = self.radio.transmit(buf, user.tx_len.get());
+            if rval != ReturnCode::SUCCESS {
+                // A failed transmission consumes the frame; let the
+                // user send another
+                self.inflight.set(None);
+                user.transmitting.set(false);
+            }
+        }
+    }
+}
+
+impl<'a, R: radio::Radio> radio::TxClient for MuxRadio<'a, R> {
+    fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
+        self.inflight.get().map(move |user| {
+            self.inflight.set(None);
+            self.do_next_op();
+            user.send_done(buf, result);
+        });
+    }
+}
+
+impl<'a, R: radio::Radio> radio::RxClient for MuxRadio<'a, R> {
+    fn receive(&self,
+               buf: &'static mut [u8],
+               frame_len: u8,
+               info: radio::RxInfo,
+               result: ReturnCode) {
+        let len = min(buf.len(), self.radio.psdu_offset() as usize + frame_len as usize);
+        for user in self.users.iter() {
+            let client = match user.rx_client.get() {
+                Some(client) => client,
+                None => continue,
+            };
+            // Users that are still holding the previous frame miss this one
+            if let Some(rx_buf) = user.rx_buf.take() {
+                if rx_buf.len() < len {
+                    user.rx_buf.replace(rx_buf);
+                    continue;
+                }
+                rx_buf[..len].copy_from_slice(&buf[..len]);
+                client.receive(rx_buf, frame_len, info, result);
+            }
+        }
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+impl<'a, R: radio::Radio> radio::PowerClient for MuxRadio<'a, R> {
+    fn changed(&self, on: bool) {
+        for user in self.users.iter() {
+            user.power_client.get().map(|client| client.changed(on));
+        }
+    }
+}
+
+impl<'a, R: radio::Radio> radio::EnergyClient for MuxRadio<'a, R> {
+    fn energy_detect_done(&self, rssi: i8, clear: bool, result: ReturnCode) {
+        for user in self.users.iter() {
+            if user.energy_pending.get() {
+                user.energy_pending.set(false);
+                user.energy_client
+                    .get()
+                    .map(|client| client.energy_detect_done(rssi, clear, result));
+            }
+        }
+    }
+}
+
+pub struct VirtualRadio<'a, R: radio::Radio + 'a> {
+    mux: &'a MuxRadio<'a, R>,
+    next: ListLink<'a, VirtualRadio<'a, R>>,
+    tx_client: Cell<Option<&'static radio::TxClient>>,
+    rx_client: Cell<Option<&'static radio::RxClient>>,
+    energy_client: Cell<Option<&'static radio::EnergyClient>>,
+    power_client: Cell<Option<&'static radio::PowerClient>>,
+    // A frame waiting to be sent, and whether one is queued or in flight
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<u8>,
+    transmitting: Cell<bool>,
+    rx_buf: TakeCell<'static, [u8]>,
+    energy_pending: Cell<bool>,
+}
+
+impl<'a, R: radio::Radio> VirtualRadio<'a, R> {
+    pub const fn new(mux: &'a MuxRadio<'a, R>) -> VirtualRadio<'a, R> {
+        VirtualRadio {
+            mux: mux,
+            next: ListLink::empty(),
+            tx_client: Cell::new(None),
+            rx_client: Cell::new(None),
+            energy_client: Cell::new(None),
+            power_client: Cell::new(None),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            transmitting: Cell::new(false),
+            rx_buf: TakeCell::empty(),
+            energy_pending: Cell::new(false),
+        }
+    }
+
+    /// Adds this user to the mux.
+    pub fn init(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
+        self.transmitting.set(false);
+        self.tx_client.get().map(move |client| client.send_done(buf, result));
+    }
+}
+
+impl<'a, R: radio::Radio> ListNode<'a, VirtualRadio<'a, R>> for VirtualRadio<'a, R> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualRadio<'a, R>> {
+        &self.next
+    }
+}
+
+impl<'a, R: radio::Radio> radio::Radio for VirtualRadio<'a, R> {
+    /// The board initializes the underlying radio.
+    fn initialize(&self,
+                  _spi_buf: &'static mut [u8],
+                  _reg_write: &'static mut [u8],
+                  _reg_read: &'static mut [u8])
+                  -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.mux.radio.start()
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.mux.radio.stop()
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.mux.radio.reset()
+    }
+
+    fn ready(&self) -> bool {
+        self.mux.radio.ready()
+    }
+
+    fn set_transmit_client(&self, client: &'static radio::TxClient) {
+        self.tx_client.set(Some(client));
+    }
+
+    fn set_receive_client(&self,
+                          client: &'static radio::RxClient,
+                          receive_buffer: &'static mut [u8]) {
+        self.rx_client.set(Some(client));
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn set_energy_client(&self, client: &'static radio::EnergyClient) {
+        self.energy_client.set(Some(client));
+    }
+
+    fn set_power_client(&self, client: &'static radio::PowerClient) {
+        self.power_client.set(Some(client));
+    }
+
+    fn set_address(&self, addr: u16) -> ReturnCode {
+        self.mux.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) -> ReturnCode {
+        self.mux.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, addr: u16) -> ReturnCode {
+        self.mux.radio.set_pan(addr)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.mux.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.mux.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.mux.radio.get_pan()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.mux.radio.set_channel(chan)
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.mux.radio.get_channel()
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.mux.radio.set_tx_power(power)
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.mux.radio.get_tx_power()
+    }
+
+    fn set_max_retries(&self, retries: u8) -> ReturnCode {
+        self.mux.radio.set_max_retries(retries)
+    }
+
+    fn get_max_retries(&self) -> u8 {
+        self.mux.radio.get_max_retries()
+    }
+
+    fn psdu_offset(&self) -> u8 {
+        self.mux.radio.psdu_offset()
+    }
+
+    fn transmit(&self, frame: &'static mut [u8], frame_len: u8) -> ReturnCode {
+        if !self.mux.radio.ready() {
+            return ReturnCode::EOFF;
+        } else if self.transmitting.get() {
+            return ReturnCode::EBUSY;
+        } else if self.psdu_offset() as usize + frame_len as usize > frame.len() ||
+                  frame_len > radio::MAX_FRAME_SIZE - 2 {
+            return ReturnCode::ESIZE;
+        }
+        self.tx_buf.replace(frame);
+        self.tx_len.set(frame_len);
+        self.transmitting.set(true);
+        self.mux.do_next_op();
+        ReturnCode::SUCCESS
+    }
+
+    fn energy_detect(&self) -> ReturnCode {
+        let rval = self.mux.radio.energy_detect();
+        if rval == ReturnCode::SUCCESS {
+            self.energy_pending.set(true);
+        }
+        rval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use kernel::hil::radio::Radio;
+    use kernel::hil::time::{Alarm, Time};
+    use sim_radio::{SimMedium, SimRadio};
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use super::*;
+    use virtual_alarm::tests::ManualAlarm;
+
+    type Sim = SimRadio<'static, ManualAlarm<'static>>;
+
+    fn leak<T>(value: T) -> &'static T {
+        unsafe { &*Box::into_raw(Box::new(value)) }
+    }
+
+    fn buffer() -> &'static mut [u8] {
+        unsafe { &mut *Box::into_raw(vec![0; radio::MAX_BUF_SIZE].into_boxed_slice()) }
+    }
+
+    /// Keeps the results of a user's transmissions and its frame buffer.
+    struct Client {
+        sent: RefCell<Vec<ReturnCode>>,
+        tx_buf: TakeCell<'static, [u8]>,
+    }
+
+    impl radio::TxClient for Client {
+        fn send_done(&self, buf: &'static mut [u8], result: ReturnCode) {
+            self.tx_buf.replace(buf);
+            self.sent.borrow_mut().push(result);
+        }
+    }
+
+    /// A simulated radio that is on, shared by `count` users.
+    fn users(count: usize) -> (&'static ManualAlarm<'static>,
+                               &'static Sim,
+                               Vec<(&'static VirtualRadio<'static, Sim>, &'static Client)>) {
+        let alarm = leak(ManualAlarm::new());
+        let medium = leak(SimMedium::new(alarm));
+        alarm.set_client(medium);
+        let sim = leak(SimRadio::new(medium, 1));
+        sim.init();
+        let mux = leak(MuxRadio::new(sim));
+        sim.set_transmit_client(mux);
+        let users = (0..count)
+            .map(|_| {
+                let user = leak(VirtualRadio::new(mux));
+                let client = leak(Client {
+                    sent: RefCell::new(Vec::new()),
+                    tx_buf: TakeCell::new(buffer()),
+                });
+                user.init();
+                user.set_transmit_client(client);
+                (user, client)
+            })
+            .collect();
+        assert_eq!(sim.start(), ReturnCode::SUCCESS);
+        alarm.advance(1);
+        assert!(sim.ready());
+        (alarm, sim, users)
+    }
+
+    /// Lets every pending event of the medium happen.
+    fn run(alarm: &ManualAlarm) {
+        while alarm.is_armed() {
+            alarm.advance(alarm.get_alarm().wrapping_sub(alarm.now()));
+        }
+    }
+
+    fn send(user: &VirtualRadio<'static, Sim>, client: &Client) -> ReturnCode {
+        user.transmit(client.tx_buf.take().unwrap(), 10)
+    }
+
+    #[test]
+    fn frames_are_sent_in_turn() {
+        let (alarm, _, users) = users(2);
+        for &(user, client) in users.iter() {
+            assert_eq!(send(user, client), ReturnCode::SUCCESS);
+        }
+        run(alarm);
+        for &(_, client) in users.iter() {
+            assert_eq!(*client.sent.borrow(), [ReturnCode::SUCCESS]);
+        }
+    }
+
+    #[test]
+    fn queued_frame_is_returned_when_radio_stops() {
+        let (alarm, sim, users) = users(2);
+        let (first, first_client) = users[0];
+        let (second, second_client) = users[1];
+        assert_eq!(send(first, first_client), ReturnCode::SUCCESS);
+        assert_eq!(send(second, second_client), ReturnCode::SUCCESS);
+
+        // The frame in flight is still sent, the queued one is handed back
+        assert_eq!(sim.stop(), ReturnCode::SUCCESS);
+        run(alarm);
+        assert_eq!(*first_client.sent.borrow(), [ReturnCode::SUCCESS]);
+        assert_eq!(*second_client.sent.borrow(), [ReturnCode::EOFF]);
+        assert!(!sim.ready());
+
+        // Both users can send again once the radio is back on
+        assert_eq!(sim.start(), ReturnCode::SUCCESS);
+        run(alarm);
+        assert_eq!(send(second, second_client), ReturnCode::SUCCESS);
+        run(alarm);
+        assert_eq!(*second_client.sent.borrow(),
+                   [ReturnCode::EOFF, ReturnCode::SUCCESS]);
+    }
+}
@@ -0,0 +1,159 @@
+//! Virtualize a UART so that several capsules can use it, for example the
+//! console and the kernel's `debug!` output.
+//!
+//! Each `UartDevice` implements `hil::uart::UART` and
+//! `hil::uart::UARTAdvanced`. Every device may have one buffer waiting to be
+//! transmitted; the mux transmits them one at a time, so output from
+//! different devices is never mixed within a buffer. Receptions are passed
+//! to the UART directly, and a new reception replaces the one in progress,
+//! whichever device started it. The UART's settings are shared: `init` on
+//! any device configures it for all.
+//!
+//! ```ignore
+//! let uart_mux = static_init!(MuxUart<'static, usart::USART>,
+//!                             MuxUart::new(&usart::USART0),
+//!                             96/8);
+//! hil::uart::UART::set_client(&usart::USART0, uart_mux);
+//!
+//! let console_uart = static_init!(UartDevice<'static, usart::USART>,
+//!                                 UartDevice::new(uart_mux),
+//!                                 256/8);
+//! console_uart.init();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart::{self, UARTAdvanced};
+
+pub struct MuxUart<'a, U: UARTAdvanced + 'a> {
+    uart: &'a U,
+    devices: List<'a, UartDevice<'a, U>>,
+    inflight: Cell<Option<&'a UartDevice<'a, U>>>,
+}
+
+impl<'a, U: UARTAdvanced> MuxUart<'a, U> {
+    pub const fn new(uart: &'a U) -> MuxUart<'a, U> {
+        MuxUart {
+            uart: uart,
+            devices: List::new(),
+            inflight: Cell::new(None),
+        }
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.get().is_some() {
+            return;
+        }
+        let next = self.devices.iter().find(|device| device.tx_buffer.is_some());
+        next.map(|device| {
+            device.tx_buffer.take().map(|buf| {
+                self.inflight.set(Some(device));
+                self.uart.transmit(buf, device.tx_len.get());
+            });
+        });
+    }
+}
+
+impl<'a, U: UARTAdvanced> uart::Client for MuxUart<'a, U> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        self.inflight.get().map(move |device| {
+            self.inflight.set(None);
+            self.do_next_op();
+            device.client.get().map(move |client| client.transmit_complete(tx_buffer, error));
+        });
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        let receiver = self.devices.iter().find(|device| device.receiving.get());
+        receiver.map(move |device| {
+            device.receiving.set(false);
+            device.client
+                .get()
+                .map(move |client| client.receive_complete(rx_buffer, rx_len, error));
+        });
+    }
+}
+
+pub struct UartDevice<'a, U: UARTAdvanced + 'a> {
+    mux: &'a MuxUart<'a, U>,
+    next: ListLink<'a, UartDevice<'a, U>>,
+    client: Cell<Option<&'static uart::Client>>,
+    // A buffer waiting to be transmitted
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    receiving: Cell<bool>,
+}
+
+impl<'a, U: UARTAdvanced> UartDevice<'a, U> {
+    pub const fn new(mux: &'a MuxUart<'a, U>) -> UartDevice<'a, U> {
+        UartDevice {
+            mux: mux,
+            next: ListLink::empty(),
+            client: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            receiving: Cell::new(false),
+        }
+    }
+
+    /// Adds the device to its mux.
+    pub fn init(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+
+    /// Marks this device as the one receiving. Called after the reception
+    /// has been passed to the UART, so that a reception it replaces is
+    /// reported to the device that started it.
+    fn start_receiving(&self) {
+        for device in self.mux.devices.iter() {
+            device.receiving.set(false);
+        }
+        self.receiving.set(true);
+    }
+}
+
+impl<'a, U: UARTAdvanced> ListNode<'a, UartDevice<'a, U>> for UartDevice<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, UartDevice<'a, U>> {
+        &self.next
+    }
+}
+
+impl<'a, U: UARTAdvanced> uart::UART for UartDevice<'a, U> {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self, params: uart::UARTParams) {
+        self.mux.uart.init(params);
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        // Like the UART itself, a second transmission replaces the first
+        self.tx_buffer.take().map(|buf| {
+            self.client
+                .get()
+                .map(move |client| client.transmit_complete(buf, uart::Error::RepeatCallError));
+        });
+        self.tx_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.mux.do_next_op();
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.mux.uart.receive(rx_buffer, rx_len);
+        self.start_receiving();
+    }
+}
+
+impl<'a, U: UARTAdvanced> uart::UARTAdvanced for UartDevice<'a, U> {
+    fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8) {
+        self.mux.uart.receive_automatic(rx_buffer, interbyte_timeout);
+        self.start_receiving();
+    }
+
+    fn receive_until_terminator(&self, rx_buffer: &'static mut [u8], terminator: u8) {
+        self.mux.uart.receive_until_terminator(rx_buffer, terminator);
+        self.start_receiving();
+    }
+}
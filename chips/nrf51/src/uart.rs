@@ -1,5 +1,6 @@
 use chip;
 use core::cell::Cell;
+use core::cmp;
 use core::mem;
 use kernel::common::VolatileCell;
 use kernel::common::take_cell::TakeCell;
@@ -58,6 +59,10 @@ pub struct UART {
     buffer: TakeCell<'static, [u8]>,
     len: Cell<usize>,
     index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    rx_terminator: Cell<Option<u8>>,
 }
 
 #[derive(Copy, Clone)]
@@ -80,6 +85,10 @@ impl UART {
             buffer: TakeCell::empty(),
             len: Cell::new(0),
             index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_terminator: Cell::new(None),
         }
     }
 
@@ -149,15 +158,14 @@ impl UART {
 
     pub fn handle_interrupt(&mut self) {
         let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        // let rx = regs.event_rxdrdy.get() != 0;
+        let rx = regs.event_rxdrdy.get() != 0;
         let tx = regs.event_txdrdy.get() != 0;
 
-        // if rx {
-        //     let val = regs.rxd.get();
-        //     self.client.map(|client| {
-        //         client.read_done(val as u8);
-        //     });
-        // }
+        if rx {
+            regs.event_rxdrdy.set(0 as u32);
+            let byte = regs.rxd.get() as u8;
+            self.receive_byte(byte);
+        }
         if tx {
             regs.event_txdrdy.set(0 as u32);
 
@@ -183,6 +191,51 @@ impl UART {
         }
     }
 
+    /// Stores a received byte, finishing the reception when the buffer is
+    /// full or at the terminator.
+    fn receive_byte(&self, byte: u8) {
+        let index = self.rx_index.get();
+        let done = self.rx_buffer.map_or(false, |buffer| {
+            if index < self.rx_len.get() {
+                buffer[index] = byte;
+                self.rx_index.set(index + 1);
+            }
+            self.rx_index.get() >= self.rx_len.get() || self.rx_terminator.get() == Some(byte)
+        });
+        if done {
+            let regs: &Registers = unsafe { mem::transmute(self.regs) };
+            regs.task_stoprx.set(1 as u32);
+            self.disable_rx_interrupts();
+            self.client.get().map(|client| {
+                self.rx_buffer.take().map(|buffer| {
+                    client.receive_complete(buffer,
+                                            self.rx_index.get(),
+                                            uart::Error::CommandComplete);
+                });
+            });
+        }
+    }
+
+    fn start_receive(&self, rx_buffer: &'static mut [u8], rx_len: usize, terminator: Option<u8>) {
+        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+        // Hand back the buffer of a reception this one replaces, as the
+        // sam4l USART does
+        self.rx_buffer.take().map(|buffer| {
+            self.client.get().map(|client| {
+                client.receive_complete(buffer, self.rx_index.get(), uart::Error::RepeatCallError);
+            });
+        });
+        self.rx_len.set(cmp::min(rx_len, rx_buffer.len()));
+        self.rx_index.set(0);
+        self.rx_terminator.set(terminator);
+        self.rx_buffer.replace(rx_buffer);
+
+        regs.event_rxdrdy.set(0);
+        self.enable_rx_interrupts();
+        regs.task_startrx.set(1);
+        self.enable_nvic();
+    }
+
     pub unsafe fn send_byte(&self, byte: u8) {
         let regs: &mut Registers = mem::transmute(self.regs);
 
@@ -200,11 +253,6 @@ impl UART {
         let regs: &Registers = unsafe { mem::transmute(self.regs) };
         regs.event_txdrdy.get() & 0b1 != 0
     }
-
-    fn rx_ready(&self) -> bool {
-        let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        regs.event_rxdrdy.get() & 0b1 != 0
-    }
 }
 
 impl uart::UART for UART {
@@ -236,14 +284,20 @@ impl uart::UART for UART {
     }
 
     fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
-        let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        regs.task_startrx.set(1);
-        let mut i = 0;
-        while i < rx_len {
-            while !self.rx_ready() {}
-            rx_buffer[i] = regs.rxd.get() as u8;
-            i += 1;
-        }
+        self.start_receive(rx_buffer, rx_len, None);
+    }
+}
+
+impl uart::UARTAdvanced for UART {
+    // There is no receive timeout, so this waits for a full buffer
+    fn receive_automatic(&self, rx_buffer: &'static mut [u8], _interbyte_timeout: u8) {
+        let len = rx_buffer.len();
+        self.start_receive(rx_buffer, len, None);
+    }
+
+    fn receive_until_terminator(&self, rx_buffer: &'static mut [u8], terminator: u8) {
+        let len = rx_buffer.len();
+        self.start_receive(rx_buffer, len, Some(terminator));
     }
 }
 
@@ -25,8 +25,11 @@
 
 use core::cell::Cell;
 use core::mem;
+use kernel::ReturnCode;
 use kernel::common::VolatileCell;
-use kernel::common::take_cell::MapCell;
+use kernel::common::deferred_call::{self, DeferredCall};
+use kernel::common::take_cell::{MapCell, TakeCell};
+use kernel::hil::flash;
 use nvic;
 use pm;
 
@@ -150,6 +153,16 @@ pub enum FlashState {
     Unconfigured, // The Flash is unconfigured, call configure()
 }
 
+/// The `hil::flash` operation in progress, if any.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+    /// Erasing `page`, and then the pages up to `end`
+    Erase { page: i32, end: i32 },
+}
+
 // The FLASHCALW controller
 pub struct FLASHCALW {
     registers: *mut Registers,
@@ -163,6 +176,12 @@ pub struct FLASHCALW {
     current_state: Cell<FlashState>,
     current_command: Cell<Command>,
     page_buffer: MapCell<[u8; PAGE_SIZE as usize]>,
+    flash_client: Cell<Option<&'static flash::Client>>,
+    operation: Cell<Operation>,
+    /// The buffer of the `hil::flash` read or write in progress
+    buffer: TakeCell<'static, [u8]>,
+    /// Completes reads, which need no flash command
+    deferred_call: Cell<Option<&'static DeferredCall>>,
 }
 
 // static instance for the board. Only one FLASHCALW on chip.
@@ -219,6 +238,10 @@ impl FLASHCALW {
             current_state: Cell::new(FlashState::Unconfigured),
             current_command: Cell::new(Command::None),
             page_buffer: MapCell::new([0; PAGE_SIZE as usize]),
+            flash_client: Cell::new(None),
+            operation: Cell::new(Operation::None),
+            buffer: TakeCell::empty(),
+            deferred_call: Cell::new(None),
         }
     }
 
@@ -295,6 +318,8 @@ impl FLASHCALW {
                     _ => {}
                 }
             });
+            self.operation_complete(ReturnCode::FAIL);
+            return;
         }
 
         //  Part of a command succeeded -- continue onto next steps.
@@ -362,6 +387,40 @@ impl FLASHCALW {
         if self.current_command.get() == Command::None &&
            self.current_state.get() == FlashState::Ready {
             self.client.get().map(|value| { value.command_complete(Error::CommandComplete); });
+            self.operation_complete(ReturnCode::SUCCESS);
+        }
+    }
+
+    /// Reports the end of a `hil::flash` operation, or moves on to the next
+    /// page of an erase.
+    fn operation_complete(&self, result: ReturnCode) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::None);
+        match operation {
+            Operation::None => {}
+            Operation::Read => {
+                self.buffer.take().map(|buffer| {
+                    self.flash_client.get().map(move |client| client.read_complete(buffer, result));
+                });
+            }
+            Operation::Write => {
+                self.buffer.take().map(|buffer| {
+                    self.flash_client
+                        .get()
+                        .map(move |client| client.write_complete(buffer, result));
+                });
+            }
+            Operation::Erase { page, end } => {
+                if result == ReturnCode::SUCCESS && page + 1 < end {
+                    self.operation.set(Operation::Erase {
+                        page: page + 1,
+                        end: end,
+                    });
+                    self.erase_page(page + 1);
+                } else {
+                    self.flash_client.get().map(|client| client.erase_complete(result));
+                }
+            }
         }
     }
 
@@ -789,6 +848,19 @@ impl FLASHCALW {
         self.current_state.set(FlashState::Ready);
     }
 
+    /// Gives the controller the deferred call it completes `hil::flash`
+    /// reads with.
+    pub fn set_deferred_call(&self, deferred_call: &'static DeferredCall) {
+        self.deferred_call.set(Some(deferred_call));
+    }
+
+    /// Whether `len` bytes at `address` are whole pages within the flash.
+    fn whole_pages(&self, address: usize, len: usize) -> bool {
+        let page_size = PAGE_SIZE as usize;
+        address % page_size == 0 && len % page_size == 0 && len > 0 &&
+        address.checked_add(len).map_or(false, |end| end <= self.get_flash_size() as usize)
+    }
+
     pub fn get_page_size(&self) -> u32 {
         PAGE_SIZE
     }
@@ -878,3 +950,73 @@ pub unsafe extern "C" fn flash_handler() {
     nvic::disable(nvic::NvicIdx::HFLASHC);
     chip::INTERRUPT_QUEUE.as_mut().unwrap().enqueue(nvic::NvicIdx::HFLASHC);
 }
+
+impl flash::Flash for FLASHCALW {
+    fn set_client(&self, client: &'static flash::Client) {
+        self.flash_client.set(Some(client));
+    }
+
+    fn read(&self,
+            offset: usize,
+            buf: &'static mut [u8])
+            -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let deferred_call = match self.deferred_call.get() {
+            Some(deferred_call) => deferred_call,
+            None => return Err((ReturnCode::ENOSUPPORT, buf)),
+        };
+        if self.operation.get() != Operation::None {
+            return Err((ReturnCode::EBUSY, buf));
+        }
+        let len = buf.len();
+        if FLASHCALW::read(self, offset, len, buf) != 0 {
+            return Err((ReturnCode::EINVAL, buf));
+        }
+        self.buffer.replace(buf);
+        self.operation.set(Operation::Read);
+        deferred_call.set();
+        Ok(())
+    }
+
+    fn write(&self,
+             offset: usize,
+             buf: &'static mut [u8])
+             -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.operation.get() != Operation::None ||
+           self.current_state.get() != FlashState::Ready {
+            return Err((ReturnCode::EBUSY, buf));
+        }
+        if buf.len() != PAGE_SIZE as usize || !self.whole_pages(offset, buf.len()) {
+            return Err((ReturnCode::EINVAL, buf));
+        }
+        // Erases the page before writing it
+        self.write_page((offset / PAGE_SIZE as usize) as i32, buf);
+        self.buffer.replace(buf);
+        self.operation.set(Operation::Write);
+        Ok(())
+    }
+
+    fn erase(&self, offset: usize, len: usize) -> ReturnCode {
+        if self.operation.get() != Operation::None ||
+           self.current_state.get() != FlashState::Ready {
+            return ReturnCode::EBUSY;
+        }
+        if !self.whole_pages(offset, len) {
+            return ReturnCode::EINVAL;
+        }
+        let page = (offset / PAGE_SIZE as usize) as i32;
+        self.operation.set(Operation::Erase {
+            page: page,
+            end: page + (len / PAGE_SIZE as usize) as i32,
+        });
+        self.erase_page(page);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl deferred_call::Client for FLASHCALW {
+    fn call(&self) {
+        if self.operation.get() == Operation::Read {
+            self.operation_complete(ReturnCode::SUCCESS);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use core::cell::Cell;
 use core::cmp;
 use core::mem;
 use dma;
+use kernel::common::take_cell::TakeCell;
 use kernel::common::volatile_cell::VolatileCell;
 // other modules
 use kernel::hil;
@@ -49,6 +50,7 @@ const USART_BASE_ADDRS: [*mut USARTRegisters; 4] = [0x40024000 as *mut USARTRegi
 pub enum USARTStateRX {
     Idle,
     DMA_Receiving,
+    Interrupt_Receiving, // One byte per RXRDY interrupt, to find a terminator
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -85,6 +87,10 @@ pub struct USART {
     rx_dma: Cell<Option<&'static dma::DMAChannel>>,
     rx_dma_peripheral: dma::DMAPeripheral,
     rx_len: Cell<usize>,
+    // Reception without DMA
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_index: Cell<usize>,
+    rx_terminator: Cell<u8>,
     tx_dma: Cell<Option<&'static dma::DMAChannel>>,
     tx_dma_peripheral: dma::DMAPeripheral,
     tx_len: Cell<usize>,
@@ -137,6 +143,9 @@ impl USART {
             rx_dma: Cell::new(None),
             rx_dma_peripheral: rx_dma_peripheral,
             rx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_index: Cell::new(0),
+            rx_terminator: Cell::new(0),
             tx_dma: Cell::new(None),
             tx_dma_peripheral: tx_dma_peripheral,
             tx_len: Cell::new(0),
@@ -207,6 +216,43 @@ impl USART {
                     UsartClient::SpiMaster(_) => {}
                 });
             });
+        } else if self.usart_rx_state.get() == USARTStateRX::Interrupt_Receiving {
+            self.disable_rx();
+            self.disable_rx_interrupts();
+            self.finish_rx_interrupt(error);
+        }
+    }
+
+    /// Passes the bytes received so far without DMA to the client.
+    fn finish_rx_interrupt(&self, error: hil::uart::Error) {
+        self.usart_rx_state.set(USARTStateRX::Idle);
+        let length = self.rx_index.get();
+        self.rx_index.set(0);
+        self.client.get().map(|usartclient| {
+            self.rx_buffer.take().map(|buf| match usartclient {
+                UsartClient::Uart(client) => {
+                    client.receive_complete(buf, length, error);
+                }
+                UsartClient::SpiMaster(_) => {}
+            });
+        });
+    }
+
+    /// Stores a byte received without DMA, finishing the reception at the
+    /// terminator or when the buffer is full.
+    fn receive_byte(&self, byte: u8) {
+        let full = self.rx_buffer.map_or(true, |buf| {
+            let index = self.rx_index.get();
+            if index < buf.len() {
+                buf[index] = byte;
+                self.rx_index.set(index + 1);
+            }
+            self.rx_index.get() >= buf.len()
+        });
+        if full || byte == self.rx_terminator.get() {
+            self.disable_rx();
+            self.disable_rx_interrupts();
+            self.finish_rx_interrupt(hil::uart::Error::CommandComplete);
         }
     }
 
@@ -296,6 +342,13 @@ impl USART {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
         let status = regs.csr.get();
 
+        if self.usart_rx_state.get() == USARTStateRX::Interrupt_Receiving &&
+           status & (1 << 1) != 0 {
+            // RXRDY
+            let byte = regs.rhr.get() as u8;
+            self.receive_byte(byte);
+        }
+
         if status & (1 << 12) != 0 {
             // DO NOTHING. Why are we here!?
 
@@ -394,11 +447,6 @@ impl USART {
         regs.idr.set((1 << 8)); // TIMEOUT
     }
 
-    fn enable_rx_terminator(&self, _terminator: u8) {
-        // XXX: implement me
-        panic!("didn't write terminator stuff yet");
-    }
-
     // for use by panic in io.rs
     pub fn send_byte(&self, byte: u8) {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
@@ -644,21 +692,18 @@ impl hil::uart::UARTAdvanced for USART {
         // quit current reception if any
         self.abort_rx(hil::uart::Error::RepeatCallError);
 
-        // enable receive terminator
-        self.enable_rx_terminator(terminator);
+        // The DMA cannot stop at the terminator, so take one byte per
+        // RXRDY interrupt instead
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_index.set(0);
+        self.rx_terminator.set(terminator);
 
         // enable RX
         self.enable_rx();
         self.enable_rx_error_interrupts();
-        self.usart_rx_state.set(USARTStateRX::DMA_Receiving);
-
-        // set up dma transfer and start reception
-        self.rx_dma.get().map(move |dma| {
-            dma.enable();
-            let length = rx_buffer.len();
-            dma.do_xfer(self.rx_dma_peripheral, rx_buffer, length);
-            self.rx_len.set(length);
-        });
+        self.usart_rx_state.set(USARTStateRX::Interrupt_Receiving);
+        let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
+        regs.ier.set(1 << 1); // RXRDY
     }
 }
 
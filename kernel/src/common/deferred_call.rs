@@ -0,0 +1,101 @@
+//! Deferred procedure calls: work a capsule schedules to run later on the
+//! kernel thread.
+//!
+//! A capsule owns a `DeferredCall` and registers itself as its client. Calling
+//! `set` marks the call pending; the kernel main loop then invokes the
+//! client's `call` once, after servicing interrupts and before running
+//! processes. Setting a call that is already pending has no further effect.
+//!
+//! This lets a capsule split long computations into chunks, or continue an
+//! operation from within one of its own callbacks without recursing into
+//! itself:
+//!
+//! ```ignore
+//! impl<'a> deferred_call::Client for Parser<'a> {
+//!     fn call(&self) {
+//!         if self.parse_next_chunk() {
+//!             self.deferred_call.set();
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! At most `MAX_DEFERRED_CALLS` calls can exist on a board.
+
+use common::VolatileCell;
+use support;
+
+pub const MAX_DEFERRED_CALLS: usize = 32;
+
+pub trait Client {
+    /// Called from the kernel main loop after the call was `set`.
+    fn call(&self);
+}
+
+static mut CLIENTS: [Option<&'static Client>; MAX_DEFERRED_CALLS] = [None; MAX_DEFERRED_CALLS];
+
+/// Bit `n` is set if deferred call `n` is pending
+static mut PENDING: VolatileCell<usize> = VolatileCell::new(0);
+
+/// Number of deferred calls created so far
+static mut COUNT: usize = 0;
+
+pub struct DeferredCall {
+    idx: usize,
+}
+
+impl DeferredCall {
+    /// Creates a new deferred call. Must be called during board setup, before
+    /// the kernel main loop starts.
+    pub unsafe fn new() -> DeferredCall {
+        let idx = COUNT;
+        if idx >= MAX_DEFERRED_CALLS {
+            panic!("More than {} deferred calls", MAX_DEFERRED_CALLS);
+        }
+        COUNT += 1;
+        DeferredCall { idx: idx }
+    }
+
+    pub fn set_client(&self, client: &'static Client) {
+        unsafe {
+            CLIENTS[self.idx] = Some(client);
+        }
+    }
+
+    /// Asks the kernel to invoke the client from the main loop.
+    pub fn set(&self) {
+        unsafe {
+            support::atomic(|| PENDING.set(PENDING.get() | (1 << self.idx)));
+        }
+    }
+
+    /// Whether the call is set and has not run yet.
+    pub fn is_pending(&self) -> bool {
+        unsafe { PENDING.get() & (1 << self.idx) != 0 }
+    }
+}
+
+/// Whether any deferred call is waiting to run.
+pub fn has_pending() -> bool {
+    unsafe { PENDING.get() != 0 }
+}
+
+/// Runs every deferred call that is pending. Calls set while this runs are
+/// left for the next pass of the main loop.
+pub fn service_pending() {
+    let pending = unsafe {
+        support::atomic(|| {
+            let pending = PENDING.get();
+            PENDING.set(0);
+            pending
+        })
+    };
+
+    for idx in 0..MAX_DEFERRED_CALLS {
+        if pending & (1 << idx) != 0 {
+            unsafe {
+                CLIENTS[idx].map(|client| client.call());
+            }
+        }
+    }
+}
@@ -2,7 +2,8 @@ use callback::AppId;
 use core::marker::PhantomData;
 use core::mem::size_of;
 use core::ops::{Deref, DerefMut};
-use core::ptr::{read_volatile, write_volatile, Unique};
+use core::ptr::{self, read_volatile, write_volatile, Unique};
+use core::slice;
 use process::{self, Error};
 
 pub static mut CONTAINER_COUNTER: usize = 0;
@@ -14,6 +15,7 @@ pub struct Container<T: Default> {
 
 pub struct AppliedContainer<T> {
     appid: usize,
+    container_num: usize,
     container: *mut T,
     _phantom: PhantomData<T>,
 }
@@ -26,6 +28,7 @@ impl<T> AppliedContainer<T> {
         let mut allocator = Allocator {
             app: unsafe { process::PROCS[self.appid].as_mut().unwrap() },
             app_id: self.appid,
+            container_num: self.container_num,
         };
         let mut root = unsafe { Owned::new(self.container, self.appid) };
         fun(&mut root, &mut allocator)
@@ -35,6 +38,7 @@ impl<T> AppliedContainer<T> {
 pub struct Allocator<'a> {
     app: &'a mut process::Process<'a>,
     app_id: usize,
+    container_num: usize,
 }
 
 pub struct Owned<T: ?Sized> {
@@ -87,13 +91,30 @@ impl<'a> Allocator<'a> {
     pub fn alloc<T>(&mut self, data: T) -> Result<Owned<T>, Error> {
         unsafe {
             let app_id = self.app_id;
-            self.app.alloc(size_of::<T>()).map_or(Err(Error::OutOfMemory), |arr| {
+            self.app.alloc(self.container_num, size_of::<T>()).map_or(Err(Error::OutOfMemory), |arr| {
                 let mut owned = Owned::new(arr.as_mut_ptr() as *mut T, app_id);
                 *owned = data;
                 Ok(owned)
             })
         }
     }
+
+    /// Allocates a slice of `len` default values, e.g. a table with one entry
+    /// per process slot.
+    pub fn alloc_slice<T: Default>(&mut self, len: usize) -> Result<Owned<[T]>, Error> {
+        unsafe {
+            let app_id = self.app_id;
+            self.app
+                .alloc(self.container_num, len * size_of::<T>())
+                .map_or(Err(Error::OutOfMemory), |arr| {
+                    let data = arr.as_mut_ptr() as *mut T;
+                    for i in 0..len {
+                        ptr::write(data.offset(i as isize), T::default());
+                    }
+                    Ok(Owned::new(slice::from_raw_parts_mut(data, len) as *mut [T], app_id))
+                })
+        }
+    }
 }
 
 impl<T: Default> Container<T> {
@@ -117,6 +138,7 @@ impl<T: Default> Container<T> {
                     } else {
                         Some(AppliedContainer {
                             appid: app_id,
+                            container_num: self.container_num,
                             container: cntr,
                             _phantom: PhantomData,
                         })
@@ -141,6 +163,7 @@ impl<T: Default> Container<T> {
                             let mut allocator = Allocator {
                                 app: app,
                                 app_id: app_id,
+                                container_num: self.container_num,
                             };
                             let res = fun(&mut root, &mut allocator);
                             Ok(res)
@@ -0,0 +1,259 @@
+//! Buffered debug output from the kernel.
+//!
+//! `debug!` and its per-level variants format a message into a ring buffer
+//! and return without waiting for the UART. Once a board has handed the
+//! debug output a UART with `set_uart`, normally a device on the same UART
+//! mux as the console, the buffer is drained in the background, up to
+//! `TX_LEN` bytes at a time. The mux never interleaves the bytes of two
+//! transmissions, so kernel messages shorter than `TX_LEN` are not split by
+//! console output. Messages that do not fit in the buffer are dropped and
+//! counted, and the count is logged once there is room again.
+//!
+//! ```ignore
+//! debug!("radio on channel {}", channel);
+//! debug_warn!("dropped {} frames", dropped);
+//! ```
+//!
+//! Messages have a `Level`; those less severe than the level the kernel was
+//! built with are discarded before they are formatted. By default every level
+//! is logged. Boards choose a level with one of the `debug_level_info`,
+//! `debug_level_warn` and `debug_level_error` features on their `kernel`
+//! dependency, or turn debug output off altogether with `debug_off`.
+
+use core::fmt::{self, Arguments, Write};
+use hil::uart;
+
+/// Size of the ring buffer messages are written into.
+pub const BUF_LEN: usize = 1024;
+
+/// Size of the buffer passed to the UART.
+pub const TX_LEN: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+static mut RING: [u8; BUF_LEN] = [0; BUF_LEN];
+
+/// Index of the oldest byte in `RING` not yet transmitted
+static mut HEAD: usize = 0;
+
+/// Number of bytes in `RING` not yet transmitted
+static mut LEN: usize = 0;
+
+/// Bytes of messages dropped since the last drop was logged
+static mut DROPPED: usize = 0;
+
+static mut TX_BUF: [u8; TX_LEN] = [0; TX_LEN];
+
+/// `TX_BUF` while no transmission is in progress
+static mut TX: Option<&'static mut [u8]> = None;
+
+static mut UART: Option<&'static uart::UART> = None;
+
+/// Called whenever a transmission completes, see `set_drain_callback`
+static mut DRAIN_CALLBACK: Option<fn()> = None;
+
+struct DebugClient;
+
+static DEBUG_CLIENT: DebugClient = DebugClient;
+
+/// Whether messages of `level` are logged by this build of the kernel.
+pub fn enabled(level: Level) -> bool {
+    if cfg!(feature = "debug_off") {
+        false
+    } else if cfg!(feature = "debug_level_error") {
+        level <= Level::Error
+    } else if cfg!(feature = "debug_level_warn") {
+        level <= Level::Warn
+    } else if cfg!(feature = "debug_level_info") {
+        level <= Level::Info
+    } else {
+        true
+    }
+}
+
+/// Appends to the ring buffer, keeping track of how much of the message did
+/// not fit.
+struct RingWriter {
+    written: usize,
+    overflow: usize,
+}
+
+impl Write for RingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            unsafe {
+                if LEN + self.written < BUF_LEN {
+                    RING[(HEAD + LEN + self.written) % BUF_LEN] = byte;
+                    self.written += 1;
+                } else {
+                    self.overflow += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes one message to the ring buffer, or nothing if it does not fit
+/// whole. Returns whether it was written.
+unsafe fn append(args: Arguments) -> bool {
+    let mut writer = RingWriter {
+        written: 0,
+        overflow: 0,
+    };
+    let _ = writer.write_fmt(args);
+    if writer.overflow > 0 {
+        DROPPED += writer.written + writer.overflow;
+        false
+    } else {
+        LEN += writer.written;
+        true
+    }
+}
+
+/// Number of bytes that can be logged before messages are dropped.
+pub fn room() -> usize {
+    unsafe { BUF_LEN - LEN }
+}
+
+/// Has `callback` called each time a part of the buffered output has been
+/// transmitted, so that output larger than the buffer can be logged a piece
+/// at a time as `room` allows. `None` stops the calls.
+pub fn set_drain_callback(callback: Option<fn()>) {
+    unsafe {
+        DRAIN_CALLBACK = callback;
+    }
+}
+
+/// Logs a message at `level`. Normally called through `debug!` and the other
+/// macros rather than directly.
+pub fn log(level: Level, args: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    unsafe {
+        if DROPPED > 0 {
+            let dropped = DROPPED;
+            DROPPED = 0;
+            if !append(format_args!("[kernel] WARN: dropped {} bytes of debug output\r\n",
+                                    dropped)) {
+                DROPPED += dropped;
+            }
+        }
+        append(format_args!("[kernel] {}: {}\r\n", level.name(), args));
+    }
+    flush();
+}
+
+/// Starts transmitting buffered output if there is any and the UART is not
+/// already busy with it.
+fn flush() {
+    unsafe {
+        let uart = match UART {
+            Some(uart) => uart,
+            None => return,
+        };
+        if LEN == 0 {
+            return;
+        }
+        TX.take().map(|buf| {
+            let mut len = if LEN < buf.len() { LEN } else { buf.len() };
+            for i in 0..len {
+                buf[i] = RING[(HEAD + i) % BUF_LEN];
+            }
+            // Keep lines together when they fit in the buffer, so that
+            // console output sent in between does not split them
+            if len < LEN {
+                len = buf[..len].iter().rposition(|&byte| byte == b'\n').map_or(len, |i| i + 1);
+            }
+            HEAD = (HEAD + len) % BUF_LEN;
+            LEN -= len;
+            uart.transmit(buf, len);
+        });
+    }
+}
+
+impl uart::Client for DebugClient {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        unsafe {
+            TX = Some(buffer);
+            DRAIN_CALLBACK.map(|callback| callback());
+        }
+        flush();
+    }
+
+    fn receive_complete(&self, _buffer: &'static mut [u8], _rx_len: usize, _error: uart::Error) {}
+}
+
+/// Sends debug output to `uart`, starting with anything logged before.
+/// The board configures the UART.
+pub unsafe fn set_uart(uart: &'static uart::UART) {
+    uart.set_client(&DEBUG_CLIENT);
+    UART = Some(uart);
+    TX = Some(&mut TX_BUF);
+    flush();
+}
+
+/// Writes the output that has not been transmitted yet, e.g. from a panic
+/// handler.
+pub unsafe fn dump<W: Write>(writer: &mut W) {
+    if LEN == 0 && DROPPED == 0 {
+        return;
+    }
+    let _ = writer.write_fmt(format_args!("\r\n---| Debug Output |---\r\n"));
+    while LEN > 0 {
+        let byte = RING[HEAD];
+        let _ = writer.write_char(if byte < 0x80 { byte as char } else { '?' });
+        HEAD = (HEAD + 1) % BUF_LEN;
+        LEN -= 1;
+    }
+    if DROPPED > 0 {
+        let _ = writer.write_fmt(format_args!("dropped {} bytes\r\n", DROPPED));
+        DROPPED = 0;
+    }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => (
+        $crate::debug::log($crate::debug::Level::Debug, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! debug_info {
+    ($($arg:tt)*) => (
+        $crate::debug::log($crate::debug::Level::Info, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! debug_warn {
+    ($($arg:tt)*) => (
+        $crate::debug::log($crate::debug::Level::Warn, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! debug_error {
+    ($($arg:tt)*) => (
+        $crate::debug::log($crate::debug::Level::Error, format_args!($($arg)*))
+    );
+}
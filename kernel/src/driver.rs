@@ -11,8 +11,12 @@
 //!   called later, when an event has occurred or data of interest is available.
 //!
 //!   * `command` tells the driver to do something immediately.
+//!   Drivers that need a second argument or a second return value implement
+//!   `command2` instead.
 //!
-//!   * `allow` provides the driver access to an application buffer.
+//!   * `allow` provides the driver access to an application buffer. Buffers
+//!   the driver only reads, which may also be in the application's flash, are
+//!   passed with `allow_readonly`.
 //!
 //! ## Mapping system-calls to drivers
 //!
@@ -93,6 +97,19 @@ pub trait Driver {
         ReturnCode::ENOSUPPORT
     }
 
+    /// `command2` is `command` with a second argument, passed in `r3`.
+    ///
+    /// Returning `ReturnCode::SuccessWithValues` passes a second value back
+    /// to the application in `r1`, e.g. the upper half of a 64-bit result.
+    ///
+    /// The scheduler always calls `command2`. The default implementation
+    /// ignores `r3` and forwards to `command`, so drivers only need to
+    /// implement one of the two.
+    #[allow(unused_variables)]
+    fn command2(&self, minor_num: usize, r2: usize, r3: usize, caller_id: ::AppId) -> ReturnCode {
+        self.command(minor_num, r2, caller_id)
+    }
+
     /// `allow` lets an application give the driver access to a buffer in the
     /// application's memory.
     ///
@@ -103,4 +120,16 @@ pub trait Driver {
     fn allow(&self, app: ::AppId, minor_num: usize, slice: ::AppSlice<::Shared, u8>) -> ReturnCode {
         ReturnCode::ENOSUPPORT
     }
+
+    /// `allow_readonly` gives the driver read access to a buffer in either
+    /// the application's memory or its flash region, e.g. constant keys or
+    /// lookup tables that would otherwise have to be copied into RAM.
+    #[allow(unused_variables)]
+    fn allow_readonly(&self,
+                      app: ::AppId,
+                      minor_num: usize,
+                      slice: ::AppSlice<::ReadOnly, u8>)
+                      -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
 }
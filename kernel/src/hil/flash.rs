@@ -1,3 +1,5 @@
+use returncode::ReturnCode;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     PageBoundary,
@@ -5,29 +7,40 @@ pub enum Error {
 }
 
 /// A block of writable persistent flash memory.
+///
+/// Offsets are addresses in flash. Writes and erases must cover whole pages.
+/// An operation that cannot be started returns the reason, together with
+/// the buffer if it took one; otherwise the client is called when it
+/// completes.
 pub trait Flash {
     /// Set the client for this flash peripheral. The client will be called
     /// when operations complete.
     fn set_client(&self, client: &'static Client);
 
     /// Read data
-    fn read(&self, offset: usize, buf: &'static mut [u8]);
+    fn read(&self,
+            offset: usize,
+            buf: &'static mut [u8])
+            -> Result<(), (ReturnCode, &'static mut [u8])>;
 
     /// Write data
-    fn write(&self, offset: usize, buf: &'static mut [u8]);
+    fn write(&self,
+             offset: usize,
+             buf: &'static mut [u8])
+             -> Result<(), (ReturnCode, &'static mut [u8])>;
 
     /// Erase flash
-    fn erase(&self, offset: usize, len: usize);
+    fn erase(&self, offset: usize, len: usize) -> ReturnCode;
 }
 
 /// Implement Client to receive callbacks from Flash
 pub trait Client {
     /// Flash read complete
-    fn read_complete(&self, read_buffer: &'static mut [u8], error: Error);
+    fn read_complete(&self, read_buffer: &'static mut [u8], result: ReturnCode);
 
     /// Flash write complete
-    fn write_complete(&self, write_buffer: &'static mut [u8], error: Error);
+    fn write_complete(&self, write_buffer: &'static mut [u8], result: ReturnCode);
 
     /// Flash erase complete
-    fn erase_complete(&self, error: Error);
+    fn erase_complete(&self, result: ReturnCode);
 }
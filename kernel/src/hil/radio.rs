@@ -1,18 +1,52 @@
 /// 802.15.4 radio interface
+///
+/// The radio sends and receives whole MAC frames: the MAC header and
+/// payload, which start `psdu_offset()` bytes into the buffer. The radio
+/// adds and checks the FCS. Building and parsing MAC headers is left to
+/// the radio's clients (see `capsules::ieee802154`).
 
 use returncode::ReturnCode;
 pub trait TxClient {
+    /// `result` is ENOACK if the frame requested an acknowledgement and
+    /// none arrived after all retries, and EBUSY if the channel stayed busy.
     fn send_done(&self, buf: &'static mut [u8], result: ReturnCode);
 }
 
 pub trait RxClient {
-    fn receive(&self, buf: &'static mut [u8], len: u8, result: ReturnCode);
+    /// `frame_len` is the length of the MAC header and payload.
+    fn receive(&self, buf: &'static mut [u8], frame_len: u8, info: RxInfo, result: ReturnCode);
 }
 
-pub const HEADER_SIZE: u8 = 10;
-pub const MAX_PACKET_SIZE: u8 = 128;
-pub const MAX_BUF_SIZE: usize = 129; // +1 for opcode
-pub const MIN_PACKET_SIZE: u8 = HEADER_SIZE + 2; // +2 for CRC
+pub trait EnergyClient {
+    /// Reports the energy on the channel in dBm, and whether it is below
+    /// the radio's clear channel assessment threshold.
+    fn energy_detect_done(&self, rssi: i8, clear: bool, result: ReturnCode);
+}
+
+pub trait PowerClient {
+    /// The radio finished turning on (`on` is true) after `start`, or off
+    /// after `stop`.
+    fn changed(&self, on: bool);
+}
+
+/// Link metrics the radio measured while receiving a frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RxInfo {
+    /// Received signal strength in dBm
+    pub rssi: i8,
+    /// Link quality indicator, 0 (worst) to 255 (best)
+    pub lqi: u8,
+    /// When the start of the frame was detected, in tics of the radio's
+    /// alarm
+    pub timestamp: u32,
+}
+
+pub const MAX_FRAME_SIZE: u8 = 127; // Including the 2-byte FCS
+pub const MIN_FRAME_SIZE: u8 = 5; // An acknowledgement
+pub const MAX_BUF_SIZE: usize = 131; // +1 for opcode, +1 for length, +2 for link metrics
+pub const MAX_RETRIES: u8 = 7;
+pub const MIN_CHANNEL: u8 = 11;
+pub const MAX_CHANNEL: u8 = 26;
 
 
 pub trait Radio {
@@ -23,7 +57,11 @@ pub trait Radio {
                   reg_write: &'static mut [u8],
                   reg_read: &'static mut [u8])
                   -> ReturnCode;
+    /// Turns the radio on. It is ready once the power client is told it
+    /// changed; returns EALREADY if it is already on or turning on.
     fn start(&self) -> ReturnCode;
+    /// Turns the radio off, once any frame being sent or received is done.
+    /// Returns EALREADY if it is already off or turning off.
     fn stop(&self) -> ReturnCode;
     fn reset(&self) -> ReturnCode;
     fn ready(&self) -> bool;
@@ -31,21 +69,38 @@ pub trait Radio {
     fn set_transmit_client(&self, client: &'static TxClient);
     fn set_receive_client(&self, client: &'static RxClient, receive_buffer: &'static mut [u8]);
     fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]);
+    fn set_energy_client(&self, client: &'static EnergyClient);
+    fn set_power_client(&self, client: &'static PowerClient);
 
     fn set_address(&self, addr: u16) -> ReturnCode;
+    /// The extended (EUI-64) address, most significant byte first
+    fn set_address_long(&self, addr: [u8; 8]) -> ReturnCode;
     fn set_pan(&self, addr: u16) -> ReturnCode;
-    fn payload_offset(&self) -> u8;
-    fn header_size(&self) -> u8;
+    fn get_address(&self) -> u16;
+    fn get_address_long(&self) -> [u8; 8];
+    fn get_pan(&self) -> u16;
+    /// Selects the 802.15.4 channel (MIN_CHANNEL-MAX_CHANNEL). Takes
+    /// effect even if the radio is on; returns ERANGE for other channels.
+    fn set_channel(&self, chan: u8) -> ReturnCode;
+    fn get_channel(&self) -> u8;
+    /// Sets the transmit power in dBm. The radio picks the strongest setting
+    /// it supports that does not exceed `power`; `get_tx_power` returns it.
+    /// Returns ERANGE if `power` is below the weakest setting.
+    fn set_tx_power(&self, power: i8) -> ReturnCode;
+    fn get_tx_power(&self) -> i8;
+    /// How many times a frame that requests an acknowledgement is sent
+    /// again if none arrives (0-MAX_RETRIES).
+    fn set_max_retries(&self, retries: u8) -> ReturnCode;
+    fn get_max_retries(&self) -> u8;
+    /// Offset of the MAC header in transmit and receive buffers
+    fn psdu_offset(&self) -> u8;
 
-    fn transmit(&self, dest: u16, tx_data: &'static mut [u8], tx_len: u8) -> ReturnCode;
-}
+    /// Sends the MAC frame of `frame_len` bytes (without FCS) that starts at
+    /// `psdu_offset()` in `frame`.
+    fn transmit(&self, frame: &'static mut [u8], frame_len: u8) -> ReturnCode;
 
-#[repr(C, packed)]
-pub struct Header {
-    len: u8,
-    fcf: u16,
-    dsn: u8,
-    pan: u16,
-    src: u16,
-    dst: u16,
+    /// Measures the energy on the current channel. The result is passed to
+    /// the energy client once the radio is idle.
+    fn energy_detect(&self) -> ReturnCode;
 }
+
@@ -55,7 +55,8 @@ pub trait UART {
     /// Transmit data
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize);
 
-    /// Receive data until buffer is full
+    /// Receive data until buffer is full. Any reception already in progress
+    /// ends first, handing its buffer back with `Error::RepeatCallError`.
     fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize);
 }
 
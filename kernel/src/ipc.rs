@@ -2,62 +2,310 @@
 //! platform controlling of buttons without having to know which of the GPIO pins exposed across
 //! the syscall interface are buttons.
 
-use {AppId, AppSlice, Container, Callback, Driver, Shared};
+use {AppId, AppSlice, Container, Callback, Driver, ReadOnly, Shared};
+use container::{Allocator, Owned};
 use process;
 use returncode::ReturnCode;
 
+/// Allow number for the buffer messages from other apps are copied into.
+pub const RX_BUFFER_ALLOW_NUM: usize = 0xfe;
+
+/// Allow number for the buffer holding the next message this app sends.
+pub const TX_BUFFER_ALLOW_NUM: usize = 0xfd;
+
+/// Subscribe number for the callback signalling a received message.
+pub const MESSAGE_SUBSCRIBE_NUM: usize = 0xfe;
+
+/// Subscribe number for the callback signalling that another app withdrew
+/// memory it had shared with this app.
+pub const REVOKE_SUBSCRIBE_NUM: usize = 0xfd;
+
+/// Maximum number of slots a receive buffer can be split into.
+pub const MAX_QUEUED_MESSAGES: usize = 8;
+
+/// A message copied into a slot of the receive buffer and not yet released.
+#[derive(Clone, Copy)]
+struct QueuedMessage {
+    sender: usize,
+    seq: usize,
+}
+
 struct IPCData {
-    shared_memory: [Option<AppSlice<Shared, u8>>; 8],
-    client_callbacks: [Option<Callback>; 8],
+    /// Buffer shared with each process, indexed by process id - 1. Allocated
+    /// on first use with one entry per process slot on the board.
+    shared_memory: Option<Owned<[Option<AppSlice<Shared, u8>>]>>,
+    /// Callback for notifications from each service, indexed like
+    /// `shared_memory`
+    client_callbacks: Option<Owned<[Option<Callback>]>>,
     callback: Option<Callback>,
+    message_callback: Option<Callback>,
+    revoke_callback: Option<Callback>,
+    rx_buffer: Option<AppSlice<Shared, u8>>,
+    tx_buffer: Option<AppSlice<ReadOnly, u8>>,
+    /// Number of equally sized slots the receive buffer is split into
+    rx_slots: usize,
+    /// Number of slots a single sender may occupy at once
+    pair_depth: usize,
+    rx_queue: [Option<QueuedMessage>; MAX_QUEUED_MESSAGES],
+    rx_seq: usize,
+}
+
+impl IPCData {
+    /// Copies a message from `sender` into a free receive slot. Returns the
+    /// address of the slot.
+    fn enqueue(&mut self, sender: usize, message: &[u8]) -> Result<usize, ReturnCode> {
+        let slot_len = match self.rx_buffer {
+            Some(ref buffer) => buffer.len() / self.rx_slots,
+            None => return Err(ReturnCode::ERESERVE),
+        };
+        if message.len() > slot_len {
+            return Err(ReturnCode::ESIZE);
+        }
+
+        let queued = self.rx_queue[..self.rx_slots]
+            .iter()
+            .filter(|m| m.map_or(false, |m| m.sender == sender))
+            .count();
+        if queued >= self.pair_depth {
+            return Err(ReturnCode::EBUSY);
+        }
+        let slot = match self.rx_queue[..self.rx_slots].iter().position(|m| m.is_none()) {
+            Some(slot) => slot,
+            None => return Err(ReturnCode::EBUSY),
+        };
+
+        let address = self.rx_buffer.as_mut().map_or(0, |buffer| {
+            let dest = &mut buffer.as_mut()[slot * slot_len..];
+            for (d, s) in dest.iter_mut().zip(message.iter()) {
+                *d = *s;
+            }
+            dest.as_ptr() as usize
+        });
+        self.rx_queue[slot] = Some(QueuedMessage {
+            sender: sender,
+            seq: self.rx_seq,
+        });
+        self.rx_seq = self.rx_seq.wrapping_add(1);
+        Ok(address)
+    }
+
+    /// Frees the slot of the oldest message received from `sender`.
+    fn release(&mut self, sender: usize) -> ReturnCode {
+        let rx_seq = self.rx_seq;
+        let oldest = self.rx_queue
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| m.map(|m| (i, m)))
+            .filter(|&(_, m)| m.sender == sender)
+            .max_by_key(|&(_, m)| rx_seq.wrapping_sub(m.seq))
+            .map(|(i, _)| i);
+        match oldest {
+            Some(slot) => {
+                self.rx_queue[slot] = None;
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn queue_empty(&self) -> bool {
+        self.rx_queue.iter().all(|m| m.is_none())
+    }
+
+    /// Allocates the per-process tables if this app has not used them yet.
+    fn alloc_tables(&mut self, allocator: &mut Allocator) -> ReturnCode {
+        let num_procs = unsafe { process::PROCS.len() };
+        if self.shared_memory.is_none() {
+            match allocator.alloc_slice(num_procs) {
+                Ok(table) => self.shared_memory = Some(table),
+                Err(_) => return ReturnCode::ENOMEM,
+            }
+        }
+        if self.client_callbacks.is_none() {
+            match allocator.alloc_slice(num_procs) {
+                Ok(table) => self.client_callbacks = Some(table),
+                Err(_) => return ReturnCode::ENOMEM,
+            }
+        }
+        ReturnCode::SUCCESS
+    }
 }
 
 impl Default for IPCData {
     fn default() -> IPCData {
         IPCData {
-            shared_memory: [None, None, None, None, None, None, None, None],
-            client_callbacks: [None, None, None, None, None, None, None, None],
+            shared_memory: None,
+            client_callbacks: None,
             callback: None,
+            message_callback: None,
+            revoke_callback: None,
+            rx_buffer: None,
+            tx_buffer: None,
+            rx_slots: 1,
+            pair_depth: 1,
+            rx_queue: [None; MAX_QUEUED_MESSAGES],
+            rx_seq: 0,
         }
     }
 }
 
+/// Removes the MPU region `target` has for `slice` and, if it had one, tells
+/// it that `owner` withdrew the memory.
+unsafe fn revoke(slice: &AppSlice<Shared, u8>, target: AppId, owner: AppId) {
+    let procs = &mut process::PROCS;
+    procs.get_mut(target.idx()).map(|slot| {
+        slot.as_mut().map(|process| if process.remove_mpu_region(slice.ptr()) {
+            process.schedule_ipc(owner, process::IPCType::Revoke);
+        });
+    });
+}
+
+/// The clients a service accepts. Other apps can neither discover nor notify
+/// it, nor send it messages.
+///
+/// Both are process ids, as apps see them: apps get ids in the order they are
+/// in flash, and an app installed at runtime never gets the id of one that
+/// was removed, so an id always names the same app image. Unlike package
+/// names, which come from each app's own header, ids cannot be claimed by
+/// another app.
+pub struct ServiceClients {
+    pub service: usize,
+    pub clients: &'static [usize],
+}
+
 pub struct IPC {
     data: Container<IPCData>,
+    services: &'static [ServiceClients],
 }
 
 impl IPC {
-    pub unsafe fn new() -> IPC {
-        IPC { data: Container::create() }
+    /// `services` lists the services that only accept some clients. Any app
+    /// may use a service that is not listed.
+    ///
+    /// ```ignore
+    /// // Only the app with id 1 may use the service with id 3
+    /// static SERVICES: [ServiceClients; 1] = [ServiceClients {
+    ///     service: 3,
+    ///     clients: &[1],
+    /// }];
+    /// let ipc = kernel::ipc::IPC::new(&SERVICES);
+    /// ```
+    pub unsafe fn new(services: &'static [ServiceClients]) -> IPC {
+        IPC {
+            data: Container::create(),
+            services: services,
+        }
+    }
+
+    /// Whether `service` accepts `client`.
+    fn accepts(&self, service: AppId, client: AppId) -> bool {
+        match self.services.iter().find(|s| s.service == service.idx() + 1) {
+            Some(s) => s.clients.contains(&(client.idx() + 1)),
+            None => true,
+        }
+    }
+
+    /// Copies the message in `from`'s transmit buffer into `to`'s receive
+    /// buffer and signals `to` with its message callback.
+    ///
+    /// The sender may reuse its transmit buffer as soon as this returns.
+    /// Returns `EBUSY` if `to` has no free slot or already holds as many
+    /// messages from `from` as its per-sender queue depth allows.
+    fn send_message(&self, from: AppId, to: AppId, to_service: bool) -> ReturnCode {
+        if from.idx() == to.idx() {
+            return ReturnCode::EINVAL;
+        }
+        if to_service && !self.accepts(to, from) {
+            return ReturnCode::EACCES;
+        }
+        let sender = from.idx() + 1;
+
+        self.data
+            .enter(from, |fromdata, _| {
+                let message = match fromdata.tx_buffer {
+                    Some(ref buffer) => buffer,
+                    None => return ReturnCode::EINVAL,
+                };
+                self.data
+                    .enter(to, |todata, _| match todata.enqueue(from.idx(), message.as_ref()) {
+                        Ok(address) => {
+                            todata.message_callback.map(|mut callback| {
+                                callback.schedule(sender, message.len(), address)
+                            });
+                            ReturnCode::SUCCESS
+                        }
+                        Err(err) => err,
+                    })
+                    .unwrap_or(ReturnCode::ENOMEM)
+            })
+            .unwrap_or(ReturnCode::ENOMEM)
+    }
+
+    /// Sets how many slots `appid`'s receive buffer is split into (low
+    /// byte of `config`) and how many of them a single sender may occupy
+    /// (second byte).
+    fn configure_queue(&self, appid: AppId, config: usize) -> ReturnCode {
+        let slots = config & 0xff;
+        let depth = (config >> 8) & 0xff;
+        if slots == 0 || slots > MAX_QUEUED_MESSAGES || depth == 0 || depth > slots {
+            return ReturnCode::EINVAL;
+        }
+        self.data
+            .enter(appid, |data, _| {
+                if !data.queue_empty() {
+                    return ReturnCode::EBUSY;
+                }
+                data.rx_slots = slots;
+                data.pair_depth = depth;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or(ReturnCode::ENOMEM)
     }
 
     pub unsafe fn schedule_callback(&self,
                                     appid: AppId,
                                     otherapp: AppId,
                                     cb_type: process::IPCType) {
+        if let process::IPCType::Revoke = cb_type {
+            self.data
+                .enter(appid, |mydata, _| {
+                    mydata.revoke_callback
+                        .map(|mut callback| callback.schedule(otherapp.idx() + 1, 0, 0));
+                })
+                .unwrap_or(());
+            return;
+        }
+
+        let sender = otherapp.idx() + 1;
+
         self.data
             .enter(appid, |mydata, _| {
                 let callback = match cb_type {
                     process::IPCType::Service => mydata.callback,
+                    process::IPCType::Revoke => None,
                     process::IPCType::Client => {
-                        *mydata.client_callbacks.get(otherapp.idx()).unwrap_or(&None)
+                        mydata.client_callbacks
+                            .as_ref()
+                            .and_then(|callbacks| callbacks.get(otherapp.idx()).cloned())
+                            .unwrap_or(None)
                     }
                 };
                 callback.map(|mut callback| {
                         self.data
                             .enter(otherapp, |otherdata, _| {
-                                if appid.idx() >= otherdata.shared_memory.len() {
-                                    return;
-                                }
-                                match otherdata.shared_memory[appid.idx()] {
-                                    Some(ref slice) => {
+                                // An app that never shared memory has no table
+                                let shared = otherdata.shared_memory
+                                    .as_ref()
+                                    .and_then(|table| table.get(appid.idx()));
+                                match shared {
+                                    Some(&Some(ref slice)) => {
                                         slice.expose_to(appid);
-                                        callback.schedule(otherapp.idx() + 1,
+                                        callback.schedule(sender,
                                                           slice.len(),
                                                           slice.ptr() as usize);
                                     }
-                                    None => {
-                                        callback.schedule(appid.idx() + 1, 0, 0);
+                                    _ => {
+                                        callback.schedule(sender, 0, 0);
                                     }
                                 }
                             })
@@ -78,12 +326,30 @@ impl Driver for IPC {
                     ReturnCode::SUCCESS
                 }).unwrap_or(ReturnCode::EBUSY)
             }
+            REVOKE_SUBSCRIBE_NUM => {
+                self.data.enter(callback.app_id(), |data, _| {
+                    data.revoke_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ReturnCode::EBUSY)
+            }
+            MESSAGE_SUBSCRIBE_NUM => {
+                self.data.enter(callback.app_id(), |data, _| {
+                    data.message_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ReturnCode::EBUSY)
+            }
             svc_id /* Client callback */ => {
-                if svc_id - 1 >= 8 {
-                    ReturnCode::EINVAL /* Maximum of 8 IPC's exceeded */
+                if svc_id > unsafe { process::PROCS.len() } {
+                    ReturnCode::EINVAL /* No such process slot */
                 } else {
-                    self.data.enter(callback.app_id(), |data, _| {
-                        data.client_callbacks[svc_id - 1] = Some(callback);
+                    self.data.enter(callback.app_id(), |data, allocator| {
+                        let res = data.alloc_tables(allocator);
+                        if res != ReturnCode::SUCCESS {
+                            return res;
+                        }
+                        data.client_callbacks.as_mut().map(|callbacks| {
+                            callbacks[svc_id - 1] = Some(callback);
+                        });
                         ReturnCode::SUCCESS
                     }).unwrap_or(ReturnCode::EBUSY)
                 }
@@ -91,18 +357,48 @@ impl Driver for IPC {
         }
     }
 
-    fn command(&self, target_id: usize, client_or_svc: usize, appid: AppId) -> ReturnCode {
+    /// Command 0 with a non-zero argument configures the message queue of
+    /// the calling app (see `configure_queue`). For other commands,
+    /// `target_id` is the process id of the other app and `command_num`
+    /// selects the operation:
+    ///
+    /// - `0`: notify the service `target_id`
+    /// - `1`: notify the client `target_id`
+    /// - `2`: send the transmit buffer to the service `target_id`
+    /// - `3`: send the transmit buffer to the client `target_id`
+    /// - `4`: release the oldest message received from `target_id`
+    fn command(&self, target_id: usize, command_num: usize, appid: AppId) -> ReturnCode {
         let procs = unsafe { &mut process::PROCS };
-        if target_id == 0 || target_id > procs.len() {
+        if target_id == 0 {
+            return match command_num {
+                0 => ReturnCode::SUCCESS,
+                config => self.configure_queue(appid, config),
+            };
+        }
+        if target_id > procs.len() || procs[target_id - 1].is_none() {
             return ReturnCode::EINVAL; /* Request to IPC to impossible process */
         }
+        let target = AppId::new(target_id - 1);
 
-        let cb_type = if client_or_svc == 0 {
-            process::IPCType::Service
-        } else {
-            process::IPCType::Client
+        let cb_type = match command_num {
+            0 => process::IPCType::Service,
+            1 => process::IPCType::Client,
+            2 => return self.send_message(appid, target, true),
+            3 => return self.send_message(appid, target, false),
+            4 => {
+                return self.data
+                    .enter(appid, |data, _| data.release(target.idx()))
+                    .unwrap_or(ReturnCode::ENOMEM)
+            }
+            _ => return ReturnCode::ENOSUPPORT,
         };
 
+        if let process::IPCType::Service = cb_type {
+            if !self.accepts(target, appid) {
+                return ReturnCode::EACCES;
+            }
+        }
+
         procs[target_id - 1]
             .as_mut()
             .map(|target| {
@@ -125,7 +421,10 @@ impl Driver for IPC {
                                s.iter()
                                 .zip(slice.iter())
                                 .all(|(c1, c2)| c1 == c2) {
-                                return ReturnCode::SuccessWithValue { value: (i as usize) + 1 };
+                                if !self.accepts(AppId::new(i), appid) {
+                                    return ReturnCode::EACCES;
+                                }
+                                return ReturnCode::SuccessWithValue { value: i + 1 };
                             }
                         }
                         &None => {}
@@ -134,16 +433,158 @@ impl Driver for IPC {
             }
             return ReturnCode::EINVAL; /* AppSlice must have non-zero length */
         }
+        if target_id == RX_BUFFER_ALLOW_NUM {
+            return self.data
+                .enter(appid, |data, _| {
+                    // Messages still queued in the old buffer are dropped
+                    data.rx_queue = [None; MAX_QUEUED_MESSAGES];
+                    data.rx_buffer = Some(slice);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or(ReturnCode::EBUSY);
+        }
+        if target_id == TX_BUFFER_ALLOW_NUM {
+            return self.allow_readonly(appid, target_id, slice.into_readonly());
+        }
+        if target_id > unsafe { process::PROCS.len() } {
+            return ReturnCode::EINVAL; /* Target process does not exist */
+        }
         return self.data
-            .enter(appid, |data, _| {
-                data.shared_memory
-                    .get_mut(target_id - 1)
-                    .map(|smem| {
-                        *smem = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
-                    .unwrap_or(ReturnCode::EINVAL) /* Target process does not exist */
+            .enter(appid, |data, allocator| {
+                let res = data.alloc_tables(allocator);
+                if res != ReturnCode::SUCCESS {
+                    return res;
+                }
+                data.shared_memory.as_mut().map(|table| {
+                    // Withdraw the previous buffer from the target, if it was
+                    // ever exposed to it
+                    table[target_id - 1].take().map(|old| unsafe {
+                        revoke(&old, AppId::new(target_id - 1), appid);
+                    });
+                    // A zero-length allow only unshares
+                    if slice.len() > 0 {
+                        table[target_id - 1] = Some(slice);
+                    }
+                });
+                ReturnCode::SUCCESS
             })
             .unwrap_or(ReturnCode::EBUSY);
     }
+
+    /// The transmit buffer is only read, so it can also be in the app's flash.
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: AppSlice<ReadOnly, u8>)
+                      -> ReturnCode {
+        match allow_num {
+            TX_BUFFER_ALLOW_NUM => {
+                self.data
+                    .enter(appid, |data, _| {
+                        data.tx_buffer = Some(slice);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ReturnCode::EBUSY)
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::nonzero::NonZero;
+    use process::tests::{load_app, region_size, RecordingMPU};
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use super::*;
+
+    /// More process slots than the eight the IPC tables used to have
+    const NUM_PROCS: usize = 10;
+
+    /// Loads `NUM_PROCS` apps as the kernel's processes.
+    unsafe fn load_procs() {
+        let procs: Vec<Option<process::Process<'static>>> =
+            (0..NUM_PROCS).map(|_| Some(load_app(0))).collect();
+        process::PROCS = &mut *Box::into_raw(procs.into_boxed_slice());
+    }
+
+    /// `len` bytes at the start of the memory of process `idx`.
+    unsafe fn buffer(idx: usize, len: usize) -> AppSlice<Shared, u8> {
+        let start = process::PROCS[idx].as_ref().unwrap().mem_start();
+        AppSlice::new(start as *mut u8, len, AppId::new(idx))
+    }
+
+    /// The first region process `idx` has for memory other apps shared with
+    /// it: (start, size).
+    unsafe fn shared_region(idx: usize) -> (u32, usize) {
+        let mpu = RecordingMPU::new();
+        process::PROCS[idx].as_ref().unwrap().setup_mpu(&mpu);
+        let (start, len, _, _) = mpu.region(4);
+        (start, if len == 0 { 0 } else { region_size(len) })
+    }
+
+    #[test]
+    fn share_with_every_process_slot() {
+        unsafe {
+            let ipc = IPC::new(&[]);
+            load_procs();
+            let client = AppId::new(0);
+            let service = AppId::new(NUM_PROCS - 1);
+
+            for target_id in 1..NUM_PROCS + 1 {
+                assert_eq!(ipc.allow(client, target_id, buffer(0, 256)),
+                           ReturnCode::SUCCESS);
+            }
+            assert_eq!(ipc.allow(client, NUM_PROCS + 1, buffer(0, 256)),
+                       ReturnCode::EINVAL);
+
+            // Notifying the service in the last slot exposes the buffer to it
+            let callback = Callback::new(service, 0, NonZero::new(1 as *mut ()));
+            assert_eq!(ipc.subscribe(0, callback), ReturnCode::SUCCESS);
+            assert_eq!(ipc.command(NUM_PROCS, 0, client), ReturnCode::SUCCESS);
+            ipc.schedule_callback(service, client, process::IPCType::Service);
+            assert_eq!(shared_region(service.idx()),
+                       (buffer(0, 256).ptr() as u32, 256));
+
+            // Sharing an empty buffer withdraws it again
+            assert_eq!(ipc.allow(client, NUM_PROCS, buffer(0, 0)), ReturnCode::SUCCESS);
+            assert_eq!(shared_region(service.idx()).1, 0);
+        }
+    }
+
+    #[test]
+    fn restricted_service() {
+        unsafe {
+            // Only the app with id 2 may use the service with id 1
+            static SERVICES: [ServiceClients; 1] = [ServiceClients {
+                                                        service: 1,
+                                                        clients: &[2],
+                                                    }];
+            let ipc = IPC::new(&SERVICES);
+            load_procs();
+            let client = AppId::new(1);
+            let other = AppId::new(2);
+
+            // Every test app is called "test", so discovery finds the service
+            let name = |idx: usize| {
+                let mut slice = buffer(idx, 4);
+                slice.as_mut().copy_from_slice(b"test");
+                slice
+            };
+            assert_eq!(ipc.allow(client, 0, name(1)),
+                       ReturnCode::SuccessWithValue { value: 1 });
+            assert_eq!(ipc.allow(other, 0, name(2)), ReturnCode::EACCES);
+
+            assert_eq!(ipc.command(1, 0, client), ReturnCode::SUCCESS);
+            assert_eq!(ipc.command(1, 0, other), ReturnCode::EACCES);
+
+            assert_eq!(ipc.allow_readonly(other, TX_BUFFER_ALLOW_NUM, name(2).into_readonly()),
+                       ReturnCode::SUCCESS);
+            assert_eq!(ipc.command(1, 2, other), ReturnCode::EACCES);
+
+            // Services that are not listed accept anyone
+            assert_eq!(ipc.command(4, 0, other), ReturnCode::SUCCESS);
+        }
+    }
 }
@@ -1,8 +1,15 @@
-#![feature(asm,core_intrinsics,unique,nonzero,const_fn,lang_items)]
+#![feature(asm,core_intrinsics,unique,nonzero,const_fn,lang_items,try_from)]
 #![no_std]
 
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
 pub mod common;
 
+#[macro_use]
+pub mod debug;
+
 pub mod callback;
 pub mod container;
 pub mod driver;
@@ -17,12 +24,13 @@ pub mod support;
 mod sched;
 
 mod syscall;
+pub mod syscall_trace;
 mod platform;
 
 pub use callback::{AppId, Callback};
 pub use container::Container;
 pub use driver::Driver;
-pub use mem::{AppSlice, AppPtr, Private, Shared};
+pub use mem::{AppSlice, AppPtr, Private, ReadOnly, Shared};
 pub use platform::{Chip, mpu, Platform, systick};
 pub use platform::systick::SysTick;
 pub use process::{Process, State};
@@ -34,23 +42,27 @@ pub fn main<P: Platform, C: Chip>(platform: &P,
                                   ipc: &ipc::IPC) {
     let processes = unsafe {
         process::PROCS = processes;
+        process::remove_duplicate_names();
         &mut process::PROCS
     };
 
     loop {
         unsafe {
             chip.service_pending_interrupts();
+            common::deferred_call::service_pending();
 
             for (i, p) in processes.iter_mut().enumerate() {
                 p.as_mut().map(|process| {
                     sched::do_process(platform, chip, process, AppId::new(i), ipc);
                 });
-                if chip.has_pending_interrupts() {
+                if chip.has_pending_interrupts() || common::deferred_call::has_pending() {
                     break;
                 }
             }
 
-            support::atomic(|| if !chip.has_pending_interrupts() && process::processes_blocked() {
+            support::atomic(|| if !chip.has_pending_interrupts() &&
+                                  !common::deferred_call::has_pending() &&
+                                  process::processes_blocked() {
                 support::wfi();
             })
         };
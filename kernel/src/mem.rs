@@ -1,5 +1,6 @@
 use AppId;
 use core::marker::PhantomData;
+use core::mem;
 use core::ops::{Deref, DerefMut};
 use core::ptr::Unique;
 use core::slice;
@@ -9,6 +10,10 @@ use process;
 pub struct Private;
 #[derive(Debug)]
 pub struct Shared;
+/// Marks app memory the kernel may only read, such as buffers in the app's
+/// flash region passed with the read-only allow system call.
+#[derive(Debug)]
+pub struct ReadOnly;
 
 pub struct AppPtr<L, T> {
     ptr: Unique<T>,
@@ -72,6 +77,21 @@ impl<L, T> AppSlice<L, T> {
         self.ptr.ptr.get() as *const T
     }
 
+    pub fn iter(&self) -> slice::Iter<T> {
+        self.as_ref().iter()
+    }
+}
+
+impl<T> AppSlice<Shared, T> {
+    /// Gives up write access to the buffer.
+    pub fn into_readonly(self) -> AppSlice<ReadOnly, T> {
+        let slice = unsafe {
+            AppSlice::new(self.ptr() as *mut T, self.len, self.ptr.process)
+        };
+        mem::forget(self);
+        slice
+    }
+
     pub unsafe fn expose_to(&self, appid: AppId) -> bool {
         let ps = &mut process::PROCS;
         if appid.idx() != self.ptr.process.idx() && ps.len() > appid.idx() {
@@ -83,10 +103,6 @@ impl<L, T> AppSlice<L, T> {
             false
         }
     }
-
-    pub fn iter(&self) -> slice::Iter<T> {
-        self.as_ref().iter()
-    }
 }
 
 impl<L, T> AsRef<[T]> for AppSlice<L, T> {
@@ -95,7 +111,13 @@ impl<L, T> AsRef<[T]> for AppSlice<L, T> {
     }
 }
 
-impl<L, T> AsMut<[T]> for AppSlice<L, T> {
+impl<T> AsMut<[T]> for AppSlice<Shared, T> {
+    fn as_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.ptr.get_mut(), self.len) }
+    }
+}
+
+impl<T> AsMut<[T]> for AppSlice<Private, T> {
     fn as_mut(&mut self) -> &mut [T] {
         unsafe { slice::from_raw_parts_mut(self.ptr.ptr.get_mut(), self.len) }
     }
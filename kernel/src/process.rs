@@ -2,7 +2,7 @@ use callback::AppId;
 use common::{RingBuffer, Queue, VolatileCell};
 
 use container;
-use core::{mem, ptr, slice, str};
+use core::{cmp, mem, ptr, slice, str};
 use core::cell::Cell;
 use core::fmt::Write;
 use core::intrinsics;
@@ -17,6 +17,18 @@ macro_rules! align8 {
     ( $e:expr ) => ( ($e) + ((8 - (($e) % 8)) % 8 ) );
 }
 
+/// Size of the inaccessible region placed below the stack of a process whose
+/// header does not choose one.
+///
+/// A function whose stack frame is larger than the guard can skip over it
+/// without touching it, so the guard should be at least as large as the
+/// largest frame the app is expected to have. A region must be aligned to its
+/// size, so up to as many bytes again may be skipped to align it.
+const DEFAULT_STACK_GUARD_SIZE: usize = 256;
+
+/// Number of drivers whose syscalls are counted separately for each process
+const DRIVER_SYSCALL_SLOTS: usize = 8;
+
 #[no_mangle]
 pub static mut SYSCALL_FIRED: usize = 0;
 
@@ -36,6 +48,8 @@ extern "C" {
 
 pub static mut PROCS: &'static mut [Option<Process<'static>>] = &mut [];
 
+/// Queues a callback for the process. Returns false, and counts the callback
+/// as dropped, if the process's callback queue is full.
 pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
     let procs = unsafe { &mut PROCS };
     let idx = appid.idx();
@@ -47,15 +61,29 @@ pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
         None => false,
         Some(ref mut p) => {
             // TODO(alevy): validate appid liveness
-            unsafe {
-                HAVE_WORK.set(HAVE_WORK.get() + 1);
-            }
-
-            p.tasks.enqueue(Task::FunctionCall(callback))
+            p.enqueue_task(Task::FunctionCall(callback))
         }
     }
 }
 
+/// The package name of the process, or `None` if there is no such process.
+pub fn package_name(appid: AppId) -> Option<&'static str> {
+    let procs = unsafe { &PROCS };
+    match procs.get(appid.idx()) {
+        Some(&Some(ref p)) => Some(p.package_name),
+        _ => None,
+    }
+}
+
+/// The image in flash of the process, or `None` if there is no such process.
+pub fn image(appid: AppId) -> Option<&'static [u8]> {
+    let procs = unsafe { &PROCS };
+    match procs.get(appid.idx()) {
+        Some(&Some(ref p)) => Some(p.text),
+        _ => None,
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     NoSuchApp,
@@ -80,6 +108,8 @@ pub enum FaultResponse {
 pub enum IPCType {
     Service,
     Client,
+    /// Memory the other app shared has been withdrawn
+    Revoke,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -121,6 +151,48 @@ struct LoadInfo {
     checksum: u32,
 }
 
+/// Header fields that follow `LoadInfo` in version 2 headers. They are
+/// covered by the checksum in `LoadInfo`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LoadInfoV2 {
+    flags: u32,
+}
+
+/// Header flag allowing the process to execute code from its RAM. Without it
+/// all process RAM is mapped execute-never.
+pub const FLAG_RAM_EXECUTABLE: u32 = 0x1;
+
+/// Header flag bits holding how many callbacks may be queued for the process
+/// at once. 0 selects `DEFAULT_CALLBACK_QUEUE_DEPTH`.
+pub const FLAG_CALLBACK_QUEUE_MASK: u32 = 0xff << FLAG_CALLBACK_QUEUE_SHIFT;
+pub const FLAG_CALLBACK_QUEUE_SHIFT: u32 = 16;
+
+/// Header flag bits holding the base-2 logarithm of the size of the stack
+/// guard, from 5 (32 bytes, the smallest MPU region) to 12 (4 kB). 0 selects
+/// `DEFAULT_STACK_GUARD_SIZE`.
+pub const FLAG_STACK_GUARD_MASK: u32 = 0xf << FLAG_STACK_GUARD_SHIFT;
+pub const FLAG_STACK_GUARD_SHIFT: u32 = 8;
+
+/// Size of the stack guard of a process with header `flags`.
+fn stack_guard_size(flags: u32) -> usize {
+    match (flags & FLAG_STACK_GUARD_MASK) >> FLAG_STACK_GUARD_SHIFT {
+        0 => DEFAULT_STACK_GUARD_SIZE,
+        order => 1 << cmp::min(cmp::max(order, 5), 12),
+    }
+}
+
+/// Callbacks queued for a process whose header does not set a depth.
+const DEFAULT_CALLBACK_QUEUE_DEPTH: usize = 9;
+
+/// Number of callbacks that may be queued for a process with header `flags`.
+fn callback_queue_depth(flags: u32) -> usize {
+    match (flags & FLAG_CALLBACK_QUEUE_MASK) >> FLAG_CALLBACK_QUEUE_SHIFT {
+        0 => DEFAULT_CALLBACK_QUEUE_DEPTH,
+        depth => depth as usize,
+    }
+}
+
 /// Converts a pointer to memory to a LoadInfo struct
 ///
 /// This function takes a pointer to arbitrary memory and Optionally returns a
@@ -129,25 +201,264 @@ struct LoadInfo {
 unsafe fn parse_and_validate_load_info(address: *const u8) -> Option<&'static LoadInfo> {
     let load_info = &*(address as *const LoadInfo);
 
-    if load_info.version != 1 {
+    if !checksum_valid(load_info, load_info.version, read_v2_flags(address, load_info.version)) {
+        return None;
+    }
+
+    Some(load_info)
+}
+
+/// Returns the flags of a header at `address` with version `version`. Version 1
+/// headers have no flags.
+unsafe fn read_v2_flags(address: *const u8, version: u32) -> u32 {
+    if version == 2 {
+        let v2 = &*(address.offset(mem::size_of::<LoadInfo>() as isize) as *const LoadInfoV2);
+        v2.flags
+    } else {
+        0
+    }
+}
+
+/// Checks the header checksum, assuming the header has version `version`.
+/// `flags` is only part of the checksum for version 2 headers.
+fn checksum_valid(load_info: &LoadInfo, version: u32, flags: u32) -> bool {
+    match version {
+        1 => checksum(load_info, 1) == load_info.checksum,
+        2 => checksum(load_info, 2) ^ flags == load_info.checksum,
+        _ => false,
+    }
+}
+
+/// Version number written over the header of an app removed at runtime.
+///
+/// Clearing the version field only requires programming bits to zero, so an
+/// image can be retired without erasing its flash pages. The rest of the
+/// header is left intact so that the image can still be skipped over.
+const REMOVED_VERSION: u32 = 0;
+
+/// Computes the header checksum as if the header had version `version`.
+fn checksum(load_info: &LoadInfo, version: u32) -> u32 {
+    version ^ load_info.total_size ^ load_info.entry_offset ^ load_info.rel_data_offset ^
+    load_info.rel_data_size ^ load_info.text_offset ^ load_info.text_size ^
+    load_info.got_offset ^ load_info.got_size ^ load_info.data_offset ^
+    load_info.data_size ^ load_info.bss_mem_offset ^ load_info.bss_size ^
+    load_info.min_stack_len ^ load_info.min_app_heap_len ^ load_info.min_kernel_heap_len ^
+    load_info.pkg_name_offset ^ load_info.pkg_name_size
+}
+
+/// Returns the header of an app that was removed at runtime, if `address`
+/// points to one.
+unsafe fn parse_removed_load_info(address: *const u8) -> Option<&'static LoadInfo> {
+    let load_info = &*(address as *const LoadInfo);
+
+    if load_info.version != REMOVED_VERSION {
         return None;
     }
 
-    let checksum =
-        load_info.version ^ load_info.total_size ^ load_info.entry_offset ^
-        load_info.rel_data_offset ^ load_info.rel_data_size ^ load_info.text_offset ^
-        load_info.text_size ^ load_info.got_offset ^
-        load_info.got_size ^
-        load_info.data_offset ^ load_info.data_size ^ load_info.bss_mem_offset ^
-        load_info.bss_size ^
-        load_info.min_stack_len ^ load_info.min_app_heap_len ^
-        load_info.min_kernel_heap_len ^ load_info.pkg_name_offset ^ load_info.pkg_name_size;
+    // The original version was cleared, so try each one
+    if checksum_valid(load_info, 1, 0) ||
+       checksum_valid(load_info, 2, read_v2_flags(address, 2)) {
+        Some(load_info)
+    } else {
+        None
+    }
+}
+
+/// Size of the RAM region a process needs, rounded up to a power of two so it
+/// can be covered by a single MPU region.
+fn memory_size(load_info: &LoadInfo, flags: u32) -> usize {
+    let data_len = align8!(load_info.bss_mem_offset + load_info.bss_size);
+    let stack_len = align8!(load_info.min_stack_len);
+    let app_heap_len = align8!(load_info.min_app_heap_len);
+    let kernel_heap_len = align8!(load_info.min_kernel_heap_len);
+    let stack_guard_len = 2 * stack_guard_size(flags) as u32;
+    // Queues deeper than the default do not come out of the kernel heap
+    let extra_queue_len = (callback_queue_depth(flags)
+        .saturating_sub(DEFAULT_CALLBACK_QUEUE_DEPTH) * mem::size_of::<Task>()) as u32;
+    closest_power_of_two(data_len + stack_guard_len + stack_len + app_heap_len +
+                         kernel_heap_len + extra_queue_len) as usize
+}
+
+/// The address of the memory access that caused the most recent process
+/// fault, if the fault status registers recorded one.
+unsafe fn fault_address() -> Option<usize> {
+    let cfsr = SCB_REGISTERS[1];
+    let mmfar = SCB_REGISTERS[3];
+    let bfar = SCB_REGISTERS[4];
+
+    if (cfsr & 0x80) == 0x80 {
+        Some(mmfar as usize)
+    } else if ((cfsr >> 8) & 0x80) == 0x80 {
+        Some(bfar as usize)
+    } else {
+        None
+    }
+}
+
+/// Summary of an app image header, as seen by code installing apps at
+/// runtime.
+#[derive(Copy, Clone, Debug)]
+pub struct ImageInfo {
+    /// Size of the whole image in flash, including the header.
+    pub total_size: usize,
+    /// Size of the RAM region the image needs once loaded.
+    pub memory_size: usize,
+    /// Whether the image has been removed and only occupies flash.
+    pub removed: bool,
+}
+
+/// Length of the longest header supported at the start of an app image.
+pub fn header_len() -> usize {
+    mem::size_of::<LoadInfo>() + mem::size_of::<LoadInfoV2>()
+}
 
-    if checksum != load_info.checksum {
+/// Validates an app image header held in an arbitrary buffer (e.g. one just
+/// received over a bus).
+///
+/// Returns `None` if the buffer is too short, or if the header has an unknown
+/// version or a bad checksum.
+pub fn parse_image_header(header: &[u8]) -> Option<ImageInfo> {
+    if header.len() < header_len() {
         return None;
     }
 
-    Some(load_info)
+    // The buffer need not be word aligned, so assemble the header one field
+    // at a time.
+    let mut words = [0u32; 20];
+    for (i, word) in words.iter_mut().enumerate() {
+        let b = &header[i * 4..i * 4 + 4];
+        *word = (b[0] as u32) | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+    }
+    let v2_flags = words[19];
+    let load_info: LoadInfo = unsafe { mem::transmute_copy(&words) };
+
+    let removed = load_info.version == REMOVED_VERSION;
+    let valid = if removed {
+        checksum_valid(&load_info, 1, 0) || checksum_valid(&load_info, 2, v2_flags)
+    } else {
+        checksum_valid(&load_info, load_info.version, v2_flags)
+    };
+    if !valid {
+        return None;
+    }
+
+    Some(ImageInfo {
+        total_size: load_info.total_size as usize,
+        memory_size: memory_size(&load_info, v2_flags),
+        removed: removed,
+    })
+}
+
+/// Whether one of `procs` has the package name `name`.
+fn name_taken(procs: &[Option<Process>], name: &str) -> bool {
+    procs.iter().any(|p| p.as_ref().map_or(false, |p| p.package_name == name))
+}
+
+/// Stops every process whose package name an app earlier in flash already
+/// has, so that an app cannot pose as another to be discovered in its place.
+pub unsafe fn remove_duplicate_names() {
+    for i in 0..PROCS.len() {
+        let name = match PROCS[i] {
+            Some(ref p) => p.package_name,
+            None => continue,
+        };
+        if name_taken(&PROCS[..i], name) {
+            debug_warn!("Process {} not started, another app has the same name", name);
+            remove(AppId::new(i));
+        }
+    }
+}
+
+/// Number of process slots that have held an app removed with `remove`. Slots
+/// below this are not reused, so that an `AppId` held by a capsule never names
+/// a newer app.
+static mut SLOTS_RETIRED: usize = 0;
+
+/// Loads the app image at `app_flash_address` and places it in the first
+/// process slot after every slot that is or has been in use, so that it is
+/// scheduled without a reboot.
+///
+/// The process memory is carved out of `remaining_app_memory`, aligned to its
+/// size as required by the MPU. On success, returns the new process's `AppId`
+/// and how many bytes of `remaining_app_memory` were consumed. Returns `None`
+/// if the image is invalid, does not fit, every process slot is taken, or a
+/// process with the same package name is running.
+pub unsafe fn install(app_flash_address: *const u8,
+                      remaining_app_memory: *mut u8,
+                      remaining_app_memory_size: usize,
+                      fault_response: FaultResponse)
+                      -> Option<(AppId, usize)> {
+    let load_info = match parse_and_validate_load_info(app_flash_address) {
+        Some(load_info) => load_info,
+        None => return None,
+    };
+
+    let procs = &mut PROCS;
+    if name_taken(procs, read_package_name(load_info, app_flash_address)) {
+        return None;
+    }
+    let used = procs.iter().rposition(|p| p.is_some()).map_or(0, |idx| idx + 1);
+    let idx = if used > SLOTS_RETIRED { used } else { SLOTS_RETIRED };
+    if idx >= procs.len() {
+        return None;
+    }
+
+    let app_slice_size = memory_size(load_info,
+                                     read_v2_flags(app_flash_address, load_info.version));
+    let align_offset = (app_slice_size - (remaining_app_memory as usize % app_slice_size)) %
+                       app_slice_size;
+    if align_offset + app_slice_size > remaining_app_memory_size {
+        return None;
+    }
+
+    let (process, _, memory_offset) = Process::create(app_flash_address,
+                                                      remaining_app_memory.offset(align_offset as
+                                                                                  isize),
+                                                      remaining_app_memory_size - align_offset,
+                                                      fault_response);
+    process.map(|process| {
+        procs[idx] = Some(process);
+        (AppId::new(idx), align_offset + memory_offset)
+    })
+}
+
+/// Stops the process `appid` and retires its slot.
+///
+/// Any pending callbacks are discarded. Returns the process's image in flash,
+/// so that the caller can retire it, or `None` if there is no such process.
+pub unsafe fn remove(appid: AppId) -> Option<&'static [u8]> {
+    let procs = &mut PROCS;
+    if appid.idx() >= procs.len() {
+        return None;
+    }
+
+    procs[appid.idx()].take().map(|process| {
+        if appid.idx() + 1 > SLOTS_RETIRED {
+            SLOTS_RETIRED = appid.idx() + 1;
+        }
+        revoke_shared_memory(appid, process.mem_start(), process.mem_end());
+        let mut work = process.tasks.len();
+        if process.state == State::Running {
+            work += 1;
+        }
+        HAVE_WORK.set(HAVE_WORK.get() - work);
+        process.text
+    })
+}
+
+/// Removes the MPU regions other processes were given into `[start, end)`,
+/// the memory of `owner`, and tells each affected process through IPC.
+unsafe fn revoke_shared_memory(owner: AppId, start: *const u8, end: *const u8) {
+    for (i, slot) in PROCS.iter_mut().enumerate() {
+        if i == owner.idx() {
+            continue;
+        }
+        if let Some(ref mut process) = *slot {
+            if process.remove_mpu_regions_in(start, end) {
+                process.schedule_ipc(owner, IPCType::Revoke);
+            }
+        }
+    }
 }
 
 #[derive(Default)]
@@ -178,6 +489,10 @@ pub struct Process<'a> {
     ///  |   ↓
     ///  |  ----   <- cur_stack
     ///  |
+    ///  |  ----
+    ///  | Guard
+    ///  |  ----   <- stack_guard
+    ///  |
     ///  |  ----   <- app_mem_start
     ///  |  Data
     ///  |======== <- memory[0]
@@ -191,15 +506,39 @@ pub struct Process<'a> {
     cur_stack: *const u8,
     app_mem_start: *const u8,
 
+    /// Start of the region below the stack that the process may not access.
+    /// A fault inside it means the stack overflowed.
+    stack_guard: *const u8,
+
+    /// Lowest stack pointer observed when switching back from the process
+    stack_low_water: *const u8,
+
+    /// Highest app break the process has requested
+    heap_high_water: *const u8,
+
+    /// Bytes of grant memory allocated by each container, indexed by
+    /// container number
+    grant_usage: &'a mut [usize],
+
     /// How many syscalls have occurred since the process started
     syscall_count: Cell<usize>,
 
     /// What was the most recent syscall
     last_syscall: Cell<Option<Syscall>>,
 
+    /// Number of subscribe, command and allow calls per driver, as (driver
+    /// number, count) pairs in the order drivers were first used
+    driver_syscalls: [(usize, usize); DRIVER_SYSCALL_SLOTS],
+
+    /// Driver syscalls to drivers that did not fit in `driver_syscalls`
+    other_driver_syscalls: usize,
+
     /// Process text segment
     text: &'static [u8],
 
+    /// Flags from the app header (`FLAG_*`)
+    flags: u32,
+
     stored_regs: StoredRegs,
 
     yield_pc: usize,
@@ -222,10 +561,13 @@ pub struct Process<'a> {
     /// The pointer must be aligned to the size. E.g. if the size is 32 bytes, the pointer must be
     /// 32-byte aligned.
     ///
-    mpu_regions: [Cell<(*const u8, usize)>; 5],
+    mpu_regions: [Cell<(*const u8, usize)>; 4],
 
     tasks: RingBuffer<'a, Task>,
 
+    /// Callbacks dropped because `tasks` was full
+    dropped_callbacks: usize,
+
     pub package_name: &'static str,
 }
 
@@ -249,10 +591,27 @@ pub fn processes_blocked() -> bool {
 
 impl<'a> Process<'a> {
     pub fn schedule_ipc(&mut self, from: AppId, cb_type: IPCType) {
-        unsafe {
-            HAVE_WORK.set(HAVE_WORK.get() + 1);
+        self.enqueue_task(Task::IPC((from, cb_type)));
+    }
+
+    /// Adds a task to the callback queue, or counts it as dropped if the
+    /// queue is full.
+    fn enqueue_task(&mut self, task: Task) -> bool {
+        if self.tasks.enqueue(task) {
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            }
+            true
+        } else {
+            self.dropped_callbacks += 1;
+            debug_warn!("Process {} dropped a callback, its queue is full", self.package_name);
+            false
         }
-        self.tasks.enqueue(Task::IPC((from, cb_type)));
+    }
+
+    /// Number of callbacks dropped because the callback queue was full.
+    pub fn dropped_callbacks(&self) -> usize {
+        self.dropped_callbacks
     }
 
     pub fn current_state(&self) -> State {
@@ -270,23 +629,96 @@ impl<'a> Process<'a> {
 
     pub unsafe fn fault_state(&mut self) {
         write_volatile(&mut APP_FAULT, 0);
-        self.state = State::Fault;
+
+        let kind = if self.stack_overflowed() {
+            "stack overflow"
+        } else {
+            "fault"
+        };
 
         match self.fault_response {
             FaultResponse::Panic => {
+                self.state = State::Fault;
                 // process faulted. Panic and print status
-                panic!("Process {} had a fault", self.package_name);
+                match fault_address() {
+                    Some(addr) => {
+                        panic!("Process {} had a {} at {:#010X}",
+                               self.package_name,
+                               kind,
+                               addr)
+                    }
+                    None => panic!("Process {} had a {}", self.package_name, kind),
+                }
             }
             FaultResponse::Restart => {
-                //XXX: unimplemented
-                panic!("Process {} had a fault and could not be restarted",
-                       self.package_name);
-                /*
-                // HAVE_WORK is really screwed up in this case
-                // the tasks ring buffer needs to be cleared
-                // need to re-load() the app
-                 */
+                let address = fault_address();
+                if !self.restart() {
+                    self.state = State::Fault;
+                    panic!("Process {} had a {} and could not be restarted",
+                           self.package_name,
+                           kind);
+                }
+                match address {
+                    Some(addr) => {
+                        debug_warn!("Process {} had a {} at {:#010X} and was restarted",
+                                    self.package_name,
+                                    kind,
+                                    addr)
+                    }
+                    None => {
+                        debug_warn!("Process {} had a {} and was restarted",
+                                    self.package_name,
+                                    kind)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the most recent fault was an access to the stack guard.
+    pub unsafe fn stack_overflowed(&self) -> bool {
+        let guard_start = self.stack_guard as usize;
+        let guard_end = guard_start + stack_guard_size(self.flags);
+        let mstkerr = (SCB_REGISTERS[1] & 0x10) == 0x10;
+        if mstkerr {
+            // Stacking the exception frame faulted, which records no fault
+            // address. The stack pointer was still moved down over the frame.
+            (self.cur_stack as usize) < guard_end
+        } else {
+            fault_address().map_or(false, |addr| addr >= guard_start && addr < guard_end)
+        }
+    }
+
+    /// Reloads the process from flash into its existing memory and schedules
+    /// it from the start, discarding any pending callbacks and grant state.
+    ///
+    /// Returns false if the image in flash is no longer valid.
+    unsafe fn restart(&mut self) -> bool {
+        // A faulting process was running and may have had callbacks queued
+        let mut work = self.tasks.len();
+        if self.state == State::Running {
+            work += 1;
+        }
+
+        // Memory this process shared is about to be reinitialized
+        let this = self as *const Process;
+        let owner = PROCS.iter()
+            .position(|p| p.as_ref().map_or(false, |p| p as *const Process == this));
+        if let Some(idx) = owner {
+            revoke_shared_memory(AppId::new(idx), self.mem_start(), self.mem_end());
+        }
+
+        let (process, _, _) = Process::create(self.text.as_ptr(),
+                                              self.memory.as_mut_ptr(),
+                                              self.memory.len(),
+                                              self.fault_response);
+        match process {
+            Some(process) => {
+                HAVE_WORK.set(HAVE_WORK.get() - work);
+                *self = process;
+                true
             }
+            None => false,
         }
     }
 
@@ -307,12 +739,36 @@ impl<'a> Process<'a> {
         unsafe { self.memory.as_ptr().offset(self.memory.len() as isize) }
     }
 
+    pub fn flash_start(&self) -> *const u8 {
+        self.text.as_ptr()
+    }
+
+    /// Whether the app may execute code from its RAM.
+    pub fn ram_executable(&self) -> bool {
+        self.flags & FLAG_RAM_EXECUTABLE != 0
+    }
+
+    pub fn flash_end(&self) -> *const u8 {
+        unsafe { self.text.as_ptr().offset(self.text.len() as isize) }
+    }
+
+    /// Lowest address of the grant region. Grant memory grows down from
+    /// `mem_end`, so this moves as capsules allocate.
+    pub fn kernel_memory_break(&self) -> *const u8 {
+        self.kernel_memory_break
+    }
+
+    /// Boundary between the stack (growing down) and the heap (growing up).
+    pub fn stack_heap_boundary(&self) -> *const u8 {
+        self.stack_heap_boundary
+    }
+
     pub fn setup_mpu<MPU: mpu::MPU>(&self, mpu: &MPU) {
         let data_start = self.memory.as_ptr() as usize;
-        let data_len = (32 - self.memory.len().leading_zeros()) as u32;
+        let data_len = ((32 - (self.memory.len() as u32).leading_zeros()) - 2) as u32;
 
         let text_start = self.text.as_ptr() as usize;
-        let text_len = ((32 - self.text.len().leading_zeros()) - 2) as u32;
+        let text_len = ((32 - (self.text.len() as u32).leading_zeros()) - 2) as u32;
 
         let mut grant_size = unsafe {
             self.memory.as_ptr().offset(self.memory.len() as isize) as u32 -
@@ -327,11 +783,19 @@ impl<'a> Process<'a> {
         };
         let mgrant_size = grant_size.trailing_zeros() - 1;
 
-        // Data segment read/write/execute
+        // Process RAM is writable, so it is execute-never unless the app
+        // asked otherwise in its header
+        let ram_execute = || if self.ram_executable() {
+            mpu::ExecutePermission::ExecutionPermitted
+        } else {
+            mpu::ExecutePermission::ExecutionNotPermitted
+        };
+
+        // Data segment read/write
         mpu.set_mpu(0,
                     data_start as u32,
                     data_len,
-                    mpu::ExecutePermission::ExecutionPermitted,
+                    ram_execute(),
                     mpu::AccessPermission::ReadWrite);
         // Text segment read/execute (no write)
         mpu.set_mpu(1,
@@ -347,11 +811,20 @@ impl<'a> Process<'a> {
                     mpu::ExecutePermission::ExecutionNotPermitted,
                     mpu::AccessPermission::PrivilegedOnly);
 
+        // Disallow access to the stack guard, so an overflowing stack faults
+        // instead of running into the data segment
+        let mguard_size = stack_guard_size(self.flags).trailing_zeros() - 1;
+        mpu.set_mpu(3,
+                    self.stack_guard as u32,
+                    mguard_size,
+                    mpu::ExecutePermission::ExecutionNotPermitted,
+                    mpu::AccessPermission::PrivilegedOnly);
+
         for (i, region) in self.mpu_regions.iter().enumerate() {
-            mpu.set_mpu((i + 3) as u32,
+            mpu.set_mpu((i + 4) as u32,
                         region.get().0 as u32,
                         region.get().1 as u32,
-                        mpu::ExecutePermission::ExecutionPermitted,
+                        ram_execute(),
                         mpu::AccessPermission::ReadWrite);
         }
     }
@@ -375,11 +848,42 @@ impl<'a> Process<'a> {
         return false;
     }
 
+    /// Stops exposing the region starting at `base`. Returns whether such a
+    /// region was exposed.
+    pub fn remove_mpu_region(&self, base: *const u8) -> bool {
+        for region in self.mpu_regions.iter() {
+            if region.get().0 == base && base != ptr::null() {
+                region.set((ptr::null(), 0));
+                return true;
+            }
+        }
+        return false;
+    }
+
+    /// Stops exposing any region that starts within `[start, end)`. Returns
+    /// whether any region was removed.
+    pub fn remove_mpu_regions_in(&self, start: *const u8, end: *const u8) -> bool {
+        let mut removed = false;
+        for region in self.mpu_regions.iter() {
+            let base = region.get().0;
+            if base != ptr::null() && base >= start && base < end {
+                region.set((ptr::null(), 0));
+                removed = true;
+            }
+        }
+        removed
+    }
+
     pub unsafe fn create(app_flash_address: *const u8,
                          remaining_app_memory: *mut u8,
                          remaining_app_memory_size: usize,
                          fault_response: FaultResponse)
                          -> (Option<Process<'a>>, usize, usize) {
+        if let Some(load_info) = parse_removed_load_info(app_flash_address) {
+            // Skip over apps that were removed at runtime
+            return (None, load_info.total_size as usize, 0);
+        }
+
         if let Some(load_info) = parse_and_validate_load_info(app_flash_address) {
             let app_flash_size = load_info.total_size as usize;
 
@@ -390,12 +894,9 @@ impl<'a> Process<'a> {
                      remaining_app_memory,
                      remaining_app_memory_size) {
                 let stack_len = align8!(load_info.min_stack_len);
-                let app_heap_len = align8!(load_info.min_app_heap_len);
-                let kernel_heap_len = align8!(load_info.min_kernel_heap_len);
 
-                let app_slice_size =
-                    closest_power_of_two(load_result.data_len + stack_len + app_heap_len +
-                                         kernel_heap_len) as usize;
+                let flags = read_v2_flags(app_flash_address, load_info.version);
+                let app_slice_size = memory_size(load_info, flags);
                 // TODO round app_slice_size up to a closer MPU unit.
                 // This is a very conservative approach that rounds up to power of
                 // two. We should be able to make this closer to what we actually need.
@@ -408,8 +909,16 @@ impl<'a> Process<'a> {
                 }
 
                 let app_memory = slice::from_raw_parts_mut(remaining_app_memory, app_slice_size);
-                let stack_heap_boundary = app_memory.as_mut_ptr()
-                    .offset((load_result.data_len + stack_len) as isize);
+
+                // Place the stack guard at the first suitably aligned
+                // address after the data segment, and the stack above it
+                let data_end = load_result.app_mem_start;
+                let guard_size = stack_guard_size(flags);
+                let stack_guard = data_end.offset(((guard_size -
+                                                    data_end as usize % guard_size) %
+                                                   guard_size) as isize);
+                let stack_heap_boundary = stack_guard
+                    .offset((guard_size + stack_len as usize) as isize);
                 let app_memory_break = stack_heap_boundary;
 
                 // Set up initial grant region
@@ -429,9 +938,19 @@ impl<'a> Process<'a> {
                     *opt = ptr::null()
                 }
 
-                // Allocate memory for callback ring buffer
+                // Allocate memory for per-container grant accounting
+                let usage_offset = num_ctrs * mem::size_of::<usize>();
+                kernel_memory_break = kernel_memory_break.offset(-(usage_offset as isize));
+                let grant_usage = slice::from_raw_parts_mut(kernel_memory_break as *mut usize,
+                                                            num_ctrs);
+                for usage in grant_usage.iter_mut() {
+                    *usage = 0;
+                }
+
+                // Allocate memory for callback ring buffer, which holds one
+                // entry more than the number of callbacks it can queue
                 let callback_size = mem::size_of::<Task>();
-                let callback_len = 10;
+                let callback_len = callback_queue_depth(flags) + 1;
                 let callback_offset = callback_len * callback_size;
                 kernel_memory_break = kernel_memory_break.offset(-(callback_offset as isize));
 
@@ -448,11 +967,19 @@ impl<'a> Process<'a> {
                     stack_heap_boundary: stack_heap_boundary,
                     cur_stack: stack_heap_boundary,
                     app_mem_start: load_result.app_mem_start,
+                    stack_guard: stack_guard,
+
+                    stack_low_water: stack_heap_boundary,
+                    heap_high_water: app_memory_break,
+                    grant_usage: grant_usage,
 
                     syscall_count: Cell::new(0),
                     last_syscall: Cell::new(None),
+                    driver_syscalls: [(0, 0); DRIVER_SYSCALL_SLOTS],
+                    other_driver_syscalls: 0,
 
                     text: slice::from_raw_parts(app_flash_address, app_flash_size),
+                    flags: flags,
 
                     stored_regs: Default::default(),
                     yield_pc: load_result.init_fn,
@@ -463,11 +990,11 @@ impl<'a> Process<'a> {
                     fault_response: fault_response,
 
                     mpu_regions: [Cell::new((ptr::null(), 0)),
-                                  Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0))],
                     tasks: tasks,
+                    dropped_callbacks: 0,
                     package_name: load_result.package_name,
                 };
 
@@ -507,6 +1034,9 @@ impl<'a> Process<'a> {
         } else {
             let old_break = self.app_memory_break;
             self.app_memory_break = new_break;
+            if new_break > self.heap_high_water {
+                self.heap_high_water = new_break;
+            }
             Ok(old_break)
         }
     }
@@ -518,16 +1048,59 @@ impl<'a> Process<'a> {
         buf_start_addr >= self.mem_start() && buf_end_addr <= self.mem_end()
     }
 
-    pub unsafe fn alloc(&mut self, size: usize) -> Option<&mut [u8]> {
+    /// Whether the buffer lies within the process's flash region.
+    pub fn in_flash_bounds(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        let buf_end_addr = unsafe { buf_start_addr.offset(size as isize) };
+
+        buf_start_addr >= self.flash_start() && buf_end_addr <= self.flash_end()
+    }
+
+    /// Allocates `size` bytes of grant memory on behalf of container
+    /// `container_num`.
+    pub unsafe fn alloc(&mut self, container_num: usize, size: usize) -> Option<&mut [u8]> {
         let new_break = self.kernel_memory_break.offset(-(size as isize));
         if new_break < self.app_memory_break {
             None
         } else {
             self.kernel_memory_break = new_break;
+            self.grant_usage.get_mut(container_num).map(|usage| *usage += size);
             Some(slice::from_raw_parts_mut(new_break as *mut u8, size))
         }
     }
 
+    /// Bytes of grant memory allocated by container `container_num`.
+    pub fn grant_usage(&self, container_num: usize) -> usize {
+        self.grant_usage.get(container_num).map_or(0, |usage| *usage)
+    }
+
+    /// Bytes of heap currently in use by the process.
+    pub fn heap_usage(&self) -> usize {
+        self.app_memory_break as usize - self.stack_heap_boundary as usize
+    }
+
+    /// Bytes of stack currently in use by the process.
+    pub fn stack_usage(&self) -> usize {
+        self.stack_heap_boundary as usize - self.cur_stack as usize
+    }
+
+    /// Bytes of grant memory in use, including kernel bookkeeping.
+    pub fn grant_total(&self) -> usize {
+        self.mem_end() as usize - self.kernel_memory_break as usize
+    }
+
+    /// Most heap the process has used since it started.
+    pub fn heap_high_water(&self) -> usize {
+        self.heap_high_water as usize - self.stack_heap_boundary as usize
+    }
+
+    /// Most stack the process has been observed to use since it started.
+    ///
+    /// The stack pointer is only sampled on context switches, so this is a
+    /// lower bound on the true maximum.
+    pub fn stack_high_water(&self) -> usize {
+        self.stack_heap_boundary as usize - self.stack_low_water as usize
+    }
+
     pub unsafe fn free<T>(&mut self, _: *mut T) {}
 
     unsafe fn container_ptr<T>(&self, container_num: usize) -> *mut *mut T {
@@ -544,7 +1117,7 @@ impl<'a> Process<'a> {
                                                      -> Option<*mut T> {
         let ctr_ptr = self.container_ptr::<T>(container_num);
         if (*ctr_ptr).is_null() {
-            self.alloc(mem::size_of::<T>()).map(|root_arr| {
+            self.alloc(container_num, mem::size_of::<T>()).map(|root_arr| {
                 let root_ptr = root_arr.as_mut_ptr() as *mut T;
                 *root_ptr = Default::default();
                 write_volatile(ctr_ptr, root_ptr);
@@ -601,8 +1174,11 @@ impl<'a> Process<'a> {
         write_volatile(&mut SYSCALL_FIRED, 0);
         let psp = switch_to_user(self.cur_stack,
                                  self.memory.as_ptr(),
-                                 mem::transmute(&mut self.stored_regs));
+                                 mem::transmute(&mut self.stored_regs)) as *const u8;
         self.cur_stack = psp;
+        if psp < self.stack_low_water {
+            self.stack_low_water = psp;
+        }
     }
 
     pub fn svc_number(&self) -> Option<Syscall> {
@@ -617,14 +1193,33 @@ impl<'a> Process<'a> {
                 2 => Some(Syscall::COMMAND),
                 3 => Some(Syscall::ALLOW),
                 4 => Some(Syscall::MEMOP),
+                5 => Some(Syscall::ALLOW_READONLY),
                 _ => None,
             }
         }
     }
 
-    pub fn incr_syscall_count(&self) {
+    pub fn incr_syscall_count(&mut self) {
+        let syscall = self.svc_number();
         self.syscall_count.set(self.syscall_count.get() + 1);
-        self.last_syscall.set(self.svc_number());
+        self.last_syscall.set(syscall);
+
+        match syscall {
+            Some(Syscall::SUBSCRIBE) |
+            Some(Syscall::COMMAND) |
+            Some(Syscall::ALLOW) |
+            Some(Syscall::ALLOW_READONLY) => {
+                let driver_num = self.r0();
+                let slot = self.driver_syscalls
+                    .iter_mut()
+                    .find(|&&mut (driver, count)| driver == driver_num || count == 0);
+                match slot {
+                    Some(slot) => *slot = (driver_num, slot.1 + 1),
+                    None => self.other_driver_syscalls += 1,
+                }
+            }
+            _ => {}
+        }
     }
 
     pub fn sp(&self) -> usize {
@@ -647,6 +1242,9 @@ impl<'a> Process<'a> {
     }
 
     pub fn set_return_code(&mut self, return_code: ReturnCode) {
+        if let ReturnCode::SuccessWithValues { value1, .. } = return_code {
+            self.set_r1(value1 as isize);
+        }
         let r: isize = return_code.into();
         self.set_r0(r);
     }
@@ -656,6 +1254,11 @@ impl<'a> Process<'a> {
         unsafe { write_volatile(pspr, val) }
     }
 
+    pub fn set_r1(&mut self, val: isize) {
+        let pspr = self.cur_stack as *mut isize;
+        unsafe { write_volatile(pspr.offset(1), val) }
+    }
+
     pub fn r1(&self) -> usize {
         let pspr = self.cur_stack as *const usize;
         unsafe { read_volatile(pspr.offset(1)) }
@@ -806,6 +1409,11 @@ impl<'a> Process<'a> {
                 writer.write_fmt(format_args!("Faulting Memory Address:            {:#010X}\r\n",
                                               mmfar));
         }
+        if self.stack_overflowed() {
+            let _ =
+                writer.write_fmt(format_args!("Stack Overflow (guard at):          {:#010X}\r\n",
+                                              self.stack_guard as usize));
+        }
         if bfarvalid {
             let _ =
                 writer.write_fmt(format_args!("Bus Fault Address:                  {:#010X}\r\n",
@@ -904,10 +1512,11 @@ impl<'a> Process<'a> {
             // You can thank the piece of garbage rustfmt for this.
             let _ = writer.write_fmt(format_args!("\
             App: {}   -   [{:?}]\
-            \r\n Events Queued: {}   Syscall Count: {}   ",
+            \r\n Events Queued: {}   Events Dropped: {}   Syscall Count: {}   ",
                                                   self.package_name,
                                                   self.state,
                                                   events_queued,
+                                                  self.dropped_callbacks,
                                                   syscall_count,
                                                   ));
 
@@ -916,6 +1525,26 @@ impl<'a> Process<'a> {
                 None => writer.write_fmt(format_args!("Last Syscall: None")),
             };
 
+            let _ = writer.write_fmt(format_args!("\r\n Syscalls by Driver:"));
+            for &(driver, count) in self.driver_syscalls.iter().filter(|&&(_, count)| count > 0) {
+                let _ = writer.write_fmt(format_args!(" [{:#x}]: {}", driver, count));
+            }
+            if self.other_driver_syscalls > 0 {
+                let _ = writer.write_fmt(format_args!(" [other]: {}", self.other_driver_syscalls));
+            }
+
+            let _ = writer.write_fmt(format_args!("\
+            \r\n Stack High Water: {}   Heap High Water: {}\
+            \r\n Grant Usage by Container:",
+                                                  self.stack_high_water(),
+                                                  self.heap_high_water(),
+                                                  ));
+            for (container_num, usage) in self.grant_usage.iter().enumerate() {
+                if *usage > 0 {
+                    let _ = writer.write_fmt(format_args!(" [{}]: {}", container_num, usage));
+                }
+            }
+
             let _ = writer.write_fmt(format_args!("\
             \r\n\
             \r\n ╔═══════════╤══════════════\
@@ -1031,13 +1660,19 @@ struct LoadResult {
     /// and BSS.
     app_mem_start: *const u8,
 
-    /// The length of the data segment
-    data_len: u32,
-
     /// The process's package name (used for IPC)
     package_name: &'static str,
 }
 
+/// The package name in the header of the app at `flash_start_addr`, or an
+/// empty name if it is not valid UTF-8.
+unsafe fn read_package_name(load_info: &LoadInfo, flash_start_addr: *const u8) -> &'static str {
+    let package_name_byte_array =
+        slice::from_raw_parts(flash_start_addr.offset(load_info.pkg_name_offset as isize),
+                              load_info.pkg_name_size as usize);
+    str::from_utf8(package_name_byte_array).unwrap_or("")
+}
+
 /// Loads the process into memory
 ///
 /// Loads the process whos binary starts at `flash_start_addr` into the memory
@@ -1057,17 +1692,10 @@ unsafe fn load(load_info: &'static LoadInfo,
                -> Option<LoadResult> {
     let mem_end = mem_base.offset(mem_size as isize);
 
-    let package_name_byte_array =
-        slice::from_raw_parts(flash_start_addr.offset(load_info.pkg_name_offset as isize),
-                              load_info.pkg_name_size as usize);
-    let mut app_name_str = "";
-    let _ = str::from_utf8(package_name_byte_array).map(|name_str| { app_name_str = name_str; });
-
     let mut load_result = LoadResult {
-        package_name: app_name_str,
+        package_name: read_package_name(load_info, flash_start_addr),
         init_fn: 0,
         app_mem_start: ptr::null(),
-        data_len: 0,
     };
 
     let text_start = flash_start_addr.offset(load_info.text_offset as isize);
@@ -1150,7 +1778,195 @@ unsafe fn load(load_info: &'static LoadInfo,
 
     let aligned_mem_start = align8!(load_info.bss_mem_offset + load_info.bss_size);
     load_result.app_mem_start = mem_base.offset(aligned_mem_start as isize);
-    load_result.data_len = aligned_mem_start;
 
     Some(load_result)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use core::cell::Cell;
+    use platform::mpu::{self, AccessPermission, ExecutePermission};
+    use std::boxed::Box;
+    use super::*;
+
+    /// Records the last configuration of each region instead of programming
+    /// hardware: (start, len, execute never, access permission).
+    pub struct RecordingMPU {
+        regions: [Cell<(u32, u32, bool, u32)>; 8],
+    }
+
+    impl RecordingMPU {
+        pub fn new() -> RecordingMPU {
+            RecordingMPU {
+                regions: [Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0)),
+                          Cell::new((0, 0, false, 0))],
+            }
+        }
+
+        pub fn region(&self, region_num: usize) -> (u32, u32, bool, u32) {
+            self.regions[region_num].get()
+        }
+    }
+
+    impl mpu::MPU for RecordingMPU {
+        fn enable_mpu(&self) {}
+
+        fn set_mpu(&self,
+                   region_num: u32,
+                   start_addr: u32,
+                   len: u32,
+                   execute: ExecutePermission,
+                   ap: AccessPermission) {
+            let xn = execute as u32 == ExecutePermission::ExecutionNotPermitted as u32;
+            self.regions[region_num as usize].set((start_addr, len, xn, ap as u32));
+        }
+    }
+
+    /// Size in bytes of an MPU region with size field `len`.
+    pub fn region_size(len: u32) -> usize {
+        1 << (len + 1)
+    }
+
+    const IMAGE_LEN: usize = 512;
+    const MEMORY_LEN: usize = 8192;
+    const STACK_LEN: usize = 512;
+
+    /// Builds a version 2 app image with header `flags` and loads it. The
+    /// image and memory are leaked, since the process refers to them for the
+    /// rest of the test.
+    pub fn load_app(flags: u32) -> Process<'static> {
+        let mut image = vec![0u32; IMAGE_LEN / 4];
+        image[0] = 2; // version
+        image[1] = IMAGE_LEN as u32; // total_size
+        image[2] = 85; // entry_offset, odd for Thumb
+        image[12] = 64; // bss_size
+        image[13] = STACK_LEN as u32; // min_stack_len
+        image[14] = 256; // min_app_heap_len
+        image[15] = 2048; // min_kernel_heap_len
+        image[16] = 80; // pkg_name_offset
+        image[17] = 4; // pkg_name_size
+        image[18] = image[..18].iter().fold(flags, |sum, word| sum ^ word);
+        image[19] = flags;
+        image[20] = 0x74736574; // "test"
+        let image: &'static [u32] = unsafe { &*Box::into_raw(image.into_boxed_slice()) };
+
+        // Process memory must be aligned to its size
+        let memory: &'static mut [u8] =
+            unsafe { &mut *Box::into_raw(vec![0u8; 2 * MEMORY_LEN].into_boxed_slice()) };
+        let offset = (MEMORY_LEN - memory.as_ptr() as usize % MEMORY_LEN) % MEMORY_LEN;
+
+        let (process, _, _) = unsafe {
+            Process::create(image.as_ptr() as *const u8,
+                            memory[offset..].as_mut_ptr(),
+                            MEMORY_LEN,
+                            FaultResponse::Panic)
+        };
+        process.expect("test app failed to load")
+    }
+
+    fn load_app_mpu(flags: u32) -> (Process<'static>, RecordingMPU) {
+        let process = load_app(flags);
+        let mpu = RecordingMPU::new();
+        process.setup_mpu(&mpu);
+        (process, mpu)
+    }
+
+    #[test]
+    fn ram_region_covers_process_memory() {
+        let (process, mpu) = load_app_mpu(0);
+        let (start, len, xn, ap) = mpu.region(0);
+        assert_eq!(start, process.mem_start() as u32);
+        assert_eq!(region_size(len),
+                   process.mem_end() as usize - process.mem_start() as usize);
+        assert!(xn);
+        assert_eq!(ap, AccessPermission::ReadWrite as u32);
+    }
+
+    #[test]
+    fn ram_region_executable_with_flag() {
+        let (_, mpu) = load_app_mpu(FLAG_RAM_EXECUTABLE);
+        let (_, _, xn, ap) = mpu.region(0);
+        assert!(!xn);
+        assert_eq!(ap, AccessPermission::ReadWrite as u32);
+    }
+
+    #[test]
+    fn flash_region_read_only_and_executable() {
+        let (process, mpu) = load_app_mpu(0);
+        let (start, len, xn, ap) = mpu.region(1);
+        assert_eq!(start, process.flash_start() as u32);
+        assert_eq!(region_size(len), IMAGE_LEN);
+        assert!(!xn);
+        assert_eq!(ap, AccessPermission::ReadOnly as u32);
+    }
+
+    #[test]
+    fn grant_region_privileged_only() {
+        let (process, mpu) = load_app_mpu(0);
+        let (start, len, xn, ap) = mpu.region(2);
+        assert_eq!(start as usize + region_size(len), process.mem_end() as u32 as usize);
+        assert!(start <= process.kernel_memory_break() as u32);
+        assert!(start >= process.stack_heap_boundary() as u32);
+        assert!(xn);
+        assert_eq!(ap, AccessPermission::PrivilegedOnly as u32);
+    }
+
+    #[test]
+    fn stack_guard_below_stack() {
+        let (process, mpu) = load_app_mpu(0);
+        let (start, len, xn, ap) = mpu.region(3);
+        assert_eq!(start, process.stack_guard as u32);
+        assert_eq!(region_size(len), DEFAULT_STACK_GUARD_SIZE);
+        assert_eq!(start as usize % DEFAULT_STACK_GUARD_SIZE, 0);
+        assert!(start >= process.app_mem_start as u32);
+        assert_eq!(start as usize + region_size(len) + STACK_LEN,
+                   process.stack_heap_boundary() as u32 as usize);
+        assert!(xn);
+        assert_eq!(ap, AccessPermission::PrivilegedOnly as u32);
+    }
+
+    #[test]
+    fn stack_guard_size_from_header() {
+        let (process, mpu) = load_app_mpu(10 << FLAG_STACK_GUARD_SHIFT);
+        let (start, len, _, _) = mpu.region(3);
+        assert_eq!(start, process.stack_guard as u32);
+        assert_eq!(region_size(len), 1024);
+        assert_eq!(start % 1024, 0);
+    }
+
+    #[test]
+    fn ipc_regions_follow_ram_permissions() {
+        let process = load_app(0);
+        let base = process.mem_start();
+        assert!(process.add_mpu_region(base, 256));
+
+        let mpu = RecordingMPU::new();
+        process.setup_mpu(&mpu);
+        let (start, len, xn, ap) = mpu.region(4);
+        assert_eq!(start, base as u32);
+        assert_eq!(region_size(len), 256);
+        assert!(xn);
+        assert_eq!(ap, AccessPermission::ReadWrite as u32);
+        for region in 5..8 {
+            assert_eq!(mpu.region(region).1, 0);
+        }
+
+        assert!(process.remove_mpu_region(base));
+        process.setup_mpu(&mpu);
+        assert_eq!(mpu.region(4).1, 0);
+    }
+
+    #[test]
+    fn ipc_regions_rejected_unless_aligned_power_of_two() {
+        let process = load_app(0);
+        let base = process.mem_start();
+        assert!(!process.add_mpu_region(base, 384));
+        assert!(!process.add_mpu_region(unsafe { base.offset(128) }, 256));
+    }
+}
@@ -1,48 +1,123 @@
 //! returncode.rs -- Standard return type for invoking operations, returning
 //! success or an error code.
 //!
+//! The table in the `return_codes!` invocation below is the only place the
+//! numeric values are defined. `tools/gen_returncodes.py` reads it to generate
+//! `userland/libtock/returncode.h`; run `make returncodes` after changing it.
+//!
 //!  Author: Philip Levis <pal@cs.stanford.edu>
 //!  Date: Dec 22, 2016
 
-#[derive(Clone, Copy, PartialEq)]
-pub enum ReturnCode {
-    SuccessWithValue { value: usize }, // Success value must be positive
-    SUCCESS,
-    FAIL, //.......... Generic failure condition
-    EBUSY, //......... Underlying system is busy; retry
-    EALREADY, //...... The state requested is already set
-    EOFF, //.......... The component is powered down
-    ERESERVE, //...... Reservation required before use
-    EINVAL, //........ An invalid parameter was passed
-    ESIZE, //......... Parameter passed was too large
-    ECANCEL, //....... Operation cancelled by a call
-    ENOMEM, //........ Memory required not available
-    ENOSUPPORT, //.... Operation or command is unsupported
-    ENODEVICE, //..... Device does not exist
-}
+use core::convert::TryFrom;
+
+macro_rules! return_codes {
+    ($($(#[$attr:meta])* $name:ident = $value:expr,)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum ReturnCode {
+            SuccessWithValue { value: usize }, // Success value must be positive
+            SuccessWithValues { value0: usize, value1: usize }, // value1 is returned in r1
+            $($(#[$attr])* $name,)*
+        }
+
+        /// Every `ReturnCode` without a value, in the order of the table.
+        pub const RETURN_CODES: &'static [ReturnCode] = &[$(ReturnCode::$name,)*];
 
-impl From<ReturnCode> for isize {
-    fn from(original: ReturnCode) -> isize {
-        match original {
-            ReturnCode::SuccessWithValue { value } => value as isize,
-            ReturnCode::SUCCESS => 0,
-            ReturnCode::FAIL => -1,
-            ReturnCode::EBUSY => -2,
-            ReturnCode::EALREADY => -3,
-            ReturnCode::EOFF => -4,
-            ReturnCode::ERESERVE => -5,
-            ReturnCode::EINVAL => -6,
-            ReturnCode::ESIZE => -7,
-            ReturnCode::ECANCEL => -8,
-            ReturnCode::ENOMEM => -9,
-            ReturnCode::ENOSUPPORT => -10,
-            ReturnCode::ENODEVICE => -11,
+        impl From<ReturnCode> for isize {
+            fn from(original: ReturnCode) -> isize {
+                match original {
+                    ReturnCode::SuccessWithValue { value } => value as isize,
+                    ReturnCode::SuccessWithValues { value0, .. } => value0 as isize,
+                    $(ReturnCode::$name => $value,)*
+                }
+            }
+        }
+
+        impl TryFrom<isize> for ReturnCode {
+            type Err = ();
+
+            /// Converts a value returned by a system call back into a
+            /// `ReturnCode`. Positive values become `SuccessWithValue`; unknown
+            /// negative values are an error.
+            fn try_from(value: isize) -> Result<ReturnCode, ()> {
+                $(if value == $value {
+                    return Ok(ReturnCode::$name);
+                })*
+                if value > 0 {
+                    Ok(ReturnCode::SuccessWithValue { value: value as usize })
+                } else {
+                    Err(())
+                }
+            }
         }
     }
 }
 
+return_codes! {
+    /// Success
+    SUCCESS = 0,
+    /// Generic failure condition
+    FAIL = -1,
+    /// Underlying system is busy; retry
+    EBUSY = -2,
+    /// The state requested is already set
+    EALREADY = -3,
+    /// The component is powered down
+    EOFF = -4,
+    /// Reservation required before use
+    ERESERVE = -5,
+    /// An invalid parameter was passed
+    EINVAL = -6,
+    /// Parameter passed was too large
+    ESIZE = -7,
+    /// Operation cancelled by a call
+    ECANCEL = -8,
+    /// Memory required not available
+    ENOMEM = -9,
+    /// Operation or command is unsupported
+    ENOSUPPORT = -10,
+    /// Device does not exist
+    ENODEVICE = -11,
+    /// Caller is not permitted to perform the operation
+    EACCES = -12,
+    /// Value is outside the range the operation accepts
+    ERANGE = -13,
+    /// Operation did not complete in time
+    ETIMEOUT = -14,
+    /// Authentication or signature verification failed
+    EAUTH = -15,
+    /// Transmission was not acknowledged by the receiver
+    ENOACK = -16,
+}
+
 impl From<ReturnCode> for usize {
     fn from(original: ReturnCode) -> usize {
         isize::from(original) as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryFrom;
+    use super::*;
+
+    #[test]
+    fn return_codes_round_trip() {
+        for code in RETURN_CODES.iter() {
+            assert_eq!(ReturnCode::try_from(isize::from(*code)), Ok(*code));
+        }
+    }
+
+    #[test]
+    fn success_values_round_trip() {
+        for value in [1, 2, 0x1000].iter() {
+            let code = ReturnCode::SuccessWithValue { value: *value };
+            assert_eq!(ReturnCode::try_from(isize::from(code)), Ok(code));
+        }
+    }
+
+    #[test]
+    fn unknown_return_code_rejected() {
+        let last = RETURN_CODES.iter().map(|code| isize::from(*code)).min().unwrap();
+        assert_eq!(ReturnCode::try_from(last - 1), Err(()));
+    }
+}
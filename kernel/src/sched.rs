@@ -5,6 +5,7 @@ use process;
 use process::{Process, Task};
 use returncode::ReturnCode;
 use syscall::Syscall;
+use syscall_trace;
 
 pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                                                chip: &mut C,
@@ -64,7 +65,9 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
 
         // process had a system call, count it
         process.incr_syscall_count();
-        match process.svc_number() {
+        let syscall = process.svc_number();
+        let args = [process.r0(), process.r1(), process.r2(), process.r3()];
+        match syscall {
             Some(Syscall::MEMOP) => {
                 let brk_type = process.r0();
                 let r1 = process.r1();
@@ -80,11 +83,52 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                             .map(|addr| ReturnCode::SuccessWithValue { value: addr as usize })
                             .unwrap_or(ReturnCode::ENOMEM)
                     },
+                    2 /* Memory usage */ => {
+                        let usage = match r1 {
+                            0 => Some(process.heap_usage()),
+                            1 => Some(process.stack_usage()),
+                            2 => Some(process.grant_total()),
+                            3 => Some(process.heap_high_water()),
+                            4 => Some(process.stack_high_water()),
+                            _ => None,
+                        };
+                        usage.map(|value| ReturnCode::SuccessWithValue { value: value })
+                            .unwrap_or(ReturnCode::EINVAL)
+                    },
+                    3 /* Grant usage of a container */ => {
+                        ReturnCode::SuccessWithValue { value: process.grant_usage(r1) }
+                    },
+                    4 /* Memory start */ => {
+                        ReturnCode::SuccessWithValue { value: process.mem_start() as usize }
+                    },
+                    5 /* Memory end */ => {
+                        ReturnCode::SuccessWithValue { value: process.mem_end() as usize }
+                    },
+                    6 /* Flash start */ => {
+                        ReturnCode::SuccessWithValue { value: process.flash_start() as usize }
+                    },
+                    7 /* Flash end */ => {
+                        ReturnCode::SuccessWithValue { value: process.flash_end() as usize }
+                    },
+                    8 /* Grant region begin */ => {
+                        ReturnCode::SuccessWithValue {
+                            value: process.kernel_memory_break() as usize
+                        }
+                    },
+                    9 /* Stack/heap boundary */ => {
+                        ReturnCode::SuccessWithValue {
+                            value: process.stack_heap_boundary() as usize
+                        }
+                    },
+                    10 /* Dropped callbacks */ => {
+                        ReturnCode::SuccessWithValue { value: process.dropped_callbacks() }
+                    },
                     _ => ReturnCode::ENOSUPPORT
                 };
                 process.set_return_code(res);
             }
             Some(Syscall::YIELD) => {
+                syscall_trace::record(appid.idx(), syscall, args, 0);
                 process.yield_state();
                 process.pop_syscall_stack();
 
@@ -112,17 +156,25 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
             }
             Some(Syscall::COMMAND) => {
                 let res = platform.with_driver(process.r0(), |driver| match driver {
-                    Some(d) => d.command(process.r1(), process.r2(), appid),
+                    Some(d) => d.command2(process.r1(), process.r2(), process.r3(), appid),
                     None => ReturnCode::ENODEVICE,
                 });
+                // The second return value is 0 unless the driver returned two
+                process.set_r1(0);
                 process.set_return_code(res);
             }
             Some(Syscall::ALLOW) => {
                 let res = platform.with_driver(process.r0(), |driver| {
                     match driver {
                         Some(d) => {
-                            let start_addr = process.r2() as *mut u8;
+                            let mut start_addr = process.r2() as *mut u8;
                             let size = process.r3();
+                            // A null, zero-length buffer withdraws an earlier
+                            // allow. AppSlices must not be null, so use an
+                            // empty slice at the start of process memory.
+                            if start_addr.is_null() && size == 0 {
+                                start_addr = process.mem_start() as *mut u8;
+                            }
                             if process.in_exposed_bounds(start_addr, size) {
                                 let slice = ::AppSlice::new(start_addr as *mut u8, size, appid);
                                 d.allow(appid, process.r1(), slice)
@@ -135,8 +187,31 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                 });
                 process.set_return_code(res);
             }
+            Some(Syscall::ALLOW_READONLY) => {
+                let res = platform.with_driver(process.r0(), |driver| {
+                    match driver {
+                        Some(d) => {
+                            let mut start_addr = process.r2() as *mut u8;
+                            let size = process.r3();
+                            if start_addr.is_null() && size == 0 {
+                                start_addr = process.mem_start() as *mut u8;
+                            }
+                            if process.in_exposed_bounds(start_addr, size) ||
+                               process.in_flash_bounds(start_addr, size) {
+                                let slice = ::AppSlice::new(start_addr as *mut u8, size, appid);
+                                d.allow_readonly(appid, process.r1(), slice)
+                            } else {
+                                ReturnCode::EINVAL /* memory not owned by process */
+                            }
+                        }
+                        None => ReturnCode::ENODEVICE,
+                    }
+                });
+                process.set_return_code(res);
+            }
             _ => {}
         }
+        syscall_trace::record(appid.idx(), syscall, args, process.r0() as isize);
     }
     systick.reset();
 }
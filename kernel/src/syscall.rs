@@ -5,4 +5,5 @@ pub enum Syscall {
     COMMAND = 2,
     ALLOW = 3,
     MEMOP = 4,
+    ALLOW_READONLY = 5,
 }
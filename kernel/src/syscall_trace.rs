@@ -0,0 +1,152 @@
+//! Log of recent system calls for debugging misbehaving apps.
+//!
+//! When the kernel is built with the `syscall_trace` feature, the scheduler
+//! records every system call: the calling process, the class of call, the
+//! driver and minor numbers, the remaining arguments and the value returned to
+//! the app. The most recent `TRACE_LEN` calls are kept; older ones are
+//! overwritten. Boards print them with `dump`, e.g. from their panic handler.
+//! While the kernel runs, `log` writes them to the `debug!` output; the
+//! console calls it for the input line `@kernel trace`.
+//!
+//! Boards enable it with `features = ["syscall_trace"]` on their `kernel`
+//! dependency. Without the feature `record` does nothing and `dump` prints
+//! nothing.
+
+use core::fmt::{self, Display, Formatter, Write};
+use debug;
+use syscall::Syscall;
+
+/// Number of system calls kept in the trace.
+pub const TRACE_LEN: usize = 64;
+
+#[derive(Copy, Clone)]
+struct Record {
+    appid: usize,
+    syscall: Option<Syscall>,
+    /// `r0`: the driver number, or the operation for `memop`
+    driver: usize,
+    /// `r1`: the minor number, or the argument for `memop`
+    minor: usize,
+    arg0: usize,
+    arg1: usize,
+    result: isize,
+}
+
+const EMPTY: Record = Record {
+    appid: 0,
+    syscall: None,
+    driver: 0,
+    minor: 0,
+    arg0: 0,
+    arg1: 0,
+    result: 0,
+};
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.syscall {
+            Some(syscall) => write!(f, "app {} {:?}", self.appid, syscall)?,
+            None => write!(f, "app {} unknown", self.appid)?,
+        }
+        write!(f,
+               " {:#x} {} {:#x} {:#x} -> {}",
+               self.driver,
+               self.minor,
+               self.arg0,
+               self.arg1,
+               self.result)
+    }
+}
+
+/// Room a record needs in the `debug!` buffer, with the message prefix.
+const LOG_LINE_LEN: usize = 128;
+
+static mut RECORDS: [Record; TRACE_LEN] = [EMPTY; TRACE_LEN];
+
+/// Index the next record is written to
+static mut NEXT: usize = 0;
+
+/// Number of records written so far, up to `TRACE_LEN`
+static mut COUNT: usize = 0;
+
+/// Number of records `log` has still to write. Recording pauses until it is
+/// done, so that the records are not overwritten meanwhile.
+static mut LOG_REMAINING: usize = 0;
+
+/// Whether the kernel was built with syscall tracing.
+pub fn enabled() -> bool {
+    cfg!(feature = "syscall_trace")
+}
+
+/// Records a system call made by process `appid`. `args` are the values of
+/// `r0`-`r3` when the call was made.
+pub fn record(appid: usize, syscall: Option<Syscall>, args: [usize; 4], result: isize) {
+    if !enabled() {
+        return;
+    }
+    unsafe {
+        if LOG_REMAINING > 0 {
+            return;
+        }
+        RECORDS[NEXT] = Record {
+            appid: appid,
+            syscall: syscall,
+            driver: args[0],
+            minor: args[1],
+            arg0: args[2],
+            arg1: args[3],
+            result: result,
+        };
+        NEXT = (NEXT + 1) % TRACE_LEN;
+        if COUNT < TRACE_LEN {
+            COUNT += 1;
+        }
+    }
+}
+
+/// Writes the recorded system calls, oldest first.
+pub unsafe fn dump<W: Write>(writer: &mut W) {
+    if !enabled() {
+        return;
+    }
+    let _ = writer.write_fmt(format_args!("\r\n---| Syscall Trace |---\r\n"));
+    let first = (NEXT + TRACE_LEN - COUNT) % TRACE_LEN;
+    for i in 0..COUNT {
+        let record = &RECORDS[(first + i) % TRACE_LEN];
+        let _ = writer.write_fmt(format_args!(" {}\r\n", record));
+    }
+}
+
+/// Writes the recorded system calls to the `debug!` output, oldest first.
+///
+/// The trace is larger than the `debug!` buffer, so it is written a part at
+/// a time as earlier output is transmitted. No calls are recorded until it
+/// has all been written.
+pub fn log() {
+    if !enabled() {
+        return;
+    }
+    unsafe {
+        if LOG_REMAINING > 0 {
+            return;
+        }
+        debug!("syscall trace, {} calls:", COUNT);
+        LOG_REMAINING = COUNT;
+    }
+    debug::set_drain_callback(Some(log_more));
+    log_more();
+}
+
+/// Writes as many of the records `log` has still to write as fit.
+fn log_more() {
+    unsafe {
+        while LOG_REMAINING > 0 && debug::room() >= LOG_LINE_LEN {
+            let record = RECORDS[(NEXT + TRACE_LEN - LOG_REMAINING) % TRACE_LEN];
+            LOG_REMAINING -= 1;
+            debug!("{}", record);
+        }
+        if LOG_REMAINING == 0 {
+            debug::set_drain_callback(None);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+#!/usr/bin/env python
+# Generate the libtock return code header from the table in
+# kernel/src/returncode.rs, so the numeric values are defined in one place.
+from __future__ import print_function
+import os
+import re
+import sys
+
+ROOT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "..")
+SOURCE = os.path.join(ROOT, "kernel", "src", "returncode.rs")
+HEADER = os.path.join(ROOT, "userland", "libtock", "returncode.h")
+
+DOC_RE = re.compile(r"^\s*///\s?(.*)$")
+CODE_RE = re.compile(r"^\s*([A-Z][A-Z0-9_]*)\s*=\s*(-?\d+),\s*$")
+
+def parse(path):
+    """Return (name, value, doc) for each row of the `return_codes!` table."""
+    codes = []
+    in_table = False
+    doc = []
+    with open(path) as f:
+        for line in f:
+            if line.startswith("return_codes! {"):
+                in_table = True
+                continue
+            if not in_table:
+                continue
+            if line.startswith("}"):
+                break
+            m = DOC_RE.match(line)
+            if m:
+                doc.append(m.group(1))
+                continue
+            m = CODE_RE.match(line)
+            if m:
+                codes.append((m.group(1), int(m.group(2)), " ".join(doc)))
+                doc = []
+    return codes
+
+def generate(codes):
+    width = max(len(name) for name, _, _ in codes)
+    lines = [
+        "// Generated by tools/gen_returncodes.py from kernel/src/returncode.rs.",
+        "// Do not edit; change the table there and run `make returncodes`.",
+        "",
+        "#pragma once",
+        "",
+    ]
+    for name, value, doc in codes:
+        lines.append("#define {} {:>3}  // {}".format(name.ljust(width), value, doc))
+    return "\n".join(lines) + "\n"
+
+def main():
+    codes = parse(SOURCE)
+    if not codes:
+        print("ERROR: no return codes found in " + SOURCE, file=sys.stderr)
+        sys.exit(1)
+    with open(HEADER, "w") as f:
+        f.write(generate(codes))
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,24 @@
+/* vim: set sw=2 expandtab tw=80: */
+
+#include <stdio.h>
+#include <string.h>
+
+#include <console.h>
+
+// Echoes every line typed into the console. With several apps loaded, type
+// "@console_echo" to send lines to this one.
+char line[64];
+char reply[80];
+
+int main() {
+  while (1) {
+    int len = getnstr(line, sizeof(line) - 1);
+    if (len < 0) {
+      putstr("read failed\n");
+      return len;
+    }
+    line[len] = '\0';
+    snprintf(reply, sizeof(reply), "echo: %s\n", line);
+    putstr(reply);
+  }
+}
@@ -0,0 +1,44 @@
+#include <stdbool.h>
+#include <stdio.h>
+
+#include "led.h"
+#include "timer.h"
+#include "tock.h"
+#include "udp.h"
+
+// Sends a counter every second to a UDP collector on the all-nodes
+// link-local multicast address (ff02::1), port 4000. A collector on a
+// border router or a sniffer host with a 6LoWPAN interface receives it.
+#define COLLECTOR_PORT 4000
+#define LOCAL_PORT 4001
+
+static const unsigned char collector[16] = {
+  0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01
+};
+
+char payload[32];
+
+int main(void) {
+  unsigned char addr[16];
+  udp_get_address(addr);
+  printf("UDP sender at fe80::%02x%02x:%02x%02x:%02x%02x:%02x%02x\n",
+         addr[8], addr[9], addr[10], addr[11], addr[12], addr[13], addr[14], addr[15]);
+
+  int err = udp_bind(LOCAL_PORT);
+  if (err < 0) {
+    printf("Could not bind port %d: %d\n", LOCAL_PORT, err);
+    return err;
+  }
+
+  unsigned int count = 0;
+  while (1) {
+    int len = snprintf(payload, sizeof(payload), "count %u\n", count++);
+    err = udp_send_to(collector, COLLECTOR_PORT, payload, len);
+    if (err == SUCCESS) {
+      led_toggle(0);
+    } else {
+      printf("Send failed: %d\n", err);
+    }
+    delay_ms(1000);
+  }
+}
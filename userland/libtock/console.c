@@ -63,3 +63,41 @@ void putnstr_async(const char *str, size_t len, subscribe_cb cb, void* userdata)
 void putstr(const char *str) {
   putnstr(str, strlen(str));
 }
+
+typedef struct getnstr_data {
+  bool called;
+  int len;
+} getnstr_data_t;
+
+static void getnstr_cb(int len,
+                       int _y __attribute__ ((unused)),
+                       int _z __attribute__ ((unused)),
+                       void* ud) {
+  getnstr_data_t* data = (getnstr_data_t*)ud;
+  data->len = len;
+  data->called = true;
+}
+
+int getnstr(char *str, size_t len) {
+  getnstr_data_t data;
+  data.called = false;
+
+  int err = getnstr_async(str, len, getnstr_cb, &data);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.called);
+  return data.len;
+}
+
+int getnstr_async(char *str, size_t len, subscribe_cb cb, void* userdata) {
+  int err = allow(0, 0, (void*)str, len);
+  if (err < 0) {
+    return err;
+  }
+  return subscribe(0, 0, cb, userdata);
+}
+
+int console_set_terminator(char terminator) {
+  return command(0, 2, terminator);
+}
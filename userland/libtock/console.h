@@ -11,6 +11,15 @@ void putstr(const char* str);
 void putnstr(const char* str, size_t len);
 void putnstr_async(const char* str, size_t len, subscribe_cb cb, void* userdata);
 
+// Reads a line typed into the console into `str`, without its terminator.
+// Returns the number of bytes read, or a negative error code. Lines go to
+// the app selected by typing "@name", or to the first app waiting for one.
+int getnstr(char* str, size_t len);
+int getnstr_async(char* str, size_t len, subscribe_cb cb, void* userdata);
+
+// Ends the lines this app reads at `terminator` instead of a newline.
+int console_set_terminator(char terminator);
+
 #ifdef __cplusplus
 }
 #endif
@@ -35,3 +35,46 @@ int ipc_share(int pid, void* base, int len) {
   return allow(IPC_DRIVER_NUM, pid, base, len);
 }
 
+int ipc_unshare(int pid) {
+  if (pid <= 0) {
+    return -1;
+  }
+  return allow(IPC_DRIVER_NUM, pid, NULL, 0);
+}
+
+int ipc_register_revoke_cb(subscribe_cb callback, void *ud) {
+  return subscribe(IPC_DRIVER_NUM, IPC_REVOKE_CB, callback, ud);
+}
+
+
+int ipc_message_buffer(void* buf, int len, int slots, int depth) {
+  int res = allow(IPC_DRIVER_NUM, IPC_RX_BUFFER, buf, len);
+  if (res < 0) {
+    return res;
+  }
+  return command(IPC_DRIVER_NUM, 0, (slots & 0xff) | ((depth & 0xff) << 8));
+}
+
+int ipc_register_message_cb(subscribe_cb callback, void *ud) {
+  return subscribe(IPC_DRIVER_NUM, IPC_MESSAGE_CB, callback, ud);
+}
+
+static int ipc_send(int pid, int command_num, const void* buf, int len) {
+  int res = allow_readonly(IPC_DRIVER_NUM, IPC_TX_BUFFER, buf, len);
+  if (res < 0) {
+    return res;
+  }
+  return command(IPC_DRIVER_NUM, pid, command_num);
+}
+
+int ipc_send_svc(int pid, const void* buf, int len) {
+  return ipc_send(pid, 2, buf, len);
+}
+
+int ipc_send_client(int pid, const void* buf, int len) {
+  return ipc_send(pid, 3, buf, len);
+}
+
+int ipc_release(int pid) {
+  return command(IPC_DRIVER_NUM, pid, 4);
+}
@@ -6,6 +6,15 @@
 
 #define IPC_DRIVER_NUM 0xff
 
+// Allow and subscribe numbers used for copied messages
+#define IPC_RX_BUFFER 0xfe
+#define IPC_TX_BUFFER 0xfd
+#define IPC_MESSAGE_CB 0xfe
+#define IPC_REVOKE_CB 0xfd
+
+// Maximum number of slots a message receive buffer can be split into
+#define IPC_MAX_QUEUED_MESSAGES 8
+
 #ifdef __cplusplus
 extern "C" {
 #endif
@@ -13,7 +22,8 @@ extern "C" {
 // Performs service discovery
 //
 // Returns the process identifier of the process with the given package name,
-// or a negative value on error.
+// or a negative value on error. Returns EACCES if the board restricts the
+// service to other clients.
 int ipc_discover(const char* pkg_name);
 
 // Registers a service callback for this process.
@@ -21,7 +31,9 @@ int ipc_discover(const char* pkg_name);
 // Service callbacks are called in response to `notify`s from clients and take
 // the following arguments in order:
 //
-//   int pid   - the notifying client's process id
+//   int pid   - the notifying client's process id, filled in by the kernel.
+//               The board may restrict a service to clients with given ids,
+//               which other apps cannot claim.
 //   int len   - the length of the shared buffer or zero if no buffer is shared
 //               from the client.
 //   char* buf - the base address of the shared buffer, or NULL if no buffer is
@@ -48,6 +60,8 @@ int ipc_register_client_cb(int svc_id, subscribe_cb callback, void *ud);
 int ipc_notify_client(int pid);
 
 // Send a notify to the service at the given process id
+//
+// Returns EACCES if the service does not accept this process as a client.
 int ipc_notify_svc(int pid);
 
 // Share a buffer with the given process (either service or client)
@@ -55,8 +69,65 @@ int ipc_notify_svc(int pid);
 // `pid` is the non-zero process id of the recipient.
 // `base` must be aligned to the value of `len`.
 // `len` must be a power-of-two larger than 16.
+//
+// Sharing a new buffer with the same process withdraws the previous one.
 int ipc_share(int pid, void* base, int len);
 
+// Withdraws the buffer shared with the given process.
+//
+// The other process loses access to the buffer and its revoke callback is
+// called. The same happens when this process exits or is restarted.
+int ipc_unshare(int pid);
+
+// Registers the callback for withdrawn buffers.
+//
+// Revoke callbacks are called when another process withdraws a buffer it
+// shared with this process, and take the following arguments in order:
+//
+//   int pid   - the process id that withdrew the buffer
+//   int unused
+//   int unused
+//   void* ud  - `userdata`. same as the argument to this function.
+//
+// Any pointer into the withdrawn buffer must not be used afterwards.
+int ipc_register_revoke_cb(subscribe_cb callback, void *ud);
+
+// Sets up the buffer messages from other processes are copied into.
+//
+// The buffer is split into `slots` equally sized slots, so each message can be
+// at most `len / slots` bytes long. A single sender may occupy at most `depth`
+// slots at once; further sends from it fail with EBUSY until the receiver
+// releases one of its messages. Messages still queued when this is called
+// again are dropped. The queue layout can only be changed while no messages
+// are queued.
+int ipc_message_buffer(void* buf, int len, int slots, int depth);
+
+// Registers the callback for received messages.
+//
+// Message callbacks take the following arguments in order:
+//
+//   int pid   - the sending process id.
+//   int len   - the length of the message.
+//   char* buf - the address of the slot holding the message.
+//   void* ud  - `userdata`. same as the argument to this function.
+//
+// The slot stays valid until `ipc_release` is called for the sender.
+int ipc_register_message_cb(subscribe_cb callback, void *ud);
+
+// Copies a message into the receive buffer of the service at `pid`.
+//
+// `buf` may be in RAM or flash and can be reused as soon as this returns.
+// Returns EBUSY if the service's queue is full, ESIZE if the message does not fit in
+// one of its slots and ERESERVE if the service has no receive buffer.
+int ipc_send_svc(int pid, const void* buf, int len);
+
+// Copies a message (e.g. a reply) into the receive buffer of the client at
+// `pid`. Errors are the same as for `ipc_send_svc`.
+int ipc_send_client(int pid, const void* buf, int len);
+
+// Frees the slot of the oldest message received from `pid`.
+int ipc_release(int pid);
+
 #ifdef __cplusplus
 }
 #endif
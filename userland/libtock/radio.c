@@ -19,9 +19,20 @@ const int COM_CHAN = 3;
 const int COM_POWER = 4;
 const int COM_TX = 5;
 const int COM_READY = 6;
+const int COM_SRC_FILTER = 7;
+const int COM_PAN_FILTER = 8;
+const int COM_GET_CHAN = 9;
+const int COM_GET_POWER = 10;
+const int COM_ENERGY = 11;
+const int COM_RETRIES = 12;
+const int COM_DST_LONG = 13;
+
+// Bit set in the filter commands' argument to enable the filter.
+const unsigned int FILTER_ENABLE = (1 << 16);
 
 const int EVT_TX = 0;
 const int EVT_RX = 1;
+const int EVT_ENERGY = 2;
 
 int radio_init() {
   while (!radio_ready()) {}
@@ -35,16 +46,51 @@ static void cb_tx( __attribute__ ((unused)) int unused0,
   *((bool*)ud) = true;
 }
 
-static void cb_rx( __attribute__ ((unused)) int unused0,
-                __attribute__ ((unused)) int unused1,
-                __attribute__ ((unused)) int unused2,
-                void* ud) {
-  *((bool*)ud) = true;
+typedef struct {
+  bool fired;
+  int result;
+  int len;
+  radio_rx_info_t info;
+} rx_data_t;
+
+// The kernel packs the payload length, LQI and RSSI into the second
+// argument and passes the timestamp in the third.
+static void cb_rx(int result,
+                  int packed,
+                  int timestamp,
+                  void* ud) {
+  rx_data_t* data = (rx_data_t*)ud;
+  data->result = result;
+  data->len = packed & 0xff;
+  data->info.lqi = (packed >> 8) & 0xff;
+  data->info.rssi = (signed char)((packed >> 16) & 0xff);
+  data->info.timestamp = (unsigned int)timestamp;
+  data->fired = true;
 }
 
-// packet contains the payload of the 802.15.4 packet; this will
-// be copied into a packet buffer with header space within the kernel.
-int radio_send(unsigned short addr, const char* packet, unsigned char len) {
+typedef struct {
+  bool fired;
+  int result;
+  int rssi;
+  bool clear;
+} energy_data_t;
+
+static void cb_energy(int result,
+                      int rssi,
+                      int clear,
+                      void* ud) {
+  energy_data_t* data = (energy_data_t*)ud;
+  data->result = result;
+  data->rssi = rssi;
+  data->clear = clear != 0;
+  data->fired = true;
+}
+
+// Bit set in the send command's argument to send to the extended
+// address given with COM_DST_LONG instead of the short address.
+#define SEND_LONG (1 << 24)
+
+static int radio_send_param(unsigned int param, const char* packet, unsigned char len) {
   bool cond = false;
   int err = allow(SYS_RADIO, BUF_TX, (void*)packet, len);
   if (err < 0) {
@@ -56,18 +102,36 @@ int radio_send(unsigned short addr, const char* packet, unsigned char len) {
   }
   // The send system call packs the length and destination address in
   // the 32-bit argument.
-  unsigned int param = addr;
   param |= (len << 16);
   err = command(SYS_RADIO, COM_TX, param);
   if (err != 0) {
     gpio_toggle(0);
     return err; // yield here too?
   } else {
-    yield_for(&cond); // This should return -1, but for some reason
-  }                   // successful calls don't return 0! radio.rs:208
+    yield_for(&cond);
+  }
   return 0;
 }
 
+// packet contains the payload of the 802.15.4 packet; this will
+// be copied into a packet buffer with header space within the kernel.
+int radio_send(unsigned short addr, const char* packet, unsigned char len) {
+  return radio_send_param(addr, packet, len);
+}
+
+int radio_send_long(const unsigned char addr[8], const char* packet, unsigned char len) {
+  unsigned int hi = 0, lo = 0;
+  for (int i = 0; i < 4; i++) {
+    hi = (hi << 8) | addr[i];
+    lo = (lo << 8) | addr[i + 4];
+  }
+  int err = command2(SYS_RADIO, COM_DST_LONG, hi, lo);
+  if (err < 0) {
+    return err;
+  }
+  return radio_send_param(SEND_LONG, packet, len);
+}
+
 // Set local 16-bit short address.
 int radio_set_addr(unsigned short addr) {
   return command(SYS_RADIO, COM_ADDR, (unsigned int)addr);
@@ -78,23 +142,91 @@ int radio_set_addr(unsigned short addr) {
 int radio_set_pan(unsigned short pan) {
   return command(SYS_RADIO, COM_PAN, (unsigned int)pan);
 }
-// Valid channels are 10-26
+// Valid channels are 11-26
 int radio_set_channel(unsigned char channel) {
   return command(SYS_RADIO, COM_CHAN, (unsigned int)channel);
 }
 
+int radio_get_channel() {
+  return command(SYS_RADIO, COM_GET_CHAN, 0);
+}
+
+int radio_set_tx_power(int power) {
+  return command(SYS_RADIO, COM_POWER, power);
+}
+
+int radio_get_tx_power(int* power) {
+  return command2_values(SYS_RADIO, COM_GET_POWER, 0, 0, power);
+}
+
+int radio_set_retries(unsigned char retries) {
+  return command(SYS_RADIO, COM_RETRIES, retries);
+}
+
+// Returns the length of the received payload.
 int radio_receive(const char* packet, unsigned char len) {
-  bool cond = false;
+  return radio_receive_info(packet, len, NULL);
+}
+
+int radio_receive_info(const char* packet, unsigned char len, radio_rx_info_t* info) {
+  rx_data_t data;
+  data.fired = false;
   int err = allow(SYS_RADIO, BUF_RX, (void*)packet, len);
   if (err < 0) {
     return err;
   }
-  err = subscribe(SYS_RADIO, EVT_RX, cb_rx, &cond);
+  err = subscribe(SYS_RADIO, EVT_RX, cb_rx, &data);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.fired);
+  if (data.result < 0) {
+    return data.result;
+  }
+  if (info != NULL) {
+    *info = data.info;
+  }
+  return data.len;
+}
+
+int radio_energy_detect(int* rssi, bool* clear) {
+  energy_data_t data;
+  data.fired = false;
+  int err = subscribe(SYS_RADIO, EVT_ENERGY, cb_energy, &data);
+  if (err < 0) {
+    return err;
+  }
+  err = command(SYS_RADIO, COM_ENERGY, 0);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.fired);
+  if (data.result < 0) {
+    return data.result;
+  }
+  if (rssi != NULL) {
+    *rssi = data.rssi;
+  }
+  if (clear != NULL) {
+    *clear = data.clear;
+  }
+  return SUCCESS;
+}
+
+int radio_set_src_filter(unsigned short addr) {
+  return command(SYS_RADIO, COM_SRC_FILTER, FILTER_ENABLE | addr);
+}
+
+int radio_set_pan_filter(unsigned short pan) {
+  return command(SYS_RADIO, COM_PAN_FILTER, FILTER_ENABLE | pan);
+}
+
+int radio_clear_filters() {
+  int err = command(SYS_RADIO, COM_SRC_FILTER, 0);
   if (err < 0) {
     return err;
   }
-  yield_for(&cond);
-  return (int)packet[1];
+  return command(SYS_RADIO, COM_PAN_FILTER, 0);
 }
 
 int radio_ready() {
@@ -7,6 +7,13 @@
 extern "C" {
 #endif
 
+/* Link metrics of a received packet. */
+typedef struct {
+  int rssi;               // Received signal strength in dBm
+  unsigned char lqi;      // Link quality, 0 (worst) to 255 (best)
+  unsigned int timestamp; // Start of the packet, in kernel alarm tics
+} radio_rx_info_t;
+
 /* System calls for an 802.15.4 radio. */
 int radio_init();
 
@@ -15,16 +22,45 @@ int radio_ready();
 
 // packet contains the payload of the 802.15.4 packet
 int radio_send(unsigned short addr, const char* packet, unsigned char len);
+// Like radio_send, to an extended address (most significant byte first).
+int radio_send_long(const unsigned char addr[8], const char* packet, unsigned char len);
 
+// Waits for a packet and copies its payload into packet. Returns the
+// payload length. Every app with a receive buffer gets each packet.
 int radio_receive(const char* packet, unsigned char len);
+// Like radio_receive, also storing the packet's link metrics in info.
+int radio_receive_info(const char* packet, unsigned char len, radio_rx_info_t* info);
+
+// Measures the energy on the channel (in dBm) and whether it is clear to
+// transmit on.
+int radio_energy_detect(int* rssi, bool* clear);
+
+// Only receive packets from this source address / on this PAN.
+int radio_set_src_filter(unsigned short addr);
+int radio_set_pan_filter(unsigned short pan);
+// Receive all packets again.
+int radio_clear_filters();
+
 // Set local 16-bit short address
 int radio_set_addr(unsigned short addr);
 
 // PAN is the personal area network identifier: it allows multiple
 // networks using the same channel to remain logically distinct
 int radio_set_pan(unsigned short pan);
-// Valid channels are 10-26
+// Valid channels are 11-26. The radio switches channel even if it is on.
 int radio_set_channel(unsigned char channel);
+// Returns the current channel.
+int radio_get_channel();
+
+// Transmit power in dBm. The radio uses the strongest power it supports
+// that does not exceed `power`.
+int radio_set_tx_power(int power);
+// Stores the transmit power in dBm in `power`.
+int radio_get_tx_power(int* power);
+
+// Number of times (0-7) an unacknowledged unicast packet is resent before
+// radio_send returns ENOACK.
+int radio_set_retries(unsigned char retries);
 
 
 #ifdef __cplusplus
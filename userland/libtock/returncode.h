@@ -0,0 +1,22 @@
+// Generated by tools/gen_returncodes.py from kernel/src/returncode.rs.
+// Do not edit; change the table there and run `make returncodes`.
+
+#pragma once
+
+#define SUCCESS      0  // Success
+#define FAIL        -1  // Generic failure condition
+#define EBUSY       -2  // Underlying system is busy; retry
+#define EALREADY    -3  // The state requested is already set
+#define EOFF        -4  // The component is powered down
+#define ERESERVE    -5  // Reservation required before use
+#define EINVAL      -6  // An invalid parameter was passed
+#define ESIZE       -7  // Parameter passed was too large
+#define ECANCEL     -8  // Operation cancelled by a call
+#define ENOMEM      -9  // Memory required not available
+#define ENOSUPPORT -10  // Operation or command is unsupported
+#define ENODEVICE  -11  // Device does not exist
+#define EACCES     -12  // Caller is not permitted to perform the operation
+#define ERANGE     -13  // Value is outside the range the operation accepts
+#define ETIMEOUT   -14  // Operation did not complete in time
+#define EAUTH      -15  // Authentication or signature verification failed
+#define ENOACK     -16  // Transmission was not acknowledged by the receiver
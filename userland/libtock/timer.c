@@ -19,11 +19,11 @@ int timer_subscribe(subscribe_cb cb, void *userdata) {
 }
 
 int timer_oneshot(uint32_t interval_ms) {
-  return command(3, 1, (int)interval_ms);
+  return command2(3, 5, (int)interval_ms, 0);
 }
 
 int timer_start_repeating(uint32_t interval_ms) {
-  return command(3, 2, (int)interval_ms);
+  return command2(3, 5, (int)interval_ms, 1);
 }
 
 int timer_stop() {
@@ -25,23 +25,74 @@ int subscribe(uint32_t driver, uint32_t subscribe,
 
 
 int command(uint32_t driver, uint32_t command, int data) {
+  return command2(driver, command, data, 0);
+}
+
+int command2(uint32_t driver, uint32_t command, int data, int arg2) {
   register int ret __asm__ ("r0");
   asm volatile("svc 2\nbx lr" ::: "memory", "r0");
   return ret;
 }
 
+int command2_values(uint32_t driver, uint32_t command, int data, int arg2,
+                    int* value1) {
+  register uint32_t r0 __asm__ ("r0") = driver;
+  register uint32_t r1 __asm__ ("r1") = command;
+  register int r2 __asm__ ("r2") = data;
+  register int r3 __asm__ ("r3") = arg2;
+  asm volatile("svc 2" : "+r" (r0), "+r" (r1) : "r" (r2), "r" (r3) : "memory");
+  if (value1 != NULL) {
+    *value1 = (int)r1;
+  }
+  return (int)r0;
+}
+
 int allow(uint32_t driver, uint32_t allow, void* ptr, size_t size) {
   register int ret __asm__ ("r0");
   asm volatile("svc 3\nbx lr" ::: "memory", "r0");
   return ret;
 }
 
+int allow_readonly(uint32_t driver, uint32_t allow, const void* ptr, size_t size) {
+  register int ret __asm__ ("r0");
+  asm volatile("svc 5\nbx lr" ::: "memory", "r0");
+  return ret;
+}
+
 int memop(uint32_t op_type, int arg1) {
   register int ret __asm__ ("r0");
   asm volatile("svc 4\nbx lr" ::: "memory", "r0");
   return ret;
 }
 
+void* tock_app_memory_begins_at(void) {
+  return (void*) memop(4, 0);
+}
+
+void* tock_app_memory_ends_at(void) {
+  return (void*) memop(5, 0);
+}
+
+void* tock_app_flash_begins_at(void) {
+  return (void*) memop(6, 0);
+}
+
+void* tock_app_flash_ends_at(void) {
+  return (void*) memop(7, 0);
+}
+
+void* tock_app_grant_begins_at(void) {
+  return (void*) memop(8, 0);
+}
+
+void* tock_app_stack_heap_boundary(void) {
+  return (void*) memop(9, 0);
+}
+
+int tock_app_dropped_callbacks(void) {
+  return memop(10, 0);
+}
+
 bool driver_exists(uint32_t driver) {
   int ret = command(driver, 0, 0);
   return ret >= 0;
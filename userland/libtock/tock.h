@@ -5,6 +5,8 @@
 #include <stdint.h>
 #include <unistd.h>
 
+#include "returncode.h"
+
 #ifdef __cplusplus
 extern "C" {
 #endif
@@ -14,31 +16,49 @@ typedef void (subscribe_cb)(int, int, int,void*);
 void yield();
 void yield_for(bool*);
 int command(uint32_t driver, uint32_t command, int data);
+// Like `command`, with a second argument for drivers that take one
+int command2(uint32_t driver, uint32_t command, int data, int arg2);
+// Like `command2`, also storing the second value returned by the driver in
+// `value1` (if not NULL). The second value is 0 if the driver returns only one.
+int command2_values(uint32_t driver, uint32_t command, int data, int arg2,
+                    int* value1);
 int subscribe(uint32_t driver, uint32_t subscribe,
               subscribe_cb cb, void* userdata);
 int allow(uint32_t driver, uint32_t allow, void* ptr, size_t size);
+// Gives a driver read-only access to a buffer, which may be constant data in
+// the app's flash (e.g. a string literal)
+int allow_readonly(uint32_t driver, uint32_t allow, const void* ptr, size_t size);
 
 // op_type can be:
 // 0: brk, arg1 is pointer to new memory break
 // 1: sbrk, arg1 is increment to increase/decrease memory break
+// 2: memory usage in bytes, arg1 selects:
+//      0: heap, 1: stack, 2: grant (kernel memory held for this app),
+//      3: heap high-water mark, 4: stack high-water mark
+// 3: grant memory in bytes allocated by the container numbered arg1
+// 4: address of the start of the app's memory
+// 5: address of the end of the app's memory
+// 6: address of the start of the app's flash region
+// 7: address of the end of the app's flash region
+// 8: address of the start of the grant region (the app must not use memory
+//    past this)
+// 9: address of the stack/heap boundary: the stack grows down from here and
+//    the heap up
+// 10: number of callbacks dropped because the app's callback queue was full
 int memop(uint32_t op_type, int arg1);
 
+// Wrappers around memop for querying the app's memory layout.
+void* tock_app_memory_begins_at(void);
+void* tock_app_memory_ends_at(void);
+void* tock_app_flash_begins_at(void);
+void* tock_app_flash_ends_at(void);
+void* tock_app_grant_begins_at(void);
+void* tock_app_stack_heap_boundary(void);
+int tock_app_dropped_callbacks(void);
+
 // Checks to see if the given driver number exists on this platform.
 bool driver_exists(uint32_t driver);
 
-#define SUCCESS   0
-#define FAIL     -1
-#define EBUSY    -2
-#define EALREADY -3
-#define EOFF     -4
-#define ERESERVE -5
-#define EINVAL   -6
-#define ESIZE    -7
-#define ECANCEL  -8
-#define ENOMEM   -9
-#define ENOSUPPORT -10
-#define ENODEVICE  -11
-
 #ifdef __cplusplus
 }
 #endif
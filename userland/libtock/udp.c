@@ -0,0 +1,124 @@
+#include "udp.h"
+/*
+ * Userland library for sending and receiving UDP datagrams over 6LoWPAN.
+ */
+
+const int SYS_UDP = 155;
+
+const int UDP_BUF_RX = 0;
+const int UDP_BUF_TX = 1;
+const int UDP_BUF_DST = 2;
+const int UDP_BUF_SRC = 3;
+
+const int UDP_COM_BIND = 1;
+const int UDP_COM_SEND = 2;
+const int UDP_COM_ADDR = 3;
+
+const int UDP_EVT_TX = 0;
+const int UDP_EVT_RX = 1;
+
+// The kernel writes the sender of each received datagram here, so it has
+// to outlive the calls that share it.
+static udp_endpoint_t src_buf;
+
+typedef struct {
+  bool fired;
+  int result;
+} tx_data_t;
+
+static void cb_tx(int result,
+                  __attribute__ ((unused)) int unused1,
+                  __attribute__ ((unused)) int unused2,
+                  void* ud) {
+  tx_data_t* data = (tx_data_t*)ud;
+  data->result = result;
+  data->fired = true;
+}
+
+typedef struct {
+  bool fired;
+  int len;
+} rx_data_t;
+
+static void cb_rx(int len,
+                  __attribute__ ((unused)) int src_port,
+                  __attribute__ ((unused)) int unused2,
+                  void* ud) {
+  rx_data_t* data = (rx_data_t*)ud;
+  data->len = len;
+  data->fired = true;
+}
+
+int udp_bind(unsigned short port) {
+  return command(SYS_UDP, UDP_COM_BIND, port);
+}
+
+int udp_send_to(const unsigned char addr[16], unsigned short port,
+                const char* payload, int len) {
+  // The kernel reads the destination when the datagram is queued, so it
+  // can live on the stack.
+  udp_endpoint_t dst;
+  for (int i = 0; i < 16; i++) {
+    dst.addr[i] = addr[i];
+  }
+  dst.port[0] = port >> 8;
+  dst.port[1] = port & 0xff;
+
+  tx_data_t data;
+  data.fired = false;
+  int err = allow(SYS_UDP, UDP_BUF_DST, (void*)&dst, sizeof(dst));
+  if (err < 0) {
+    return err;
+  }
+  err = allow(SYS_UDP, UDP_BUF_TX, (void*)payload, len);
+  if (err < 0) {
+    return err;
+  }
+  err = subscribe(SYS_UDP, UDP_EVT_TX, cb_tx, &data);
+  if (err < 0) {
+    return err;
+  }
+  err = command(SYS_UDP, UDP_COM_SEND, len);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.fired);
+  return data.result;
+}
+
+int udp_receive_from(char* payload, int len, udp_endpoint_t* src) {
+  rx_data_t data;
+  data.fired = false;
+  int err = allow(SYS_UDP, UDP_BUF_RX, (void*)payload, len);
+  if (err < 0) {
+    return err;
+  }
+  err = allow(SYS_UDP, UDP_BUF_SRC, (void*)&src_buf, sizeof(src_buf));
+  if (err < 0) {
+    return err;
+  }
+  err = subscribe(SYS_UDP, UDP_EVT_RX, cb_rx, &data);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.fired);
+  if (src != NULL) {
+    *src = src_buf;
+  }
+  return data.len;
+}
+
+int udp_get_address(unsigned char addr[16]) {
+  int err = allow(SYS_UDP, UDP_BUF_SRC, (void*)&src_buf, sizeof(src_buf));
+  if (err < 0) {
+    return err;
+  }
+  err = command(SYS_UDP, UDP_COM_ADDR, 0);
+  if (err < 0) {
+    return err;
+  }
+  for (int i = 0; i < 16; i++) {
+    addr[i] = src_buf.addr[i];
+  }
+  return SUCCESS;
+}
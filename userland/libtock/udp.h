@@ -0,0 +1,43 @@
+#ifndef _UDP_H
+#define _UDP_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An IPv6 address and UDP port, both in network byte order. This is the
+ * layout the kernel reads destinations from and writes sources to. */
+typedef struct {
+  unsigned char addr[16];
+  unsigned char port[2];
+} udp_endpoint_t;
+
+/* System calls for UDP sockets over 6LoWPAN. */
+
+// Receive datagrams sent to `port` and send datagrams from it. Each port
+// can be bound by one app at a time (EBUSY otherwise). Port 0 unbinds.
+int udp_bind(unsigned short port);
+
+// Sends `len` bytes of `payload` to `addr` (16 bytes) and `port`. Returns
+// once the datagram is sent. Destinations that are not link-local or
+// multicast are reached through the board's gateway, from this node's
+// address in the gateway's prefix. Without a gateway, sending to them fails
+// with EINVAL.
+int udp_send_to(const unsigned char addr[16], unsigned short port,
+                const char* payload, int len);
+
+// Waits for a datagram to the bound port and copies up to `len` bytes of
+// its payload into `payload`. Returns the payload length, which may be
+// larger than `len`. If `src` is not NULL, stores the sender in it.
+int udp_receive_from(char* payload, int len, udp_endpoint_t* src);
+
+// Stores this node's link-local address (16 bytes) in `addr`.
+int udp_get_address(unsigned char addr[16]);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _UDP_H
@@ -37,6 +37,18 @@ struct LoadInfo {
     checksum: u32,
 }
 
+/// Fields following `LoadInfo` in version 2 headers.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LoadInfoV2 {
+    flags: u32,
+}
+
+/// The app may execute code from RAM (otherwise RAM is execute-never).
+const FLAG_RAM_EXECUTABLE: u32 = 0x1;
+const FLAG_STACK_GUARD_SHIFT: u32 = 8;
+const FLAG_CALLBACK_QUEUE_SHIFT: u32 = 16;
+
 impl fmt::Display for LoadInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "
@@ -90,6 +102,15 @@ fn main() {
     opts.optopt("o", "", "set output file name", "OUTFILE");
     opts.optopt("n", "", "set package name", "PACKAGE_NAME");
     opts.optflag("v", "verbose", "be verbose");
+    opts.optflag("", "ram-exec", "allow the app to execute code from RAM");
+    opts.optopt("",
+                "callback-queue",
+                "number of callbacks the kernel may queue for the app (1-255)",
+                "DEPTH");
+    opts.optopt("",
+                "stack-guard",
+                "size of the guard region below the stack (a power of two, 32-4096)",
+                "BYTES");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -98,6 +119,26 @@ fn main() {
     let output = matches.opt_str("o");
     let package_name = matches.opt_str("n");
     let verbose = matches.opt_present("v");
+    let mut flags = 0;
+    if matches.opt_present("ram-exec") {
+        flags |= FLAG_RAM_EXECUTABLE;
+    }
+    if let Some(depth) = matches.opt_str("callback-queue") {
+        match depth.parse::<u32>() {
+            Ok(depth) if depth > 0 && depth <= 0xff => {
+                flags |= depth << FLAG_CALLBACK_QUEUE_SHIFT;
+            }
+            _ => panic!("callback queue depth must be between 1 and 255"),
+        }
+    }
+    if let Some(size) = matches.opt_str("stack-guard") {
+        match size.parse::<u32>() {
+            Ok(size) if size.is_power_of_two() && size >= 32 && size <= 4096 => {
+                flags |= size.trailing_zeros() << FLAG_STACK_GUARD_SHIFT;
+            }
+            _ => panic!("stack guard size must be a power of two between 32 and 4096"),
+        }
+    }
     let input = if !matches.free.is_empty() {
         matches.free[0].clone()
     } else {
@@ -115,11 +156,11 @@ fn main() {
     match output {
             None => {
                 let mut out = io::stdout();
-                do_work(&file, &mut out, package_name, verbose)
+                do_work(&file, &mut out, package_name, flags, verbose)
             }
             Some(name) => {
                 match File::create(Path::new(&name)) {
-                    Ok(mut f) => do_work(&file, &mut f, package_name, verbose),
+                    Ok(mut f) => do_work(&file, &mut f, package_name, flags, verbose),
                     Err(e) => panic!("Error: {:?}", e),
                 }
             }
@@ -167,6 +208,7 @@ unsafe fn as_byte_slice<'a, T: Copy>(input: &'a T) -> &'a [u8] {
 fn do_work(input: &elf::File,
            output: &mut Write,
            package_name: Option<String>,
+           flags: u32,
            verbose: bool)
            -> io::Result<()> {
     let package_name = package_name.unwrap_or(String::new());
@@ -187,7 +229,8 @@ fn do_work(input: &elf::File,
     let app_heap_len = get_section(input, ".app_heap").data.len() as u32;
     let kernel_heap_len = get_section(input, ".kernel_heap").data.len() as u32;
 
-    let mut total_size = (mem::size_of::<LoadInfo>() + rel_data.len() + text.data.len() +
+    let header_size = mem::size_of::<LoadInfo>() + mem::size_of::<LoadInfoV2>();
+    let mut total_size = (header_size + rel_data.len() + text.data.len() +
                           got.data.len() +
                           data.data.len() + package_name.len()) as u32;
 
@@ -199,7 +242,7 @@ fn do_work(input: &elf::File,
     };
     total_size = total_size + pad;
 
-    let rel_data_offset = mem::size_of::<LoadInfo>() as u32;
+    let rel_data_offset = header_size as u32;
     let text_offset = rel_data_offset + (rel_data_size as u32);
     let text_size = text.shdr.size as u32;
     let entry_offset = (input.ehdr.entry ^ 0x80000000) as u32 + text_offset;
@@ -210,7 +253,7 @@ fn do_work(input: &elf::File,
     let package_name_offset = data_offset + data_size;
     let package_name_size = package_name.len() as u32;
 
-    let load_info_version = 1;
+    let load_info_version = 2;
 
     let load_info = LoadInfo {
         version: load_info_version,
@@ -237,14 +280,17 @@ fn do_work(input: &elf::File,
                   data_offset ^ data_size ^ bss.shdr.addr as u32 ^
                   bss.shdr.size as u32 ^
                   stack_len ^ app_heap_len ^
-                  kernel_heap_len ^ package_name_offset ^ package_name_size,
+                  kernel_heap_len ^ package_name_offset ^ package_name_size ^ flags,
     };
+    let load_info_v2 = LoadInfoV2 { flags: flags };
 
     if verbose {
         print!("{}", load_info);
+        println!("              flags: {:>8} {:>#10X}", flags, flags);
     }
 
     try!(output.write_all(unsafe { as_byte_slice(&load_info) }));
+    try!(output.write_all(unsafe { as_byte_slice(&load_info_v2) }));
     try!(output.write_all(rel_data.as_ref()));
     try!(output.write_all(text.data.as_ref()));
     try!(output.write_all(got.data.as_ref()));